@@ -0,0 +1,55 @@
+#!/bin/bash
+# Run every example on the bytecode VM and on the tree-walking interpreter
+# and report any difference in output (stdout and stderr)
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+PROJECT_DIR="$(dirname "$SCRIPT_DIR")"
+BLUR="$PROJECT_DIR/target/release/blur"
+EXAMPLES_DIR="$PROJECT_DIR/examples"
+
+# Colors for output
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+YELLOW='\033[1;33m'
+NC='\033[0m' # No Color
+
+# Build if needed
+if [ ! -f "$BLUR" ]; then
+    echo -e "${YELLOW}Building blur...${NC}"
+    cd "$PROJECT_DIR" && cargo build --release
+fi
+
+# Examples may write files; keep them out of the project directory
+WORK_DIR=$(mktemp -d)
+trap 'rm -rf "$WORK_DIR"' EXIT
+cd "$WORK_DIR"
+
+passed=0
+failed=0
+total=0
+
+for example in "$EXAMPLES_DIR"/*.blur; do
+    filename=$(basename "$example")
+    total=$((total + 1))
+
+    vm_out=$(timeout 10s "$BLUR" "$example" 2>&1; echo "exit: $?")
+    tree_out=$(timeout 10s "$BLUR" --tree-walk "$example" 2>&1; echo "exit: $?")
+
+    if [ "$vm_out" == "$tree_out" ]; then
+        echo -e "${GREEN}[SAME]${NC} $filename"
+        passed=$((passed + 1))
+    else
+        echo -e "${RED}[DIFF]${NC} $filename"
+        diff <(echo "$tree_out") <(echo "$vm_out") | sed 's/^/    /'
+        failed=$((failed + 1))
+    fi
+done
+
+echo "========================================"
+echo "Results: $passed/$total identical"
+if [ $failed -gt 0 ]; then
+    echo -e "${RED}$failed example(s) differ between engines${NC}"
+    exit 1
+else
+    echo -e "${GREEN}Both engines agree!${NC}"
+fi
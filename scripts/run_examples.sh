@@ -1,5 +1,6 @@
 #!/bin/bash
-# Run all Blur examples and report results
+# Run all Blur examples and report results; an example passes if it runs
+# without errors and prints its expected output (see `blur test --golden`)
 
 set -e
 
@@ -39,8 +40,14 @@ for example in "$EXAMPLES_DIR"/*.blur; do
 
         if timeout 10s "$BLUR" "$example" 2>&1; then
             echo ""
-            echo -e "${GREEN}[PASS]${NC} $filename"
-            passed=$((passed + 1))
+            if golden=$(timeout 10s "$BLUR" test --golden "$example"); then
+                echo -e "${GREEN}[PASS]${NC} $filename"
+                passed=$((passed + 1))
+            else
+                echo "$golden" | sed -n '/^---- /,/^golden result/p' | sed '$d'
+                echo -e "${RED}[FAIL]${NC} $filename (output differs from the expected output)"
+                failed=$((failed + 1))
+            fi
         else
             exit_code=$?
             echo ""
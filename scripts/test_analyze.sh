@@ -0,0 +1,101 @@
+#!/bin/bash
+# Check that `blur analyze` predicts for loop iteration counts exactly:
+# every prediction is compared with the loop events of a traced run
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+PROJECT_DIR="$(dirname "$SCRIPT_DIR")"
+BLUR="$PROJECT_DIR/target/release/blur"
+TMP_DIR="$(mktemp -d)"
+trap 'rm -rf "$TMP_DIR"' EXIT
+
+# Colors for output
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+YELLOW='\033[1;33m'
+NC='\033[0m' # No Color
+
+# Build if needed
+if [ ! -f "$BLUR" ]; then
+    echo -e "${YELLOW}Building blur...${NC}"
+    cd "$PROJECT_DIR" && cargo build --release
+fi
+
+passed=0
+failed=0
+
+# check <name> <expected> <actual>
+check() {
+    if [ "$2" == "$3" ]; then
+        echo -e "${GREEN}[PASS]${NC} $1"
+        passed=$((passed + 1))
+    else
+        echo -e "${RED}[FAIL]${NC} $1"
+        diff <(echo "$2") <(echo "$3") | sed 's/^/    /'
+        failed=$((failed + 1))
+    fi
+}
+
+# predicted <blur> <loop>: the count blur analyze gives for one loop
+predicted() {
+    printf 'void blur() {\n    %s {}\n}\n' "$2" > "$TMP_DIR/loop.blur"
+    "$BLUR" analyze --blur "$1" --max-loop 0 "$TMP_DIR/loop.blur" | grep -o 'runs [0-9]* times' | grep -o '[0-9]*'
+}
+
+# actual <blur> <loop>: how many iterations a traced run does
+actual() {
+    "$BLUR" --blur "$1" --max-loop 0 --trace "$TMP_DIR/trace.jsonl" -e "$2 {}"
+    grep -c '"event":"loop"' "$TMP_DIR/trace.jsonl"
+}
+
+echo "Predictions against traced runs..."
+for blur in 1.0 0.9 0.5 0.0; do
+    for loop in \
+        'for (int i = 0; i < 5; i++)' \
+        'for (int i = 0; i <= 7; ++i)' \
+        'for (int i = 10; i > 0; i--)' \
+        'for (int i = -3; 4 > i; i += 2)' \
+        'for (float x = 0.0; x < 2.0; x += 0.25)'; do
+        check "blur $blur: $loop" "$(actual "$blur" "$loop")" "$(predicted "$blur" "$loop")"
+    done
+done
+
+echo "Reports..."
+read -r -d '' PROGRAM << 'EOF'
+void blur() {
+    for (int i = 0; i < 5; i++) {
+        print(i);
+    }
+    for (int j = 0; j < 8; j++) {}
+    for (int k = 0; k < 3; k++) {
+        k = 0;
+    }
+    sharp for (int m = 0; m < 100; m++) {}
+}
+EOF
+echo "$PROGRAM" > "$TMP_DIR/program.blur"
+output=$("$BLUR" analyze --blur 1.0 "$TMP_DIR/program.blur")
+check "stuck lengths per value" "    i = 0 for 1, 1 for 2, 2 for 7, 3 for 20, 4 for 52" "$(sed -n 2p <<< "$output")"
+check "loops over the limit are reported" "1" "$(grep -c 'j < 8.*over the max-loop limit of 1000' <<< "$output")"
+check "loops that change their counter and sharp loops are skipped" "2" "$(grep -c 'runs' <<< "$output")"
+"$BLUR" analyze --blur 1.0 "$TMP_DIR/program.blur" > /dev/null
+check "exit 1 when a loop would hit the limit" "1" "$?"
+"$BLUR" analyze --blur 1.0 --max-loop 1674 "$TMP_DIR/program.blur" > /dev/null
+check "exit 0 under a higher limit" "0" "$?"
+printf '#blur 1.0\n#max-loop 2000\n%s\n' "$PROGRAM" > "$TMP_DIR/program.blur"
+check "directives set the blur factor and limit" "0" \
+    "$("$BLUR" analyze "$TMP_DIR/program.blur" | grep -c 'over'; true)"
+check "example loop" "examples/loop_chaos.blur:7:5: for (int i = 0; i < 5; i++) runs 23 times at blur 0.9" \
+    "$(cd "$PROJECT_DIR" && "$BLUR" analyze examples/loop_chaos.blur | head -1)"
+
+# Enough output to fill the pipe after head has gone
+{ echo 'void blur() {'; for i in $(seq 2000); do echo '    for (int i = 0; i < 5; i++) {}'; done; echo '}'; } > "$TMP_DIR/many.blur"
+"$BLUR" analyze "$TMP_DIR/many.blur" 2> "$TMP_DIR/stderr" | head -1 > /dev/null
+check "a closed pipe ends the output quietly" "0 " "${PIPESTATUS[0]} $(cat "$TMP_DIR/stderr")"
+
+echo "========================================"
+echo "Results: $passed/$((passed + failed)) passed"
+if [ $failed -gt 0 ]; then
+    exit 1
+else
+    echo -e "${GREEN}Loop analysis works!${NC}"
+fi
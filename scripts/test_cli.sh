@@ -0,0 +1,179 @@
+#!/bin/bash
+# Check the command line: subcommands, flag validation, help and `--`
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+PROJECT_DIR="$(dirname "$SCRIPT_DIR")"
+BLUR="$PROJECT_DIR/target/release/blur"
+TMP_DIR="$(mktemp -d)"
+trap 'rm -rf "$TMP_DIR"' EXIT
+
+# Colors for output
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+YELLOW='\033[1;33m'
+NC='\033[0m' # No Color
+
+# Build if needed
+if [ ! -f "$BLUR" ]; then
+    echo -e "${YELLOW}Building blur...${NC}"
+    cd "$PROJECT_DIR" && cargo build --release
+fi
+
+passed=0
+failed=0
+
+# check <name> <expected> <actual>
+check() {
+    if [ "$2" == "$3" ]; then
+        echo -e "${GREEN}[PASS]${NC} $1"
+        passed=$((passed + 1))
+    else
+        echo -e "${RED}[FAIL]${NC} $1"
+        diff <(echo "$2") <(echo "$3") | sed 's/^/    /'
+        failed=$((failed + 1))
+    fi
+}
+
+# contains <name> <needle> <haystack>
+contains() {
+    if grep -qF -- "$2" <<< "$3"; then
+        echo -e "${GREEN}[PASS]${NC} $1"
+        passed=$((passed + 1))
+    else
+        echo -e "${RED}[FAIL]${NC} $1 (no '$2')"
+        failed=$((failed + 1))
+    fi
+}
+
+echo "Running programs..."
+check "a file" "Initial money: 5" "$("$BLUR" examples/hello.blur 2> /dev/null | head -1)"
+check "run subcommand" "Initial money: 5" "$("$BLUR" run examples/hello.blur 2> /dev/null | head -1)"
+check "-e" "15" "$("$BLUR" --blur 1.0 -e 'int x = 10; x = 20; print(x);')"
+check "eval subcommand" "15" "$("$BLUR" eval --blur 1.0 'int x = 10; x = 20; print(x);')"
+HISTORY='float x = 100.0; sharp for (int i = 0; i < 1000; i++) { x = i; } print(x);'
+check "folding old history keeps the average" "$("$BLUR" --raw-history 0 -e "$HISTORY")" "$("$BLUR" -e "$HISTORY")"
+for engine in "" --tree-walk; do
+    for program in 'float x = 0.1; sharp for (int i = 0; i < 20000; i++) { x = i * 0.37; } print(x);' \
+        'int x = 3; sharp for (int i = 0; i < 20000; i++) { x = 3; } print(x);' \
+        'int x = 0; sharp for (int i = 0; i < 20000; i++) { x = i % 7; } print(x);' \
+        'bool b = true; sharp for (int i = 0; i < 20000; i++) { b = i % 3 == 0; } print(b);' \
+        'string s = "ab"; sharp for (int i = 0; i < 20000; i++) { s = "cd"; } print(s);'; do
+        check "folding is exact: $program $engine" "$("$BLUR" $engine --raw-history 0 -e "$program")" "$("$BLUR" $engine -e "$program")"
+    done
+done
+check "nothing is folded at blur 1.0" "499.1008991008991" "$("$BLUR" --blur 1.0 -e "$HISTORY")"
+check "stdin" "5" "$(echo 'int x = 5; print(x);' | "$BLUR" -)"
+check "arguments after -- are not files" "3" "$("$BLUR" -e 'print(3);' -- a --blur b)"
+contains "-v" "blur 0." "$("$BLUR" -v)"
+
+echo "Flag validation..."
+output=$("$BLUR" --blurr 1.0 examples/hello.blur 2>&1)
+status=$?
+contains "misspelled flags are errors" "unexpected argument '--blurr'" "$output"
+contains "misspelled flags get a suggestion" "a similar argument exists: '--blur'" "$output"
+check "usage errors exit 2" "2" "$status"
+contains "flags a subcommand doesn't take" "unexpected argument '--tree-walk'" "$("$BLUR" fmt --tree-walk examples/hello.blur 2>&1)"
+contains "values are checked" "invalid value 'lots'" "$("$BLUR" --fuel lots examples/hello.blur 2>&1)"
+contains "while loops are capped" "while loop hit 50 iteration limit" "$("$BLUR" --max-iterations 50 -e 'while (true) {}' 2>&1)"
+contains "the same on the tree-walker" "while loop hit 50 iteration limit" "$("$BLUR" --tree-walk --max-iterations 50 -e 'while (true) {}' 2>&1)"
+contains "sharp for loops are capped" "sharp for loop hit 50 iteration limit" "$("$BLUR" --max-iterations 50 -e 'sharp for (int i = 0; true; i++) {}' 2>&1)"
+contains "the same on the tree-walker" "sharp for loop hit 50 iteration limit" "$("$BLUR" --tree-walk --max-iterations 50 -e 'sharp for (int i = 0; true; i++) {}' 2>&1)"
+check "the limit is per loop" "done" "$("$BLUR" --max-iterations 50 -e 'sharp for (int i = 0; i < 40; i++) { sharp for (int j = 0; j < 40; j++) {} } print("done");' 2>&1)"
+printf 'void bump() {\n    x = 5;\n}\nint blur() {\n    int x = 1;\n    if (true) {\n        x = 2;\n    }\n    bump();\n    return 0;\n}\n' > "$TMP_DIR/scope.blur"
+for engine in "" --tree-walk; do
+    "$BLUR" $engine --trace "$TMP_DIR/trace.jsonl" "$TMP_DIR/scope.blur"
+    check "trace pushes give the call depth and scope ${engine}" '1 0
+1 1
+2 null' "$(grep -o '"depth":[0-9]*,"scope":[0-9a-z]*' "$TMP_DIR/trace.jsonl" | sed 's/"depth":\([0-9]*\),"scope":/\1 /')"
+done
+for engine in "" --tree-walk; do
+    check "fractional array sizes are errors ${engine}" "Runtime error: Invalid array size: 2.5" "$("$BLUR" $engine -e 'int a[2.5];' 2>&1)"
+    check "arrays too large to allocate are errors ${engine}" "Runtime error: Array too large: [1000000][1000000][1000000]" "$("$BLUR" $engine -e 'int a[1000000][1000000][1000000];' 2>&1)"
+    "$BLUR" $engine -e 'int a[1000000000000];' 2> /dev/null
+    check "and exit 70 ${engine}" "70" "$?"
+    contains "--max-array ${engine}" "array would have more than 100 elements" "$("$BLUR" $engine --max-array 100 -e 'int a[10][11];' 2>&1)"
+    contains "--max-array covers copies ${engine}" "array would have more than 100 elements" "$("$BLUR" $engine --max-array 100 -e 'int b[] = {1, 2}; int a[200] = b;' 2>&1)"
+done
+contains "-e conflicts with a file" "cannot be used with" "$("$BLUR" -e 'print(1);' examples/hello.blur 2>&1)"
+contains "missing files" "required arguments were not provided" "$("$BLUR" lint 2>&1)"
+
+echo "Help..."
+output=$("$BLUR" --help)
+for command in run eval repl check test fmt lint analyze doctest debug lsp dap; do
+    contains "--help lists $command" "  $command " "$output"
+done
+contains "subcommand help" "Usage: blur test [OPTIONS] [PATHS]..." "$("$BLUR" test --help)"
+contains "help subcommand" "Usage: blur fmt [OPTIONS] [FILES]..." "$("$BLUR" help fmt)"
+for command in "" run test; do
+    check "only limits under Limits in ${command:-blur} --help" "6" "$("$BLUR" $command --help | sed -n '/^Limits:/,/^$/p' | grep -c '^  ')"
+done
+
+echo "Arguments and exit codes..."
+cat > "$TMP_DIR/args.blur" << 'EOF'
+int blur(string args[]) {
+    print(len(args), args);
+    return len(args);
+}
+EOF
+check "blur(string args[]) gets the arguments after --" "2 [one, --two]" "$("$BLUR" "$TMP_DIR/args.blur" -- one --two)"
+check "no arguments" "0 []" "$("$BLUR" "$TMP_DIR/args.blur")"
+"$BLUR" "$TMP_DIR/args.blur" -- a b c > /dev/null
+check "blur() returns the exit code" "3" "$?"
+"$BLUR" --tree-walk "$TMP_DIR/args.blur" -- a b c > /dev/null
+check "the same on the tree-walker" "3" "$?"
+for engine in "" --tree-walk; do
+    printf 'int blur() {\n    return 1 + 1;\n}\n' > "$TMP_DIR/sum.blur"
+    "$BLUR" $engine "$TMP_DIR/sum.blur"
+    check "computed exit codes $engine" "2" "$?"
+    printf 'int fib(int n) {\n    if (n < 2) {\n        return n;\n    }\n    return fib(n - 1) + fib(n - 2);\n}\nint blur() {\n    return fib(4);\n}\n' > "$TMP_DIR/fib.blur"
+    "$BLUR" $engine "$TMP_DIR/fib.blur"
+    check "exit codes from a call $engine" "3" "$?"
+    printf 'int blur() {\n    return 2.5;\n}\n' > "$TMP_DIR/float.blur"
+    "$BLUR" $engine "$TMP_DIR/float.blur"
+    check "exit codes are rounded up like ints $engine" "3" "$?"
+    printf 'int blur() {\n    return 256;\n}\n' > "$TMP_DIR/big.blur"
+    check "exit codes over 255 are errors $engine" "Runtime error: blur() returned 256, but exit codes go from 0 to 255" "$("$BLUR" $engine "$TMP_DIR/big.blur" 2>&1)"
+    "$BLUR" $engine "$TMP_DIR/big.blur" 2> /dev/null
+    check "and exit 70 $engine" "70" "$?"
+    printf 'int blur() {\n    return 0 - 1;\n}\n' > "$TMP_DIR/negative.blur"
+    "$BLUR" $engine "$TMP_DIR/negative.blur" 2> /dev/null
+    check "negative exit codes are errors $engine" "70" "$?"
+done
+check "-e code sees args" "[x, y]" "$("$BLUR" -e 'print(args);' -- x y)"
+check "stdin programs get arguments" "[z]" "$(printf 'int blur(string args[]) { print(args); return 0; }' | "$BLUR" - -- z)"
+printf 'int blur(int n) {\n    return n;\n}\n' > "$TMP_DIR/params.blur"
+contains "other blur() parameters are rejected" "blur() takes no parameters, or the command-line arguments as string args[]" "$("$BLUR" "$TMP_DIR/params.blur" 2>&1)"
+"$BLUR" -e 'int x = ;' 2> /dev/null
+check "parse errors exit 65" "65" "$?"
+"$BLUR" -e 'int a[2]; a[5] = 1;' 2> /dev/null
+check "runtime errors exit 70" "70" "$?"
+"$BLUR" "$TMP_DIR/missing.blur" 2> /dev/null
+check "missing files exit 66" "66" "$?"
+
+echo "Check..."
+"$BLUR" check examples/*.blur
+check "examples check clean" "0" "$?"
+printf 'void blur() {\n    print(y);\n    f(1);\n}\n' > "$TMP_DIR/bad.blur"
+check "undefined names" "$TMP_DIR/bad.blur:2:11: error: Undefined variable: y
+$TMP_DIR/bad.blur:3:5: error: Undefined function: f" "$("$BLUR" check "$TMP_DIR/bad.blur" 2>&1)"
+for engine in "" --tree-walk; do
+    printf 'void blur() {\n    if (false) {\n        print(y);\n    }\n    print("ran");\n}\n' > "$TMP_DIR/undefined.blur"
+    printf 'void f() {\n    print(x);\n}\nvoid blur() {\n    int x = 3;\n    f();\n}\n' > "$TMP_DIR/caller.blur"
+    check "undefined variables are found before running ${engine}" "Error: 3:15: Undefined variable: y" "$("$BLUR" $engine "$TMP_DIR/undefined.blur" 2>&1)"
+    "$BLUR" $engine "$TMP_DIR/undefined.blur" 2> /dev/null
+    check "and exit 65 ${engine}" "65" "$?"
+    check "in -e code too ${engine}" "Error: Undefined variable: a" "$("$BLUR" $engine -e 'print(a[0]);' 2>&1)"
+    check "names a caller declares still run ${engine}" "3" "$("$BLUR" $engine "$TMP_DIR/caller.blur" 2>&1)"
+done
+"$BLUR" check "$TMP_DIR/bad.blur" 2> /dev/null
+check "check exits 1 on errors" "1" "$?"
+printf 'void blur() {\n    int x = ;\n}\n' > "$TMP_DIR/parse.blur"
+contains "parse errors" "$TMP_DIR/parse.blur:2:13: Parse error" "$("$BLUR" check "$TMP_DIR/parse.blur" 2>&1)"
+
+echo "========================================"
+echo "Results: $passed/$((passed + failed)) passed"
+if [ $failed -gt 0 ]; then
+    exit 1
+else
+    echo -e "${GREEN}The command line works!${NC}"
+fi
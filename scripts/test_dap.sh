@@ -0,0 +1,147 @@
+#!/bin/bash
+# Drive `blur dap` with framed Debug Adapter Protocol requests and check the
+# responses and events it sends back
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+PROJECT_DIR="$(dirname "$SCRIPT_DIR")"
+BLUR="$PROJECT_DIR/target/release/blur"
+
+# Colors for output
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+YELLOW='\033[1;33m'
+NC='\033[0m' # No Color
+
+# Build if needed
+if [ ! -f "$BLUR" ]; then
+    echo -e "${YELLOW}Building blur...${NC}"
+    cd "$PROJECT_DIR" && cargo build --release
+fi
+
+WORK_DIR=$(mktemp -d)
+trap 'rm -rf "$WORK_DIR"' EXIT
+PROGRAM="$WORK_DIR/debug.blur"
+cat > "$PROGRAM" << 'EOF'
+int twice(int n) {
+    n = n * 2;
+    return n;
+}
+
+void blur() {
+    int x = 5;
+    x = twice(x);
+    x = 20;
+    int a[3] = {1, 2, 3};
+    a[1]++;
+    print(x);
+    int z = x / 0;
+}
+EOF
+
+passed=0
+failed=0
+REQUESTS=""
+SEQ=0
+
+# request <command> [arguments]: queue a framed request
+request() {
+    SEQ=$((SEQ + 1))
+    local body="{\"seq\":$SEQ,\"type\":\"request\",\"command\":\"$1\",\"arguments\":${2:-{\}}}"
+    REQUESTS+=$(printf 'Content-Length: %d\r\n\r\n%s' "${#body}" "$body")
+}
+
+# start [launch arguments]: begin a session that launches the program
+start() {
+    REQUESTS=""
+    SEQ=0
+    request initialize '{"adapterID":"blur"}'
+    request launch "{\"program\":\"$PROGRAM\"${1:+,$1}}"
+}
+
+# check <name> <expected fragment>...: send the queued requests
+check() {
+    local name="$1"
+    shift
+    local output
+    output=$(printf '%s' "$REQUESTS" | timeout 10s "$BLUR" dap 2>&1)
+    for expected in "$@"; do
+        if ! grep -qF -- "$expected" <<< "$output"; then
+            echo -e "${RED}[FAIL]${NC} $name: missing '$expected'"
+            echo "$output" | tr -d '\r' | sed 's/^/    /'
+            failed=$((failed + 1))
+            return
+        fi
+    done
+    echo -e "${GREEN}[PASS]${NC} $name"
+    passed=$((passed + 1))
+}
+
+start
+request configurationDone
+request disconnect
+check "runs to the end without breakpoints" \
+    '"event":"initialized"' '"output":"12\n"' '"reason":"exception"' '"text":"Runtime error at line 13: Division by zero"'
+
+start '"stopOnEntry":true'
+request configurationDone
+request threads
+request stackTrace '{"threadId":1}'
+request disconnect
+check "stops on entry" \
+    '"reason":"entry"' '"threads":[{"id":1,"name":"blur"}]' '"id":0,"line":7,"name":"blur"'
+
+start
+request setBreakpoints "{\"source\":{\"path\":\"$PROGRAM\"},\"breakpoints\":[{\"line\":2}]}"
+request configurationDone
+request stackTrace '{"threadId":1}'
+request disconnect
+check "line breakpoint and call stack" \
+    '"breakpoints":[{"id":1,"line":2,"verified":true}]' '"hitBreakpointIds":[1],"reason":"breakpoint"' \
+    '"id":0,"line":2,"name":"twice"' '"id":1,"line":8,"name":"blur"'
+
+start
+request setFunctionBreakpoints '{"breakpoints":[{"name":"twice"}]}'
+request configurationDone
+request scopes '{"frameId":1}'
+request variables '{"variablesReference":1}'
+request disconnect
+check "function breakpoint and caller's variables" \
+    '"reason":"breakpoint"' '"name":"Locals","variablesReference":1' '"name":"x","type":"int, 1 entry","value":"5"'
+
+start
+request setBreakpoints "{\"source\":{\"path\":\"$PROGRAM\"},\"breakpoints\":[{\"line\":11}]}"
+request configurationDone
+request scopes '{"frameId":0}'
+request variables '{"variablesReference":1}'
+request variables '{"variablesReference":2}'
+request variables '{"variablesReference":3}'
+request disconnect
+check "a variable's children are its raw history" \
+    '"name":"x","type":"int, 3 entries","value":"12","variablesReference":2' \
+    '{"name":"1","value":"5","variablesReference":0},{"name":"2","value":"8","variablesReference":0},{"name":"3","value":"20","variablesReference":0}' \
+    '"name":"[1]","type":"int, 1 entry","value":"2"'
+
+start '"stopOnEntry":true'
+request configurationDone
+request next '{"threadId":1}'
+request stepIn '{"threadId":1}'
+request stackTrace '{"threadId":1}'
+request continue '{"threadId":1}'
+request continue '{"threadId":1}'
+request disconnect
+check "next, stepIn and continue to the end" \
+    '"command":"next"' '"reason":"step"' '"id":0,"line":2,"name":"twice"' '"exitCode":1' '"event":"terminated"'
+
+start
+request variables '{"variablesReference":1}'
+request disconnect
+check "requests that need a stopped program fail" \
+    '"message":"The program is not running.","request_seq":3'
+
+echo "========================================"
+echo "Results: $passed/$((passed + failed)) passed"
+if [ $failed -gt 0 ]; then
+    exit 1
+else
+    echo -e "${GREEN}DAP server works!${NC}"
+fi
@@ -0,0 +1,85 @@
+#!/bin/bash
+# Drive `blur debug` with scripted commands and check what it prints
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+PROJECT_DIR="$(dirname "$SCRIPT_DIR")"
+BLUR="$PROJECT_DIR/target/release/blur"
+
+# Colors for output
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+YELLOW='\033[1;33m'
+NC='\033[0m' # No Color
+
+# Build if needed
+if [ ! -f "$BLUR" ]; then
+    echo -e "${YELLOW}Building blur...${NC}"
+    cd "$PROJECT_DIR" && cargo build --release
+fi
+
+WORK_DIR=$(mktemp -d)
+trap 'rm -rf "$WORK_DIR"' EXIT
+PROGRAM="$WORK_DIR/debug.blur"
+cat > "$PROGRAM" << 'EOF'
+int twice(int n) {
+    n = n * 2;
+    return n;
+}
+
+void blur() {
+    int x = 5;
+    x = twice(x);
+    x = 20;
+    int a[3] = {1, 2, 3};
+    a[1]++;
+    print(x);
+    int z = x / 0;
+}
+EOF
+
+passed=0
+failed=0
+
+# check <name> <commands> <expected line>...
+check() {
+    local name="$1"
+    local commands="$2"
+    shift 2
+    local output
+    output=$(printf "$commands" | timeout 10s "$BLUR" debug "$PROGRAM" 2>&1)
+    for expected in "$@"; do
+        if ! grep -qF -- "$expected" <<< "$output"; then
+            echo -e "${RED}[FAIL]${NC} $name: missing '$expected'"
+            echo "$output" | sed 's/^/    /'
+            failed=$((failed + 1))
+            return
+        fi
+    done
+    echo -e "${GREEN}[PASS]${NC} $name"
+    passed=$((passed + 1))
+}
+
+check "stops at the first statement" 'q\n' \
+    "blur() at line 7"
+check "function breakpoint" 'break twice\ncontinue\nbacktrace\nq\n' \
+    "Breakpoint 1 at twice()" "Breakpoint 1, twice() at line 2" ">#0  twice() at line 2" " #1  blur() at line 8"
+check "line breakpoint" 'break 11\ncontinue\nprint a\nprint a[1]\nq\n' \
+    "Breakpoint 1, blur() at line 11" "a = [1, 2, 3]" "a[1] = 2  (int, 1 entry)"
+check "next steps over calls" 'next\nnext\nnext\nprint x\nq\n' \
+    "blur() at line 8" "blur() at line 9" "blur() at line 10" "x = 12  (int, 3 entries)"
+check "step enters calls" 'step\nstep\nprint n\nq\n' \
+    "twice() at line 2" "n = 5  (int, 1 entry)"
+check "history shows the average after each entry" 'break 12\ncontinue\nhistory x\nq\n' \
+    "x: int, 3 entries" "1  5                    5" "2  8                    7" "3  20                   12"
+check "frames select variables" 'break twice\ncontinue\nprint x\nframe 1\nprint x\nq\n' \
+    "No variable named x in this frame" "x = 5  (int, 1 entry)"
+check "runtime error leaves variables inspectable" 'continue\nprint x\ncontinue\nq\n' \
+    "Runtime error at line 13: Division by zero" "x = 12" "The program is not running."
+
+echo "========================================"
+echo "Results: $passed/$((passed + failed)) passed"
+if [ $failed -gt 0 ]; then
+    exit 1
+else
+    echo -e "${GREEN}Debugger works!${NC}"
+fi
@@ -0,0 +1,105 @@
+#!/bin/bash
+# Check `blur doctest`: the snippets of README.md and ESOLANGS.wiki agree
+# with the interpreter, and annotations that don't are reported
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+PROJECT_DIR="$(dirname "$SCRIPT_DIR")"
+BLUR="$PROJECT_DIR/target/release/blur"
+TMP_DIR="$(mktemp -d)"
+trap 'rm -rf "$TMP_DIR"' EXIT
+
+# Colors for output
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+YELLOW='\033[1;33m'
+NC='\033[0m' # No Color
+
+# Build if needed
+if [ ! -f "$BLUR" ]; then
+    echo -e "${YELLOW}Building blur...${NC}"
+    cd "$PROJECT_DIR" && cargo build --release
+fi
+
+passed=0
+failed=0
+
+# check <name> <expected> <actual>
+check() {
+    if [ "$2" == "$3" ]; then
+        echo -e "${GREEN}[PASS]${NC} $1"
+        passed=$((passed + 1))
+    else
+        echo -e "${RED}[FAIL]${NC} $1"
+        diff <(echo "$2") <(echo "$3") | sed 's/^/    /'
+        failed=$((failed + 1))
+    fi
+}
+
+echo "Documentation..."
+for doc in README.md ESOLANGS.wiki; do
+    output=$(cd "$PROJECT_DIR" && "$BLUR" doctest "$doc")
+    status=$?
+    check "$doc snippets pass" "0" "$status"
+    if [ $status -ne 0 ]; then
+        sed -n '/^failures:/,$p' <<< "$output" | sed 's/^/    /'
+    fi
+done
+
+echo "Annotations..."
+cat > "$TMP_DIR/doc.md" << 'EOF'
+# Doc
+
+```c
+int x = 10;
+x = 20;           // x = 16
+x++;              // history: [10, 20, 17], value = 16
+int a[2] = {1, 2};
+a[0] = 10;        // a[0] = 7
+print("x:", x);   // 16
+print(x, a[1]);   // 16 3 (wrong)
+print("hi");      // "hi" - a string
+```
+
+```bash
+blur doc.md
+```
+
+```blur
+void twice() {}
+```
+
+```blur
+int y = 5
+```
+EOF
+output=$("$BLUR" doctest "$TMP_DIR/doc.md")
+status=$?
+check "snippets in other languages are ignored" "3" "$(grep -c '^doctest .* \.\.\. ' <<< "$output")"
+check "wrong values" "$TMP_DIR/doc.md:8: a[0] is 6, expected 7
+$TMP_DIR/doc.md:10: printed \"16 2\", expected \"16 3\"" "$(grep "^$TMP_DIR/doc.md:[0-9]*: [ap]" <<< "$output")"
+check "marked snippets must parse" "1" "$(grep -c "^$TMP_DIR/doc.md:23: Parse error" <<< "$output")"
+check "summary" "doctest result: FAILED. 1 passed; 2 failed; 0 skipped; 6 annotations checked" "$(tail -1 <<< "$output")"
+check "exit 1 on failure" "1" "$status"
+
+cat > "$TMP_DIR/doc.wiki" << 'EOF'
+<pre>
+#blur 1.0
+int x = 10;
+x = 20;   // x = 15
+print(x &lt; 20);  // true
+</pre>
+
+<pre>
+blur --blur 1.0 program.blur
+</pre>
+EOF
+check "MediaWiki with directives and escapes" "doctest result: ok. 1 passed; 0 failed; 1 skipped; 2 annotations checked" \
+    "$("$BLUR" doctest "$TMP_DIR/doc.wiki" | tail -1)"
+
+echo "========================================"
+echo "Results: $passed/$((passed + failed)) passed"
+if [ $failed -gt 0 ]; then
+    exit 1
+else
+    echo -e "${GREEN}Doctests work!${NC}"
+fi
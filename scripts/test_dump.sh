@@ -0,0 +1,130 @@
+#!/bin/bash
+# Check --dump-tokens, --dump-ast and --dump-ast=json
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+PROJECT_DIR="$(dirname "$SCRIPT_DIR")"
+BLUR="$PROJECT_DIR/target/release/blur"
+
+# Colors for output
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+YELLOW='\033[1;33m'
+NC='\033[0m' # No Color
+
+# Build if needed
+if [ ! -f "$BLUR" ]; then
+    echo -e "${YELLOW}Building blur...${NC}"
+    cd "$PROJECT_DIR" && cargo build --release
+fi
+
+passed=0
+failed=0
+
+# check <name> <expected> <actual>
+check() {
+    if [ "$2" == "$3" ]; then
+        echo -e "${GREEN}[PASS]${NC} $1"
+        passed=$((passed + 1))
+    else
+        echo -e "${RED}[FAIL]${NC} $1"
+        diff <(echo "$2") <(echo "$3") | sed 's/^/    /'
+        failed=$((failed + 1))
+    fi
+}
+
+# contains <name> <needle> <haystack>
+contains() {
+    if grep -qF -- "$2" <<< "$3"; then
+        echo -e "${GREEN}[PASS]${NC} $1"
+        passed=$((passed + 1))
+    else
+        echo -e "${RED}[FAIL]${NC} $1 (no '$2')"
+        failed=$((failed + 1))
+    fi
+}
+
+read -r -d '' PROGRAM << 'EOF'
+#blur 0.5
+int blur() {
+    float x = 1.5; // a comment
+    x += 2;
+    print("hi" * 2, x);
+    return 0;
+}
+EOF
+
+echo "Tokens..."
+read -r -d '' TOKENS << 'EOF'
+2:1 Int
+2:5 Identifier("blur")
+2:9 LParen
+2:10 RParen
+2:12 LBrace
+3:5 Float
+3:11 Identifier("x")
+3:13 Assign
+3:15 FloatLiteral(1.5)
+3:18 Semicolon
+4:5 Identifier("x")
+4:7 PlusAssign
+4:10 IntLiteral(2)
+4:11 Semicolon
+5:5 Print
+5:10 LParen
+5:11 StringLiteral("hi")
+5:16 Star
+5:18 IntLiteral(2)
+5:19 Comma
+5:21 Identifier("x")
+5:22 RParen
+5:23 Semicolon
+6:5 Return
+6:12 IntLiteral(0)
+6:13 Semicolon
+7:1 RBrace
+EOF
+check "--dump-tokens skips comments and directives" "$TOKENS" "$("$BLUR" --dump-tokens - <<< "$PROGRAM")"
+check "unknown characters are error tokens" "1:1 Int
+1:5 Identifier(\"x\")
+1:7 Error(\"@\")" "$("$BLUR" -e 'int x @' --dump-tokens)"
+
+UNICODE='void blur() {
+    string s = "héllo"; int x = 1;
+}'
+check "columns count bytes, like spans" "2:26 Int" "$("$BLUR" --dump-tokens - <<< "$UNICODE" | grep " Int$")"
+contains "--dump-ast=json agrees" '"col": 26' "$("$BLUR" --dump-ast=json - <<< "$UNICODE")"
+
+echo "Syntax tree..."
+output=$("$BLUR" --dump-ast - <<< "$PROGRAM")
+contains "--dump-ast prints the tree" 'StringRepeat(' "$output"
+contains "--dump-ast includes spans" 'line: 3,' "$output"
+check "--dump-ast doesn't run the program" "" "$(grep hihi <<< "$output")"
+
+json=$("$BLUR" --dump-ast=json - <<< "$PROGRAM")
+contains "--dump-ast=json names variables" '"VarDecl": [' "$json"
+contains "--dump-ast=json has compound assignments" '"CompoundAssign": [' "$json"
+contains "--dump-ast=json has return types" '"ret": "Int"' "$json"
+check "--dump-ast=json is stable" "$json" "$("$BLUR" --dump-ast=json - <<< "$PROGRAM")"
+
+before=$failed
+for file in "$PROJECT_DIR"/examples/*.blur; do
+    if ! "$BLUR" --dump-ast=json "$file" > /dev/null; then
+        echo -e "${RED}[FAIL]${NC} $(basename "$file") doesn't dump"
+        failed=$((failed + 1))
+    fi
+done
+if [ $failed -eq $before ]; then
+    echo -e "${GREEN}[PASS]${NC} every example dumps"
+    passed=$((passed + 1))
+fi
+
+"$BLUR" --dump-ast -e 'int x = ;' > /dev/null 2>&1
+check "parse errors exit 65" "65" "$?"
+
+echo "========================================"
+echo "Results: $passed/$((passed + failed)) passed"
+if [ $failed -gt 0 ]; then
+    exit 1
+else
+    echo -e "${GREEN}Dumps work!${NC}"
+fi
@@ -0,0 +1,142 @@
+#!/bin/bash
+# Check `blur fmt`: formatting is idempotent, keeps comments and directives,
+# doesn't change what a program prints, and --check reports what would change
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+PROJECT_DIR="$(dirname "$SCRIPT_DIR")"
+BLUR="$PROJECT_DIR/target/release/blur"
+TMP_DIR="$(mktemp -d)"
+trap 'rm -rf "$TMP_DIR"' EXIT
+
+# Colors for output
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+YELLOW='\033[1;33m'
+NC='\033[0m' # No Color
+
+# Build if needed
+if [ ! -f "$BLUR" ]; then
+    echo -e "${YELLOW}Building blur...${NC}"
+    cd "$PROJECT_DIR" && cargo build --release
+fi
+
+passed=0
+failed=0
+
+# check <name> <expected> <actual>
+check() {
+    if [ "$2" == "$3" ]; then
+        echo -e "${GREEN}[PASS]${NC} $1"
+        passed=$((passed + 1))
+    else
+        echo -e "${RED}[FAIL]${NC} $1"
+        diff <(echo "$2") <(echo "$3") | sed 's/^/    /'
+        failed=$((failed + 1))
+    fi
+}
+
+read -r -d '' MESSY << 'EOF'
+#blur 0.5
+// header comment
+
+/* block
+   comment */
+int   twice( int n ){ return n*2; }   // trailing
+void nothing() {}
+int blur(){
+int x=1;x=x+2*(3-1);    // set x
+    int g[2][2] = {{1,2},{3,4}};
+  if(x>1)print(x);else print(-x);
+  if (x > 1) { print("a"); } else if (x < 0) { print("b"); } else { print("c"); }
+
+
+  sharp for(int i=0;i<3;i++){print(i, g[1][i % 2]);}
+  while(x<0)x++;
+  print(- -x, !(x > 1), x - (1 - 2), "ab" * 3);
+  return twice(x) - 1;
+}
+EOF
+
+read -r -d '' FORMATTED << 'EOF'
+#blur 0.5
+// header comment
+
+/* block
+   comment */
+int twice(int n) {
+    return n * 2;
+}  // trailing
+
+void nothing() {}
+
+int blur() {
+    int x = 1;
+    x = x + 2 * (3 - 1);  // set x
+    int g[2][2] = {{1, 2}, {3, 4}};
+    if (x > 1) print(x);
+    else print(-x);
+    if (x > 1) {
+        print("a");
+    } else if (x < 0) {
+        print("b");
+    } else {
+        print("c");
+    }
+
+    sharp for (int i = 0; i < 3; i++) {
+        print(i, g[1][i % 2]);
+    }
+    while (x < 0) x++;
+    print(-(-x), !(x > 1), x - (1 - 2), "ab" * 3);
+    return twice(x) - 1;
+}
+EOF
+
+echo "Formatting..."
+check "canonical style, comments and directives kept" "$FORMATTED" "$("$BLUR" fmt <<< "$MESSY")"
+check "formatted program prints the same" "$("$BLUR" - <<< "$MESSY")" "$("$BLUR" - <<< "$FORMATTED")"
+
+echo "Examples..."
+before=$failed
+for file in "$PROJECT_DIR"/examples/*.blur; do
+    name=$(basename "$file")
+    once="$TMP_DIR/$name"
+    if ! "$BLUR" fmt < "$file" > "$once"; then
+        echo -e "${RED}[FAIL]${NC} $name doesn't format"
+        failed=$((failed + 1))
+        continue
+    fi
+    if ! "$BLUR" fmt < "$once" | cmp -s - "$once"; then
+        echo -e "${RED}[FAIL]${NC} $name: formatting twice changes it again"
+        failed=$((failed + 1))
+    fi
+    if ! grep -q "//" "$once" && grep -q "//" "$file"; then
+        echo -e "${RED}[FAIL]${NC} $name: comments lost"
+        failed=$((failed + 1))
+    fi
+done
+if [ $failed -eq $before ]; then
+    echo -e "${GREEN}[PASS]${NC} examples format idempotently"
+    passed=$((passed + 1))
+fi
+
+echo "--check..."
+"$BLUR" fmt --check "$PROJECT_DIR"/examples/*.blur > /dev/null
+check "examples are formatted" "0" "$?"
+echo "$MESSY" > "$TMP_DIR/messy.blur"
+output=$("$BLUR" fmt --check "$TMP_DIR/messy.blur")
+check "--check exits 1 for unformatted files" "1" "$?"
+check "--check lists them" "$TMP_DIR/messy.blur" "$output"
+check "--check leaves them alone" "$MESSY" "$(cat "$TMP_DIR/messy.blur")"
+"$BLUR" fmt "$TMP_DIR/messy.blur"
+check "files are rewritten in place" "$FORMATTED" "$(cat "$TMP_DIR/messy.blur")"
+"$BLUR" fmt <<< "int blur() { int x = ; }" > /dev/null 2>&1
+check "parse errors exit 1" "1" "$?"
+
+echo "========================================"
+echo "Results: $passed/$((passed + failed)) passed"
+if [ $failed -gt 0 ]; then
+    exit 1
+else
+    echo -e "${GREEN}Formatter works!${NC}"
+fi
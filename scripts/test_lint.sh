@@ -0,0 +1,98 @@
+#!/bin/bash
+# Check that each `blur lint` rule fires where it should (and only there),
+# and that rule levels can be changed from the command line
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+PROJECT_DIR="$(dirname "$SCRIPT_DIR")"
+BLUR="$PROJECT_DIR/target/release/blur"
+TMP_DIR="$(mktemp -d)"
+trap 'rm -rf "$TMP_DIR"' EXIT
+
+# Colors for output
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+YELLOW='\033[1;33m'
+NC='\033[0m' # No Color
+
+# Build if needed
+if [ ! -f "$BLUR" ]; then
+    echo -e "${YELLOW}Building blur...${NC}"
+    cd "$PROJECT_DIR" && cargo build --release
+fi
+
+passed=0
+failed=0
+
+# check <name> <expected> <actual>
+check() {
+    if [ "$2" == "$3" ]; then
+        echo -e "${GREEN}[PASS]${NC} $1"
+        passed=$((passed + 1))
+    else
+        echo -e "${RED}[FAIL]${NC} $1"
+        diff <(echo "$2") <(echo "$3") | sed 's/^/    /'
+        failed=$((failed + 1))
+    fi
+}
+
+# lint <code> [options...]: lint statements wrapped in blur(), printing line:col [rule]
+lint() {
+    local code="$1"
+    shift
+    printf 'void blur() {\n%s\n}\n' "$code" > "$TMP_DIR/test.blur"
+    "$BLUR" lint "$@" "$TMP_DIR/test.blur" | sed -E 's/^[^:]*:([0-9]+:[0-9]+): .*\[(.*)\]$/\1 \2/'
+}
+
+echo "Rules..."
+check "stalling-for on a plain for" "2:1 stalling-for" \
+    "$(lint 'for (int i = 0; i < 3; i++) { print(i); }')"
+check "no stalling-for on sharp for" "" \
+    "$(lint 'sharp for (int i = 0; i < 3; i++) { print(i); }')"
+check "stalling-for on a counter declared outside" "3:1 stalling-for" \
+    "$(lint 'int k = 0;
+for (; k < 3; k += 1) {}')"
+check "compound-type on bool and string" "3:1 compound-type
+5:1 compound-type" "$(lint 'bool b = true;
+b += 1;
+string s = "a";
+s *= 2;
+print(b, s);')"
+check "no compound-type on numbers" "" "$(lint 'int n = 1;
+n += 1;
+print(n);')"
+check "print-unassigned" "4:7 print-unassigned" "$(lint 'int x;
+int y;
+print(x);
+y = 2;
+print(y);')"
+check "unused variables, arrays and parameters" "1:18 unused
+5:9 unused
+6:9 unused" "$(printf 'int f(int a, int b) {\n    return a;\n}\nvoid blur() {\n    int x = 1;\n    int v[3];\n    int _skip = 2;\n    print(f(1, 2));\n}\n' > "$TMP_DIR/test.blur"; "$BLUR" lint "$TMP_DIR/test.blur" | sed -E 's/^[^:]*:([0-9]+:[0-9]+): .*\[(.*)\]$/\1 \2/')"
+check "compound assignments and increments read the variable" "" "$(printf 'void blur() {\n    int b = 0;\n    b += 1;\n    int c = 0;\n    c++;\n    int d[2];\n    d[0] *= 2;\n    d[1]--;\n}\n' > "$TMP_DIR/test.blur"; "$BLUR" lint "$TMP_DIR/test.blur" | grep unused)"
+check "string-repeat on a variable" "4:1 string-repeat" "$(lint 'string s = "ab";
+print("ab" * 3);
+string t = s * 3;
+print(t);')"
+
+echo "Levels..."
+check "--allow turns a rule off" "" "$(lint 'for (int i = 0; i < 3; i++) {}' --allow stalling-for)"
+lint 'for (int i = 0; i < 3; i++) {}' > /dev/null
+check "warnings exit 0" "0" "$?"
+"$BLUR" lint --deny all "$TMP_DIR/test.blur" > /dev/null
+check "denied rules exit 1" "1" "$?"
+check "denied rules are errors" "1" "$("$BLUR" lint --deny stalling-for "$TMP_DIR/test.blur" | grep -c ': error: ')"
+"$BLUR" lint --deny nonsense "$TMP_DIR/test.blur" > /dev/null 2>&1
+check "unknown rules are rejected" "1" "$?"
+check "--rules lists every rule" "5" "$("$BLUR" lint --rules | wc -l | tr -d ' ')"
+
+echo "Examples..."
+check "only loop_chaos.blur has findings" "examples/loop_chaos.blur" \
+    "$(cd "$PROJECT_DIR" && "$BLUR" lint examples/*.blur | cut -d: -f1 | sort -u)"
+
+echo "========================================"
+echo "Results: $passed/$((passed + failed)) passed"
+if [ $failed -gt 0 ]; then
+    exit 1
+else
+    echo -e "${GREEN}Linter works!${NC}"
+fi
@@ -0,0 +1,159 @@
+#!/bin/bash
+# Drive `blur lsp` with framed Language Server Protocol messages and check
+# the responses and diagnostics it sends back
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+PROJECT_DIR="$(dirname "$SCRIPT_DIR")"
+BLUR="$PROJECT_DIR/target/release/blur"
+# Content-Length counts bytes
+export LC_ALL=C
+
+# Colors for output
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+YELLOW='\033[1;33m'
+NC='\033[0m' # No Color
+
+# Build if needed
+if [ ! -f "$BLUR" ]; then
+    echo -e "${YELLOW}Building blur...${NC}"
+    cd "$PROJECT_DIR" && cargo build --release
+fi
+
+read -r -d '' PROGRAM << 'EOF'
+#blur 0.5
+int twice(int n) {
+    return n * 2;
+}
+
+void blur() {
+    int x = twice(3);
+    sharp for (int i = 0; i < 3; i++) {
+        x = x + i;
+    }
+    print(x, y);
+    push(x, 1);
+    twice(1, 2);
+}
+EOF
+
+passed=0
+failed=0
+MESSAGES=""
+ID=0
+
+# send <json>: queue a framed message
+send() {
+    MESSAGES+=$(printf 'Content-Length: %d\r\n\r\n%s' "${#1}" "$1")
+}
+
+# request <method> [params]: queue a request with the next id
+request() {
+    ID=$((ID + 1))
+    send "{\"jsonrpc\":\"2.0\",\"id\":$ID,\"method\":\"$1\",\"params\":${2:-{\}}}"
+}
+
+# at <line> <character>: params for a position in the test document (from 0)
+at() {
+    echo "{\"textDocument\":{\"uri\":\"file:///test.blur\"},\"position\":{\"line\":$1,\"character\":$2}}"
+}
+
+# start <text>: begin a session that opens a document with the given text
+start() {
+    MESSAGES=""
+    ID=0
+    request initialize
+    send '{"jsonrpc":"2.0","method":"initialized","params":{}}'
+    local text
+    text=$(printf '%s\n' "$1" | sed 's/\\/\\\\/g; s/"/\\"/g; s/\t/\\t/g' | awk '{ printf "%s\\n", $0 }')
+    send "{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/didOpen\",\"params\":{\"textDocument\":{\"uri\":\"file:///test.blur\",\"languageId\":\"blur\",\"version\":1,\"text\":\"$text\"}}}"
+}
+
+# run: send the queued messages and print what comes back
+run() {
+    request shutdown
+    send '{"jsonrpc":"2.0","method":"exit"}'
+    printf '%s' "$MESSAGES" | timeout 10s "$BLUR" lsp 2>&1
+}
+
+# check <name> <expected fragment>...
+check() {
+    local name="$1"
+    shift
+    local output
+    output=$(run)
+    for expected in "$@"; do
+        if ! grep -qF -- "$expected" <<< "$output"; then
+            echo -e "${RED}[FAIL]${NC} $name: missing '$expected'"
+            echo "$output" | tr -d '\r' | sed 's/^/    /'
+            failed=$((failed + 1))
+            return
+        fi
+    done
+    echo -e "${GREEN}[PASS]${NC} $name"
+    passed=$((passed + 1))
+}
+
+start "$PROGRAM"
+check "initialize advertises the features" \
+    '"definitionProvider":true' '"hoverProvider":true' '"completionProvider":{}' '"documentSymbolProvider":true'
+
+start "$PROGRAM"
+check "diagnostics have ranges" \
+    '{"message":"Undefined variable: y","range":{"end":{"character":14,"line":10},"start":{"character":13,"line":10}}' \
+    '{"message":"Type mismatch: x is not an array","range":{"end":{"character":10,"line":11},"start":{"character":9,"line":11}}' \
+    '"message":"twice() takes 1 argument, got 2"'
+
+start $'void blur() {\n    int x = ;\n}'
+check "parse errors point at the token" \
+    '{"message":"Parse error: Unexpected token: ;","range":{"end":{"character":13,"line":1},"start":{"character":12,"line":1}}'
+
+start "$PROGRAM"
+request textDocument/definition "$(at 8 13)"
+request textDocument/definition "$(at 6 14)"
+check "go to definition" \
+    '"id":2,"jsonrpc":"2.0","result":{"range":{"end":{"character":9,"line":6},"start":{"character":8,"line":6}}' \
+    '"id":3,"jsonrpc":"2.0","result":{"range":{"end":{"character":9,"line":1},"start":{"character":4,"line":1}}'
+
+start "$PROGRAM"
+request textDocument/hover "$(at 8 17)"
+request textDocument/hover "$(at 2 12)"
+request textDocument/hover "$(at 6 13)"
+check "hover shows the type and sharpness" \
+    '```blur\nint i\n```\n\nvariable of blur(); **sharp**' '```blur\nint n\n```\n\nparameter of twice()' \
+    '```blur\nint twice(int n)\n```'
+
+start "$PROGRAM"
+request textDocument/completion "$(at 7 0)"
+check "completion offers keywords and builtins" \
+    '{"kind":14,"label":"sharp"}' '"label":"print"' '"label":"blurstr"' '"label":"get_blur"' '"label":"push"'
+
+start "$PROGRAM"
+request textDocument/documentSymbol '{"textDocument":{"uri":"file:///test.blur"}}'
+check "document symbols list the functions" \
+    '"detail":"int twice(int n)","kind":12,"name":"twice"' '"detail":"void blur()","kind":12,"name":"blur"'
+
+# No false alarms on the examples
+before=$failed
+for file in "$PROJECT_DIR"/examples/*.blur; do
+    start "$(cat "$file")"
+    output=$(run)
+    if grep -qF '"diagnostics":[]' <<< "$output"; then
+        passed=$((passed + 1))
+    else
+        echo -e "${RED}[FAIL]${NC} $(basename "$file") has diagnostics"
+        grep -o '"diagnostics":.*' <<< "$output" | sed 's/^/    /'
+        failed=$((failed + 1))
+    fi
+done
+if [ $failed -eq $before ]; then
+    echo -e "${GREEN}[PASS]${NC} examples have no diagnostics"
+fi
+
+echo "========================================"
+echo "Results: $passed/$((passed + failed)) passed"
+if [ $failed -gt 0 ]; then
+    exit 1
+else
+    echo -e "${GREEN}Language server works!${NC}"
+fi
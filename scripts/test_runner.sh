@@ -0,0 +1,151 @@
+#!/bin/bash
+# Check `blur test`: the assert builtins, failure reports, isolation between
+# tests and files, the exit code, and golden files
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+PROJECT_DIR="$(dirname "$SCRIPT_DIR")"
+BLUR="$PROJECT_DIR/target/release/blur"
+TMP_DIR="$(mktemp -d)"
+trap 'rm -rf "$TMP_DIR"' EXIT
+
+# Colors for output
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+YELLOW='\033[1;33m'
+NC='\033[0m' # No Color
+
+# Build if needed
+if [ ! -f "$BLUR" ]; then
+    echo -e "${YELLOW}Building blur...${NC}"
+    cd "$PROJECT_DIR" && cargo build --release
+fi
+
+passed=0
+failed=0
+
+# check <name> <expected> <actual>
+check() {
+    if [ "$2" == "$3" ]; then
+        echo -e "${GREEN}[PASS]${NC} $1"
+        passed=$((passed + 1))
+    else
+        echo -e "${RED}[FAIL]${NC} $1"
+        diff <(echo "$2") <(echo "$3") | sed 's/^/    /'
+        failed=$((failed + 1))
+    fi
+}
+
+echo "Asserts in programs..."
+check "assert passes" "ok" "$("$BLUR" -e 'assert(1 < 2); print("ok");' 2>&1)"
+check "assert fails" "Runtime error: Assertion failed: condition is false" "$("$BLUR" -e 'assert(2 < 1);' 2>&1)"
+check "assert_eq shows both values" 'Runtime error: Assertion failed: "ab" != "cd"' \
+    "$("$BLUR" -e 'assert_eq("ab", "cd");' 2>&1)"
+check "assert_eq compares ints with floats" "ok" "$("$BLUR" -e 'assert_eq(2, 2.0); print("ok");' 2>&1)"
+check "assert_near" "Runtime error: Assertion failed: 1.5 is not within 0.1 of 1" \
+    "$("$BLUR" -e 'assert_near(1.5, 1, 0.1);' 2>&1)"
+check "both engines agree" "$("$BLUR" -e 'assert_eq(1, 2);' 2>&1)" "$("$BLUR" --tree-walk -e 'assert_eq(1, 2);' 2>&1)"
+
+echo "Test runs..."
+mkdir -p "$TMP_DIR/suite/nested"
+cat > "$TMP_DIR/suite/a.blur" << 'EOF'
+#blur 1.0
+
+void test_pure_average() {
+    int x = 10;
+    x = 20;
+    assert_eq(x, 15);
+}
+
+void test_fails_in_helper() {
+    print("checking");
+    positive(-1);
+}
+
+void positive(int n) {
+    assert(n > 0);
+}
+
+void test_args_are_not_tests(int n) {
+    assert(false);
+}
+EOF
+cat > "$TMP_DIR/suite/nested/b.blur" << 'EOF'
+void test_default_blur() {
+    int x = 10;
+    x = 20;
+    assert_eq(x, 16);
+}
+
+// 52 iterations at blur 0.9, 1673 at blur 1.0
+void test_loop_under_limit() {
+    for (int i = 0; i < 8; i++) {}
+}
+EOF
+output=$(cd "$TMP_DIR" && "$BLUR" test suite)
+status=$?
+check "every test is listed" "4" "$(grep -c '^test suite/.* \.\.\. ' <<< "$output")"
+check "failure location is in the helper" "suite/a.blur:15:5: in positive(): Assertion failed: condition is false" \
+    "$(grep '^suite/a.blur:' <<< "$output")"
+check "failure output is captured" "    checking" "$(grep -A1 '^output:' <<< "$output" | tail -1)"
+check "directives only apply to their own file" "0" "$(grep -c 'b.blur.*FAILED' <<< "$output")"
+check "summary" "test result: FAILED. 3 passed; 1 failed" "$(tail -1 <<< "$output")"
+check "exit 1 on failure" "1" "$status"
+(cd "$TMP_DIR" && "$BLUR" test suite/nested > /dev/null)
+check "exit 0 when all pass" "0" "$?"
+check "limits from the command line" "suite/nested/b.blur:9:28: in test_loop_under_limit(): for loop hit 40 iteration limit (use 'sharp for' for unlimited)" \
+    "$(cd "$TMP_DIR" && "$BLUR" test --max-loop 40 suite/nested | grep '^suite/')"
+"$BLUR" test "$TMP_DIR/missing" > /dev/null 2>&1
+check "exit 1 on a missing path" "1" "$?"
+check "repo tests pass" "0" "$(cd "$PROJECT_DIR" && "$BLUR" test tests > /dev/null; echo $?)"
+
+echo "Golden files..."
+mkdir -p "$TMP_DIR/golden"
+cat > "$TMP_DIR/golden/comments.blur" << 'EOF'
+#blur 1.0
+void blur() {
+    int x = 10;
+    x = 20;
+    print(x);  // expect: 15
+    print("a", x);  // expect: a 16
+}
+EOF
+cat > "$TMP_DIR/golden/block.blur" << 'EOF'
+void blur() {
+    print(1);
+    print(2);
+}
+
+/* expected-output:
+1
+2
+*/
+EOF
+printf 'void blur() {\n    print("new");\n}\n' > "$TMP_DIR/golden/none.blur"
+output=$(cd "$TMP_DIR" && "$BLUR" test --golden golden)
+status=$?
+check "files without expectations are skipped" "0" "$(grep -c 'none.blur' <<< "$output")"
+check "mismatch location and diff" "golden/comments.blur:6:21: output differs from the expected output
+-a 16
++a 15" "$(grep -A2 '^golden/comments.blur:' <<< "$output")"
+check "golden summary" "golden result: FAILED. 1 passed; 1 failed" "$(tail -1 <<< "$output")"
+check "exit 1 on a golden failure" "1" "$status"
+(cd "$TMP_DIR" && "$BLUR" test --golden --update golden > /dev/null)
+check "update rewrites comments in place" '    print("a", x);  // expect: a 15' "$(grep 'print("a"' "$TMP_DIR/golden/comments.blur")"
+check "update leaves files without expectations alone" "}" "$(tail -1 "$TMP_DIR/golden/none.blur")"
+(cd "$TMP_DIR" && "$BLUR" test --golden --update golden/none.blur > /dev/null)
+check "update adds a block to a file named on the command line" "$(printf '/* expected-output:\nnew\n*/')" "$(tail -3 "$TMP_DIR/golden/none.blur")"
+(cd "$TMP_DIR" && "$BLUR" test --golden golden > /dev/null)
+check "exit 0 after updating" "0" "$?"
+mkdir -p "$TMP_DIR/unicode"
+printf 'void blur() {\n    print("é"); // expect: e\n}\n' > "$TMP_DIR/unicode/accent.blur"
+check "golden columns count bytes" "unicode/accent.blur:2:18: output differs from the expected output" "$(cd "$TMP_DIR" && "$BLUR" test --golden unicode | grep '^unicode/')"
+"$BLUR" test --update "$TMP_DIR/golden" > /dev/null 2>&1
+check "--update needs --golden" "2" "$?"
+
+echo "========================================"
+echo "Results: $passed/$((passed + failed)) passed"
+if [ $failed -gt 0 ]; then
+    exit 1
+else
+    echo -e "${GREEN}Test runner works!${NC}"
+fi
@@ -0,0 +1,223 @@
+// Loop analysis (`blur analyze`): how long a blurred for loop runs
+//
+// A plain for loop's counter is averaged with its history, so `i++` only
+// moves it a fraction of a step and the loop gets stuck on each value for
+// longer and longer. For a simple counter loop (a literal start, a
+// condition comparing the counter with a literal, an update by a literal
+// step, and a body that leaves the counter alone) the count depends only on
+// the bounds and the blur factor. It is found by replaying the counter on a
+// real BlurValue with the interpreter's own arithmetic, so it is exact.
+
+use crate::ast::*;
+use crate::builtins::{self, Param};
+use crate::compiler::IncResult;
+use crate::fmt;
+use crate::interpreter::{compound, eval_binop, inc, BlurValue, Value};
+
+/// Iterations replayed before giving up on an exact count
+pub const SIMULATION_LIMIT: u64 = 1_000_000;
+
+/// What one simple counter loop does each time it runs
+#[derive(Debug, Clone)]
+pub struct LoopReport {
+    pub span: Span,
+    /// "for (int i = 0; i < 5; i++)"
+    pub header: String,
+    pub counter: String,
+    /// Body executions; None if more than SIMULATION_LIMIT
+    pub iterations: Option<u64>,
+    /// Each value the condition saw the counter at, and for how many iterations in a row
+    pub runs: Vec<(Value, u64)>,
+    /// The body has a return, so it may stop sooner
+    pub may_return: bool,
+}
+
+/// Analyze every simple counter loop in a program, in source order
+pub fn analyze(program: &Program) -> Vec<LoopReport> {
+    let mut reports = Vec::new();
+    for func in &program.functions {
+        walk(&func.body, &mut reports);
+    }
+    reports
+}
+
+fn walk(stmts: &[Stmt], reports: &mut Vec<LoopReport>) {
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::If(_, then_branch, else_branch) => {
+                walk(std::slice::from_ref(then_branch), reports);
+                if let Some(else_branch) = else_branch {
+                    walk(std::slice::from_ref(else_branch), reports);
+                }
+            }
+            StmtKind::While(_, body) | StmtKind::SharpFor(_, _, _, body) => walk(std::slice::from_ref(body), reports),
+            StmtKind::For(init, cond, update, body) => {
+                if let (Some(init), Some(cond), Some(update)) = (init, cond, update) {
+                    reports.extend(simulate(stmt.span, init, cond, update, body));
+                }
+                walk(std::slice::from_ref(body), reports);
+            }
+            StmtKind::Block(stmts) => walk(stmts, reports),
+            _ => {}
+        }
+    }
+}
+
+/// A literal, possibly negated
+fn constant(expr: &Expr) -> Option<Value> {
+    match expr {
+        Expr::IntLit(n) => Some(Value::Int(*n)),
+        Expr::FloatLit(x) => Some(Value::Float(*x)),
+        Expr::UnaryOp(UnaryOp::Neg, operand) => match constant(operand)? {
+            Value::Int(n) => Some(Value::Int(-n)),
+            Value::Float(x) => Some(Value::Float(-x)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+enum Step {
+    Inc(f64),
+    Compound(CompoundOp, Value),
+}
+
+/// Replay a for loop if it is a simple counter loop
+fn simulate(span: Span, init: &Stmt, cond: &Expr, update: &Stmt, body: &Stmt) -> Option<LoopReport> {
+    let StmtKind::VarDecl(ty @ (Type::Int | Type::Float), counter, Some(start)) = &init.kind else {
+        return None;
+    };
+    let start = constant(start)?;
+    let is_counter = |e: &Expr| matches!(e, Expr::Var(ident) if ident.name == counter.name);
+    // The counter on one side of a comparison, a literal on the other
+    let (op, bound, counter_left) = match cond {
+        Expr::BinOp(left, op, right) if is_counter(left) => (*op, constant(right)?, true),
+        Expr::BinOp(left, op, right) if is_counter(right) => (*op, constant(left)?, false),
+        _ => return None,
+    };
+    let step = match &update.kind {
+        StmtKind::PreIncrement(ident) | StmtKind::PostIncrement(ident) if ident.name == counter.name => Step::Inc(1.0),
+        StmtKind::PreDecrement(ident) | StmtKind::PostDecrement(ident) if ident.name == counter.name => Step::Inc(-1.0),
+        StmtKind::CompoundAssign(ident, op, value) if ident.name == counter.name => Step::Compound(*op, constant(value)?),
+        _ => return None,
+    };
+    if writes(body, counter) {
+        return None;
+    }
+    let may_return = returns(body);
+
+    let mut value = BlurValue::new(ty.clone());
+    value.push_value(&start);
+    let mut iterations = 0;
+    let mut runs: Vec<(Value, u64)> = Vec::new();
+    loop {
+        let current = value.get();
+        let (left, right) = if counter_left { (current.clone(), bound.clone()) } else { (bound.clone(), current.clone()) };
+        if !eval_binop(left, op, right).ok()?.to_bool() {
+            break;
+        }
+        if iterations == SIMULATION_LIMIT {
+            let header = header(init, cond, update);
+            return Some(LoopReport { span, header, counter: counter.to_string(), iterations: None, runs, may_return });
+        }
+        iterations += 1;
+        match runs.last_mut() {
+            Some((seen, count)) if seen.to_f64() == current.to_f64() => *count += 1,
+            _ => runs.push((current, 1)),
+        }
+        match &step {
+            Step::Inc(delta) => {
+                inc(&mut value, *delta, IncResult::None);
+            }
+            Step::Compound(op, rhs) => value.push(compound(value.get_raw(), *op, rhs).ok()?),
+        }
+    }
+    let header = header(init, cond, update);
+    Some(LoopReport { span, header, counter: counter.to_string(), iterations: Some(iterations), runs, may_return })
+}
+
+fn header(init: &Stmt, cond: &Expr, update: &Stmt) -> String {
+    format!("for ({}; {}; {})", fmt::simple(init), fmt::expr(cond), fmt::simple(update))
+}
+
+/// Whether a statement assigns `counter` anywhere (by name, so a shadowing
+/// variable counts too)
+fn writes(stmt: &Stmt, counter: &Ident) -> bool {
+    let named = |ident: &Ident| ident.name == counter.name;
+    match &stmt.kind {
+        StmtKind::VarDecl(_, ident, init) => named(ident) || writes_any(init.iter(), counter),
+        StmtKind::Assign(ident, value) | StmtKind::CompoundAssign(ident, _, value) => {
+            named(ident) || expr_writes(value, counter)
+        }
+        StmtKind::PreIncrement(ident)
+        | StmtKind::PreDecrement(ident)
+        | StmtKind::PostIncrement(ident)
+        | StmtKind::PostDecrement(ident) => named(ident),
+        StmtKind::If(cond, then_branch, else_branch) => {
+            expr_writes(cond, counter)
+                || writes(then_branch, counter)
+                || else_branch.as_ref().is_some_and(|s| writes(s, counter))
+        }
+        StmtKind::While(cond, body) => expr_writes(cond, counter) || writes(body, counter),
+        StmtKind::For(init, cond, update, body) | StmtKind::SharpFor(init, cond, update, body) => {
+            [init, update].into_iter().flatten().any(|s| writes(s, counter))
+                || writes_any(cond.iter(), counter)
+                || writes(body, counter)
+        }
+        StmtKind::Block(stmts) => stmts.iter().any(|s| writes(s, counter)),
+        StmtKind::Expr(expr) => expr_writes(expr, counter),
+        StmtKind::Print(values) => writes_any(values.iter(), counter),
+        StmtKind::Return(value) => writes_any(value.iter(), counter),
+        // Arrays can't be counters, but their indices can change one
+        StmtKind::ArrayDecl(_, _, dims, _) => writes_any(dims.iter().flatten(), counter),
+        StmtKind::ArrayAssign(_, indices, value) | StmtKind::ArrayCompoundAssign(_, indices, _, value) => {
+            writes_any(indices.iter().chain([value]), counter)
+        }
+        StmtKind::ArrayPreIncrement(_, indices)
+        | StmtKind::ArrayPreDecrement(_, indices)
+        | StmtKind::ArrayPostIncrement(_, indices)
+        | StmtKind::ArrayPostDecrement(_, indices) => writes_any(indices.iter(), counter),
+    }
+}
+
+fn writes_any<'e>(exprs: impl IntoIterator<Item = &'e Expr>, counter: &Ident) -> bool {
+    exprs.into_iter().any(|e| expr_writes(e, counter))
+}
+
+/// Whether an expression increments or decrements `counter`, or passes it
+/// to a builtin that stores a result in it
+fn expr_writes(expr: &Expr, counter: &Ident) -> bool {
+    match expr {
+        Expr::PreIncrement(ident) | Expr::PreDecrement(ident) | Expr::PostIncrement(ident) | Expr::PostDecrement(ident) => {
+            ident.name == counter.name
+        }
+        Expr::BinOp(left, _, right) | Expr::StringRepeat(left, right) => {
+            expr_writes(left, counter) || expr_writes(right, counter)
+        }
+        Expr::UnaryOp(_, operand) => expr_writes(operand, counter),
+        Expr::ArrayAccess(_, indices)
+        | Expr::ArrayPreIncrement(_, indices)
+        | Expr::ArrayPreDecrement(_, indices)
+        | Expr::ArrayPostIncrement(_, indices)
+        | Expr::ArrayPostDecrement(_, indices) => writes_any(indices, counter),
+        Expr::Call(name, args) => {
+            let params = builtins::lookup(name).map_or(&[][..], |b| b.params);
+            args.iter().enumerate().any(|(i, arg)| match (params.get(i), arg) {
+                (Some(Param::Var), Expr::Var(ident)) => ident.name == counter.name,
+                _ => expr_writes(arg, counter),
+            })
+        }
+        _ => false,
+    }
+}
+
+/// Whether a statement contains a return
+fn returns(stmt: &Stmt) -> bool {
+    match &stmt.kind {
+        StmtKind::Return(_) => true,
+        StmtKind::If(_, then_branch, else_branch) => returns(then_branch) || else_branch.as_deref().is_some_and(returns),
+        StmtKind::While(_, body) | StmtKind::For(_, _, _, body) | StmtKind::SharpFor(_, _, _, body) => returns(body),
+        StmtKind::Block(stmts) => stmts.iter().any(returns),
+        _ => false,
+    }
+}
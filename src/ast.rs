@@ -1,4 +1,7 @@
-#[derive(Debug, Clone, PartialEq)]
+use crate::symbol::Symbol;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Type {
     Int,
     Float,
@@ -6,9 +9,129 @@ pub enum Type {
     Char,
     String,
     Void,
+    // Array of the given element type (function parameters: int arr[], float grid[][])
+    Array(Box<Type>),
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::Char => write!(f, "char"),
+            Type::String => write!(f, "string"),
+            Type::Void => write!(f, "void"),
+            Type::Array(inner) => write!(f, "{}[]", inner),
+        }
+    }
+}
+
+/// A region of the source: byte offsets, plus the line and column (from 1) it starts at
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: u32,
+    pub col: u32,
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// Byte offset where each line of a source starts, for turning offsets into
+/// spans. Columns count bytes, like the parser's.
+#[derive(Debug, Clone, Default)]
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> Self {
+        let starts = std::iter::once(0).chain(source.match_indices('\n').map(|(i, _)| i + 1)).collect();
+        LineIndex { starts }
+    }
+
+    /// Line (from 0) of a byte offset
+    pub fn line(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&s| s <= offset) - 1
+    }
+
+    /// Byte offset where a line (from 0) starts
+    pub fn start(&self, line: usize) -> Option<usize> {
+        self.starts.get(line).copied()
+    }
+
+    /// The span from `start` to `end`, with the line and column `start` is at
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        let line = self.line(start);
+        let col = start - self.starts[line] + 1;
+        Span { start, end, line: line as u32 + 1, col: col as u32 }
+    }
 }
 
+/// Where a variable lives, filled in by the resolver
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Addr {
+    /// Not declared in the function (or not resolved yet): looked up by name
+    /// at run time in the innermost scope that has it, the callers' included
+    #[default]
+    Dynamic,
+    /// A scalar variable `depth` scopes out from the current one (0 = current), at `slot`
+    Var { depth: u32, slot: u32 },
+    /// An array, addressed the same way (arrays have their own slots)
+    Array { depth: u32, slot: u32 },
+}
+
+impl Addr {
+    /// How many scopes out from the current one the name was declared, if the
+    /// resolver found its declaration
+    pub fn depth(self) -> Option<u32> {
+        match self {
+            Addr::Dynamic => None,
+            Addr::Var { depth, .. } | Addr::Array { depth, .. } => Some(depth),
+        }
+    }
+}
+
+/// A variable or array name
 #[derive(Debug, Clone)]
+pub struct Ident {
+    pub name: Symbol,
+    pub addr: Addr,
+    /// Where the name appears (default when not parsed from source)
+    pub span: Span,
+}
+
+impl Ident {
+    pub fn new(name: &str) -> Self {
+        Ident { name: Symbol::intern(name), addr: Addr::Dynamic, span: Span::default() }
+    }
+}
+
+// Serialized as just the name: addresses are recomputed by the resolver
+impl Serialize for Ident {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.name.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ident {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Ident { name: Symbol::deserialize(deserializer)?, addr: Addr::Dynamic, span: Span::default() })
+    }
+}
+
+impl std::fmt::Display for Ident {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Expr {
     // Literals
     IntLit(i64),
@@ -18,8 +141,8 @@ pub enum Expr {
     StringLit(String),
 
     // Variable access
-    Var(String),
-    ArrayAccess(String, Box<Expr>), // array[index]
+    Var(Ident),
+    ArrayAccess(Ident, Vec<Expr>), // array[i] or grid[i][j]
 
     // Binary operations
     BinOp(Box<Expr>, BinOp, Box<Expr>),
@@ -28,16 +151,16 @@ pub enum Expr {
     UnaryOp(UnaryOp, Box<Expr>),
 
     // Pre/post increment/decrement
-    PreIncrement(String),
-    PreDecrement(String),
-    PostIncrement(String),
-    PostDecrement(String),
+    PreIncrement(Ident),
+    PreDecrement(Ident),
+    PostIncrement(Ident),
+    PostDecrement(Ident),
 
     // Array pre/post increment/decrement
-    ArrayPreIncrement(String, Box<Expr>),
-    ArrayPreDecrement(String, Box<Expr>),
-    ArrayPostIncrement(String, Box<Expr>),
-    ArrayPostDecrement(String, Box<Expr>),
+    ArrayPreIncrement(Ident, Vec<Expr>),
+    ArrayPreDecrement(Ident, Vec<Expr>),
+    ArrayPostIncrement(Ident, Vec<Expr>),
+    ArrayPostDecrement(Ident, Vec<Expr>),
 
     // Function call
     Call(String, Vec<Expr>),
@@ -46,7 +169,7 @@ pub enum Expr {
     StringRepeat(Box<Expr>, Box<Expr>),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum BinOp {
     Add,
     Sub,
@@ -63,46 +186,55 @@ pub enum BinOp {
     Or,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum UnaryOp {
     Neg,
     Not,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum CompoundOp {
-    AddAssign,
-    SubAssign,
-    MulAssign,
-    DivAssign,
-    ModAssign,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
 }
 
-#[derive(Debug, Clone)]
-pub enum Stmt {
+/// A statement and where it is in the source
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    #[serde(default)]
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum StmtKind {
     // Variable declaration with optional initializer
-    VarDecl(Type, String, Option<Expr>),
+    VarDecl(Type, Ident, Option<Expr>),
 
-    // Array declaration: type name[size] or type name[size] = {values}
-    ArrayDecl(Type, String, usize, Option<Vec<Expr>>),
+    // Array declaration: type name[size], type name[] = {values}, type name[] = expr,
+    // or one size per dimension: type name[rows][cols]
+    ArrayDecl(Type, Ident, Vec<Option<Expr>>, Option<ArrayInit>),
 
     // Assignment
-    Assign(String, Expr),
-    ArrayAssign(String, Expr, Expr), // array[index] = value
+    Assign(Ident, Expr),
+    ArrayAssign(Ident, Vec<Expr>, Expr), // array[index] = value
 
     // Compound assignment (+=, -=, etc.)
-    CompoundAssign(String, CompoundOp, Expr),
-    ArrayCompoundAssign(String, Expr, CompoundOp, Expr),
+    CompoundAssign(Ident, CompoundOp, Expr),
+    ArrayCompoundAssign(Ident, Vec<Expr>, CompoundOp, Expr),
 
     // Increment/Decrement statements
-    PreIncrement(String),
-    PreDecrement(String),
-    PostIncrement(String),
-    PostDecrement(String),
-    ArrayPreIncrement(String, Expr),
-    ArrayPreDecrement(String, Expr),
-    ArrayPostIncrement(String, Expr),
-    ArrayPostDecrement(String, Expr),
+    PreIncrement(Ident),
+    PreDecrement(Ident),
+    PostIncrement(Ident),
+    PostDecrement(Ident),
+    ArrayPreIncrement(Ident, Vec<Expr>),
+    ArrayPreDecrement(Ident, Vec<Expr>),
+    ArrayPostIncrement(Ident, Vec<Expr>),
+    ArrayPostDecrement(Ident, Vec<Expr>),
 
     // Control flow
     If(Expr, Box<Stmt>, Option<Box<Stmt>>),
@@ -124,14 +256,34 @@ pub enum Stmt {
     Return(Option<Expr>),
 }
 
-#[derive(Debug, Clone)]
+/// Initializer of an array declaration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ArrayInit {
+    // {1, 2, 3} - nested braces are flattened in row-major order
+    List(Vec<Expr>),
+    // Any expression evaluating to an array (e.g. a function call)
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Function {
     pub name: String,
-    pub params: Vec<(Type, String)>,
+    pub params: Vec<(Type, Ident)>,
     pub body: Vec<Stmt>,
+    #[serde(default = "void")]
+    pub ret: Type,
+    /// The whole definition, and just its name
+    #[serde(default)]
+    pub span: Span,
+    #[serde(default)]
+    pub name_span: Span,
 }
 
-#[derive(Debug, Clone)]
+fn void() -> Type {
+    Type::Void
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Program {
     pub functions: Vec<Function>,
 }
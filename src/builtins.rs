@@ -0,0 +1,333 @@
+// Built-in functions shared by the tree-walking interpreter and the bytecode VM
+//
+// Both engines check the argument count and the by-reference arguments,
+// evaluate the remaining arguments left to right, resolve the referenced
+// variable and then call `call()`. blurstr() is handled by the engines
+// themselves because `"str" * n` means "push n times" inside it.
+
+use crate::ast::Type;
+use crate::image::{self, Image};
+use crate::interpreter::{BlurArray, BlurValue, ExecutionLimits, RuntimeError, Value};
+use crate::spatial;
+use crate::wav::{self, Wav};
+
+/// How a builtin receives an argument
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Param {
+    /// Any expression, passed by value
+    Value,
+    /// An array variable, modified in place
+    Array,
+    /// A scalar variable that receives a result
+    Var,
+}
+
+pub struct Builtin {
+    pub name: &'static str,
+    pub params: &'static [Param],
+    /// How many of the trailing parameters may be left out
+    pub optional: usize,
+    /// Describes the arguments in error messages: "push() expects <usage>"
+    pub usage: &'static str,
+}
+
+use Param::{Array, Value as Val, Var};
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin { name: "len", params: &[Val, Val], optional: 1, usage: "an array or string and an optional dimension" },
+    Builtin { name: "push", params: &[Array, Val], optional: 0, usage: "an array and a value" },
+    Builtin { name: "pop", params: &[Array], optional: 0, usage: "an array" },
+    Builtin { name: "smooth", params: &[Array, Val], optional: 0, usage: "an array and a radius" },
+    Builtin { name: "convolve", params: &[Array, Val], optional: 0, usage: "an array and a kernel" },
+    Builtin { name: "gaussian", params: &[Array, Val], optional: 0, usage: "an array and a sigma" },
+    Builtin { name: "load_image", params: &[Val], optional: 0, usage: "a path" },
+    Builtin { name: "save_image", params: &[Val, Val, Val], optional: 1, usage: "an array, a path and an optional plain flag" },
+    Builtin { name: "load_wav", params: &[Val, Var], optional: 1, usage: "a path and an optional sample rate variable" },
+    Builtin { name: "save_wav", params: &[Val, Val, Val], optional: 0, usage: "a path, an array and a sample rate" },
+    Builtin { name: "assert", params: &[Val], optional: 0, usage: "a condition" },
+    Builtin { name: "assert_eq", params: &[Val, Val], optional: 0, usage: "two values" },
+    Builtin { name: "assert_near", params: &[Val, Val, Val], optional: 0, usage: "two numbers and a tolerance" },
+];
+
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name == name)
+}
+
+impl Builtin {
+    /// Check the argument count and that by-reference arguments are plain variables.
+    /// `is_var(i)` tells whether argument i is a variable name.
+    pub fn check_args(&self, count: usize, is_var: impl Fn(usize) -> bool) -> Result<(), RuntimeError> {
+        if count > self.params.len() || count + self.optional < self.params.len() {
+            return Err(RuntimeError::TypeMismatch(format!("{}() expects {}", self.name, self.usage)));
+        }
+        for (i, param) in self.params.iter().enumerate().take(count) {
+            if *param != Param::Value && !is_var(i) {
+                let kind = if *param == Param::Array { "an array variable" } else { "a variable" };
+                return Err(RuntimeError::TypeMismatch(format!(
+                    "{}() expects {} as argument {}", self.name, kind, i + 1
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The variable a builtin works on in place
+pub enum Target<'a> {
+    None,
+    Array(&'a str, &'a mut BlurArray),
+    Var(&'a mut BlurValue),
+}
+
+/// blurstr(...) - blur strings together; each part is pushed `times` times
+pub fn blurstr(parts: Vec<(Value, usize)>) -> Value {
+    let mut blur_val = BlurValue::new(Type::String);
+    for (value, times) in parts {
+        if let Value::String(s) = value {
+            blur_val.push_string_times(&s, times);
+        }
+    }
+    blur_val.get()
+}
+
+/// Call a builtin with its by-value arguments (in order) and its target variable;
+/// `limits` bounds the history of elements it pushes into
+pub fn call(name: &str, args: Vec<Value>, target: Target<'_>, limits: &ExecutionLimits) -> Result<Value, RuntimeError> {
+    let mut args = args.into_iter();
+    match (name, target) {
+        ("len", _) => {
+            let value = args.next();
+            let dim = args.next().map(|d| d.to_f64() as usize).unwrap_or(0);
+            len(value, dim)
+        }
+        ("push", Target::Array(_, arr)) => {
+            let mut elem = BlurValue::new(arr.elem_type.clone());
+            elem.push_value(&args.next().unwrap_or(Value::Void));
+            arr.push(elem)?;
+            Ok(Value::Int(arr.len() as i64))
+        }
+        ("pop", Target::Array(arr_name, arr)) => match arr.pop()? {
+            Some(elem) => Ok(elem.get()),
+            None => Err(RuntimeError::EmptyArray(arr_name.to_string())),
+        },
+        ("smooth" | "convolve" | "gaussian", Target::Array(_, arr)) => {
+            filter(name, args.next().unwrap_or(Value::Void), arr, limits)
+        }
+        ("load_image", _) => load_image(&string(name, args.next(), 1)?),
+        ("save_image", _) => {
+            let arr = array(name, args.next(), 1)?;
+            let path = string(name, args.next(), 2)?;
+            let plain = args.next().is_some_and(|p| p.to_bool());
+            save_image(&arr, &path, plain)
+        }
+        ("load_wav", target) => {
+            let path = string(name, args.next(), 1)?;
+            let (samples, rate) = load_wav(&path)?;
+            if let Target::Var(var) = target {
+                var.push(rate);
+            }
+            Ok(samples)
+        }
+        ("save_wav", _) => {
+            let path = string(name, args.next(), 1)?;
+            let arr = array(name, args.next(), 2)?;
+            let rate = args.next().map(|r| r.to_f64()).unwrap_or(0.0);
+            save_wav(&path, &arr, rate)
+        }
+        ("assert", _) => match args.next() {
+            Some(cond) if cond.to_bool() => Ok(Value::Void),
+            _ => Err(RuntimeError::AssertionFailed("condition is false".to_string())),
+        },
+        ("assert_eq", _) => {
+            let (left, right) = (args.next().unwrap_or(Value::Void), args.next().unwrap_or(Value::Void));
+            if same(&left, &right) {
+                Ok(Value::Void)
+            } else {
+                Err(RuntimeError::AssertionFailed(format!("{} != {}", shown(&left), shown(&right))))
+            }
+        }
+        ("assert_near", _) => {
+            let mut number = || args.next().map(|v| v.to_f64()).unwrap_or(0.0);
+            let (left, right, tolerance) = (number(), number(), number());
+            if tolerance < 0.0 {
+                return Err(RuntimeError::InvalidArgument(format!(
+                    "assert_near() tolerance must not be negative, got {}", tolerance
+                )));
+            }
+            if (left - right).abs() <= tolerance {
+                Ok(Value::Void)
+            } else {
+                Err(RuntimeError::AssertionFailed(format!("{} is not within {} of {}", left, tolerance, right)))
+            }
+        }
+        _ => Err(RuntimeError::UndefinedFunc(name.to_string())),
+    }
+}
+
+/// assert_eq() equality: strings by text, arrays by shape and current values,
+/// everything else by number (so 16 == 16.0 and 'a' == 97)
+fn same(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Array(a), Value::Array(b)) => {
+            a.dims == b.dims && a.elems.iter().zip(&b.elems).all(|(x, y)| same(&x.get(), &y.get()))
+        }
+        (Value::String(_) | Value::Array(_), _) | (_, Value::String(_) | Value::Array(_)) => false,
+        (a, b) => a.to_f64() == b.to_f64(),
+    }
+}
+
+/// A value in an assertion message; strings are quoted so "" and " " show
+fn shown(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("{:?}", s),
+        Value::Char(c) => format!("'{}'", c),
+        value => value.to_string(),
+    }
+}
+
+fn string(builtin: &str, value: Option<Value>, pos: usize) -> Result<String, RuntimeError> {
+    match value {
+        Some(Value::String(s)) => Ok(s),
+        _ => Err(RuntimeError::TypeMismatch(format!(
+            "{}() expects a string as argument {}", builtin, pos
+        ))),
+    }
+}
+
+fn array(builtin: &str, value: Option<Value>, pos: usize) -> Result<BlurArray, RuntimeError> {
+    match value {
+        Some(Value::Array(arr)) => Ok(arr),
+        _ => Err(RuntimeError::TypeMismatch(format!(
+            "{}() expects an array as argument {}", builtin, pos
+        ))),
+    }
+}
+
+/// len(arr[, dim]) - number of elements (len(grid, 1) is the second dimension),
+/// or the number of characters of a string
+fn len(value: Option<Value>, dim: usize) -> Result<Value, RuntimeError> {
+    match value {
+        Some(Value::Array(arr)) => match arr.dims.get(dim) {
+            Some(size) => Ok(Value::Int(*size as i64)),
+            None => Err(RuntimeError::TypeMismatch(format!(
+                "len(): array has no dimension {}", dim
+            ))),
+        },
+        Some(Value::String(s)) => Ok(Value::Int(s.chars().count() as i64)),
+        _ => Err(RuntimeError::TypeMismatch(
+            "len() expects an array or string".to_string(),
+        )),
+    }
+}
+
+/// smooth(arr, radius), convolve(arr, kernel), gaussian(arr, sigma) - run a spatial
+/// filter over the current values and push each result into the matching element's
+/// history, so temporal and spatial blur compose
+fn filter(builtin: &str, param: Value, arr: &mut BlurArray, limits: &ExecutionLimits) -> Result<Value, RuntimeError> {
+    let values: Vec<f64> = arr.elems.iter().map(|e| e.get().to_f64()).collect();
+    let results = match (builtin, param) {
+        ("convolve", Value::Array(kernel)) => {
+            let weights: Vec<f64> = kernel.elems.iter().map(|e| e.get().to_f64()).collect();
+            spatial::convolve(&values, &arr.dims, &weights, &kernel.dims)
+        }
+        ("convolve", _) => {
+            return Err(RuntimeError::TypeMismatch("convolve() expects an array kernel".to_string()));
+        }
+        ("smooth", radius) => {
+            let radius = radius.to_f64();
+            if radius < 0.0 {
+                return Err(RuntimeError::InvalidArgument(format!(
+                    "smooth() radius must not be negative, got {}", radius
+                )));
+            }
+            spatial::smooth(&values, &arr.dims, radius as usize)
+        }
+        (_, sigma) => {
+            let sigma = sigma.to_f64();
+            if sigma <= 0.0 {
+                return Err(RuntimeError::InvalidArgument(format!(
+                    "gaussian() sigma must be positive, got {}", sigma
+                )));
+            }
+            spatial::gaussian(&values, &arr.dims, sigma)
+        }
+    };
+    let results = results.ok_or_else(|| RuntimeError::TypeMismatch(format!(
+        "{}() expects a 1-D or 2-D array (and a kernel of the same shape)", builtin
+    )))?;
+    for elem in &arr.elems {
+        limits.check_history(elem, 1)?;
+    }
+    for (elem, result) in arr.elems.iter_mut().zip(results) {
+        elem.push_value(&Value::Float(result));
+    }
+    Ok(Value::Void)
+}
+
+/// load_image(path) - a [height][width] int array for grayscale images,
+/// [height][width][3] for color; every pixel starts with one history entry
+fn load_image(path: &str) -> Result<Value, RuntimeError> {
+    let img = image::load(path).map_err(|e| RuntimeError::Io(format!("{}: {}", path, e)))?;
+    let mut dims = vec![img.height, img.width];
+    if img.channels > 1 {
+        dims.push(img.channels);
+    }
+    let mut arr = BlurArray::new(Type::Int, dims);
+    for (elem, sample) in arr.elems.iter_mut().zip(&img.samples) {
+        elem.push(*sample as f64);
+    }
+    Ok(Value::Array(arr))
+}
+
+/// save_image(arr, path[, plain]) - write the current blurred values as PGM/PPM
+fn save_image(arr: &BlurArray, path: &str, plain: bool) -> Result<Value, RuntimeError> {
+    let (height, width, channels) = match arr.dims[..] {
+        [height, width] => (height, width, 1),
+        [height, width, 3] => (height, width, 3),
+        _ => return Err(RuntimeError::TypeMismatch(
+            "save_image() expects a [height][width] or [height][width][3] array".to_string(),
+        )),
+    };
+    let values: Vec<f64> = arr.elems.iter().map(|e| e.get().to_f64().round().max(0.0)).collect();
+    let maxval = if values.iter().any(|&v| v > 255.0) { 65535 } else { 255 };
+    let img = Image {
+        width,
+        height,
+        channels,
+        maxval,
+        samples: values.iter().map(|&v| v.min(maxval as f64) as u32).collect(),
+    };
+    image::save(path, &img, plain).map_err(|e| RuntimeError::Io(format!("{}: {}", path, e)))?;
+    Ok(Value::Void)
+}
+
+/// load_wav(path) - a float array of mono samples in [-1, 1], and the sample rate
+fn load_wav(path: &str) -> Result<(Value, f64), RuntimeError> {
+    let audio = wav::load(path).map_err(|e| RuntimeError::Io(format!("{}: {}", path, e)))?;
+    let mut arr = BlurArray::new(Type::Float, vec![audio.samples.len()]);
+    for (elem, sample) in arr.elems.iter_mut().zip(&audio.samples) {
+        elem.push(*sample);
+    }
+    Ok((Value::Array(arr), audio.sample_rate as f64))
+}
+
+/// save_wav(path, arr, rate) - write the current values as 16-bit mono PCM
+fn save_wav(path: &str, arr: &BlurArray, sample_rate: f64) -> Result<Value, RuntimeError> {
+    if arr.dims.len() != 1 {
+        return Err(RuntimeError::TypeMismatch(
+            "save_wav() expects a one-dimensional array of samples".to_string(),
+        ));
+    }
+    if sample_rate < 1.0 || sample_rate > u32::MAX as f64 {
+        return Err(RuntimeError::InvalidArgument(format!(
+            "save_wav() sample rate must be positive, got {}", sample_rate
+        )));
+    }
+    let audio = Wav {
+        sample_rate: sample_rate as u32,
+        samples: arr.elems.iter().map(|e| e.get().to_f64()).collect(),
+    };
+    wav::save(path, &audio).map_err(|e| RuntimeError::Io(format!("{}: {}", path, e)))?;
+    Ok(Value::Void)
+}
@@ -0,0 +1,374 @@
+// Static checks: what can be found wrong with a program without running it
+//
+// Walks a parsed program with the same scopes as the resolver, but reads
+// instead of rewriting and keeps going after an error. Variables are scoped
+// dynamically, so a name a function doesn't declare is only reported when no
+// function of the program declares it either. Besides the
+// diagnostics it records every declaration and which declaration each name
+// refers to, for editor features like go-to-definition and hover.
+
+use crate::ast::*;
+use crate::builtins::{self, Param};
+use crate::symbol::Symbol;
+use std::collections::HashMap;
+
+/// Functions every interpreter has besides the program's and the builtins
+const PREDEFINED: &[&str] = &["blurstr", "get_blur"];
+
+/// A problem found in the program
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DefKind {
+    Param,
+    Variable,
+    Array,
+}
+
+/// A declared variable, array or parameter
+#[derive(Debug, Clone)]
+pub struct Definition {
+    pub name: String,
+    /// Arrays have one `Type::Array` per dimension
+    pub ty: Type,
+    pub kind: DefKind,
+    /// Declared in a `sharp for` header: not averaged
+    pub sharp: bool,
+    pub span: Span,
+    /// The function it belongs to
+    pub function: String,
+}
+
+/// A use of a declared name
+#[derive(Debug, Clone, Copy)]
+pub struct Reference {
+    pub span: Span,
+    /// Index into `Analysis::definitions`
+    pub definition: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub definitions: Vec<Definition>,
+    pub references: Vec<Reference>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Analysis {
+    /// The definition declared or used at byte `offset`
+    pub fn definition_at(&self, offset: usize) -> Option<&Definition> {
+        let contains = |span: &Span| span.start <= offset && offset <= span.end;
+        self.definitions.iter().find(|d| contains(&d.span)).or_else(|| {
+            self.references.iter().find(|r| contains(&r.span)).map(|r| &self.definitions[r.definition])
+        })
+    }
+}
+
+/// Check a program
+pub fn check(program: &Program) -> Analysis {
+    let mut checker = Checker {
+        functions: program.functions.iter().map(|f| (f.name.as_str(), f)).collect(),
+        scopes: Vec::new(),
+        function: String::new(),
+        sharp: false,
+        span: Span::default(),
+        analysis: Analysis::default(),
+        dynamic: Vec::new(),
+    };
+    for func in &program.functions {
+        checker.function(func);
+    }
+    // Names some function declares may come from a caller at run time
+    let definitions = &checker.analysis.definitions;
+    let declared = |name: &str, array: Option<bool>| {
+        definitions.iter().any(|d| d.name == name && array.is_none_or(|array| (d.kind == DefKind::Array) == array))
+    };
+    let dynamic: Vec<usize> = checker
+        .dynamic
+        .iter()
+        .filter(|(_, name, array)| declared(name, *array))
+        .map(|(index, _, _)| *index)
+        .collect();
+    let mut index = 0;
+    checker.analysis.diagnostics.retain(|_| {
+        index += 1;
+        !dynamic.contains(&(index - 1))
+    });
+    checker.analysis
+}
+
+/// The names declared in one scope, as indices into the definitions
+#[derive(Default)]
+struct Names {
+    vars: HashMap<Symbol, usize>,
+    arrays: HashMap<Symbol, usize>,
+}
+
+struct Checker<'a> {
+    functions: HashMap<&'a str, &'a Function>,
+    scopes: Vec<Names>,
+    function: String,
+    /// Declaring the variables of a sharp for header
+    sharp: bool,
+    /// The statement being checked
+    span: Span,
+    analysis: Analysis,
+    /// Diagnostics for names the function doesn't declare (by index), with
+    /// the name and whether an array, a scalar or (`None`) either was wanted
+    dynamic: Vec<(usize, String, Option<bool>)>,
+}
+
+impl Checker<'_> {
+    fn error(&mut self, span: Span, message: String) {
+        self.analysis.diagnostics.push(Diagnostic { span, message });
+    }
+
+    fn function(&mut self, func: &Function) {
+        self.function = func.name.clone();
+        self.scopes = vec![Names::default()];
+        for (ty, param) in &func.params {
+            self.declare(param, ty.clone(), DefKind::Param);
+        }
+        self.stmts(&func.body);
+    }
+
+    fn declare(&mut self, ident: &Ident, ty: Type, kind: DefKind) {
+        let array = matches!(ty, Type::Array(_));
+        self.analysis.definitions.push(Definition {
+            name: ident.to_string(),
+            ty,
+            kind,
+            sharp: self.sharp,
+            span: ident.span,
+            function: self.function.clone(),
+        });
+        let index = self.analysis.definitions.len() - 1;
+        let scope = self.scopes.last_mut().unwrap();
+        let names = if array { &mut scope.arrays } else { &mut scope.vars };
+        names.insert(ident.name, index);
+    }
+
+    fn lookup(&self, name: Symbol, array: bool) -> Option<usize> {
+        self.scopes.iter().rev().find_map(|scope| if array { scope.arrays.get(&name) } else { scope.vars.get(&name) }.copied())
+    }
+
+    /// Record a use of `ident`: a scalar, an array, or (`None`) either
+    fn refer(&mut self, ident: &Ident, array: Option<bool>) -> Option<usize> {
+        let found = match array {
+            Some(array) => self.lookup(ident.name, array),
+            None => self.lookup(ident.name, false).or_else(|| self.lookup(ident.name, true)),
+        };
+        if found.is_none() {
+            self.dynamic.push((self.analysis.diagnostics.len(), ident.to_string(), array));
+        }
+        match (found, array) {
+            (Some(definition), _) => self.analysis.references.push(Reference { span: ident.span, definition }),
+            // The name exists, but as the other kind
+            (None, Some(true)) if self.lookup(ident.name, false).is_some() => {
+                self.error(ident.span, format!("Type mismatch: {} is not an array", ident));
+            }
+            (None, Some(false)) if self.lookup(ident.name, true).is_some() => {
+                self.error(ident.span, format!("Type mismatch: {} is an array; use an element like {}[0]", ident, ident));
+            }
+            (None, _) => self.error(ident.span, format!("Undefined variable: {}", ident)),
+        }
+        found
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        self.span = stmt.span;
+        match &stmt.kind {
+            StmtKind::VarDecl(ty, ident, init) => {
+                if let Some(init) = init {
+                    self.expr(init);
+                }
+                self.declare(ident, ty.clone(), DefKind::Variable);
+            }
+            StmtKind::ArrayDecl(ty, ident, dims, init) => {
+                self.exprs(dims.iter().flatten());
+                match init {
+                    Some(ArrayInit::List(values)) => self.exprs(values),
+                    Some(ArrayInit::Expr(expr)) => self.expr(expr),
+                    None => {}
+                }
+                let ty = dims.iter().fold(ty.clone(), |ty, _| Type::Array(Box::new(ty)));
+                self.declare(ident, ty, DefKind::Array);
+            }
+
+            StmtKind::Assign(ident, value) | StmtKind::CompoundAssign(ident, _, value) => {
+                self.expr(value);
+                self.refer(ident, Some(false));
+            }
+            StmtKind::ArrayAssign(ident, indices, value) | StmtKind::ArrayCompoundAssign(ident, indices, _, value) => {
+                self.exprs(indices);
+                self.expr(value);
+                self.refer(ident, Some(true));
+            }
+
+            StmtKind::PreIncrement(ident)
+            | StmtKind::PreDecrement(ident)
+            | StmtKind::PostIncrement(ident)
+            | StmtKind::PostDecrement(ident) => {
+                self.refer(ident, Some(false));
+            }
+            StmtKind::ArrayPreIncrement(ident, indices)
+            | StmtKind::ArrayPreDecrement(ident, indices)
+            | StmtKind::ArrayPostIncrement(ident, indices)
+            | StmtKind::ArrayPostDecrement(ident, indices) => {
+                self.exprs(indices);
+                self.refer(ident, Some(true));
+            }
+
+            StmtKind::If(cond, then_branch, else_branch) => {
+                self.expr(cond);
+                self.stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            StmtKind::While(cond, body) => {
+                self.expr(cond);
+                self.stmt(body);
+            }
+            StmtKind::For(init, cond, update, body) | StmtKind::SharpFor(init, cond, update, body) => {
+                self.scopes.push(Names::default());
+                if let Some(init) = init {
+                    self.sharp = matches!(stmt.kind, StmtKind::SharpFor(..));
+                    self.stmt(init);
+                    self.sharp = false;
+                }
+                if let Some(cond) = cond {
+                    self.expr(cond);
+                }
+                self.stmt(body);
+                if let Some(update) = update {
+                    self.stmt(update);
+                }
+                self.scopes.pop();
+            }
+            StmtKind::Block(stmts) => {
+                self.scopes.push(Names::default());
+                self.stmts(stmts);
+                self.scopes.pop();
+            }
+
+            StmtKind::Expr(expr) => self.expr(expr),
+            StmtKind::Print(exprs) => self.exprs(exprs),
+            StmtKind::Return(expr) => {
+                if let Some(expr) = expr {
+                    self.expr(expr);
+                }
+            }
+        }
+    }
+
+    fn exprs<'e>(&mut self, exprs: impl IntoIterator<Item = &'e Expr>) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::IntLit(_) | Expr::FloatLit(_) | Expr::BoolLit(_) | Expr::CharLit(_) | Expr::StringLit(_) => {}
+            Expr::Var(ident) => {
+                self.refer(ident, None);
+            }
+            Expr::ArrayAccess(ident, indices) => {
+                self.exprs(indices);
+                self.refer(ident, Some(true));
+            }
+            Expr::BinOp(left, _, right) | Expr::StringRepeat(left, right) => {
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::UnaryOp(_, operand) => self.expr(operand),
+            Expr::PreIncrement(ident)
+            | Expr::PreDecrement(ident)
+            | Expr::PostIncrement(ident)
+            | Expr::PostDecrement(ident) => {
+                self.refer(ident, Some(false));
+            }
+            Expr::ArrayPreIncrement(ident, indices)
+            | Expr::ArrayPreDecrement(ident, indices)
+            | Expr::ArrayPostIncrement(ident, indices)
+            | Expr::ArrayPostDecrement(ident, indices) => {
+                self.exprs(indices);
+                self.refer(ident, Some(true));
+            }
+            Expr::Call(name, args) => self.call(name, args),
+        }
+    }
+
+    /// Check a call the way the engines would when it runs
+    fn call(&mut self, name: &str, args: &[Expr]) {
+        let span = self.span;
+        if let Some(builtin) = builtins::lookup(name) {
+            if let Err(e) = builtin.check_args(args.len(), |i| matches!(args[i], Expr::Var(_))) {
+                self.error(span, e.to_string());
+            }
+            for (i, arg) in args.iter().enumerate() {
+                match (builtin.params.get(i), arg) {
+                    (Some(Param::Array), Expr::Var(ident)) => {
+                        self.refer(ident, Some(true));
+                    }
+                    (Some(Param::Var), Expr::Var(ident)) => {
+                        self.refer(ident, Some(false));
+                    }
+                    (_, arg) => self.expr(arg),
+                }
+            }
+            return;
+        }
+        self.exprs(args);
+        if PREDEFINED.contains(&name) {
+            return;
+        }
+        let Some(&func) = self.functions.get(name) else {
+            return self.error(span, format!("Undefined function: {}", name));
+        };
+        if args.len() != func.params.len() {
+            let plural = if func.params.len() == 1 { "" } else { "s" };
+            let message = format!("{}() takes {} argument{}, got {}", name, func.params.len(), plural, args.len());
+            return self.error(span, message);
+        }
+        for ((ty, param), arg) in func.params.iter().zip(args) {
+            let arg_rank = match arg {
+                Expr::Var(ident) => match self.lookup(ident.name, false) {
+                    Some(_) => Some(0),
+                    None => self.lookup(ident.name, true).map(|d| rank(&self.analysis.definitions[d].ty)),
+                },
+                Expr::Call(..) => None,
+                _ => Some(0),
+            };
+            let param_rank = rank(ty);
+            let message = match arg_rank {
+                Some(0) if param_rank > 0 => format!("parameter '{}' of {}() expects an array", param, name),
+                Some(r) if r > 0 && param_rank == 0 => format!("parameter '{}' of {}() does not take an array", param, name),
+                Some(r) if r != param_rank => {
+                    format!("parameter '{}' of {}() expects a {}-dimensional array, got {}", param, name, param_rank, r)
+                }
+                _ => continue,
+            };
+            self.error(span, message);
+        }
+    }
+}
+
+/// Number of array dimensions of a type
+fn rank(ty: &Type) -> usize {
+    match ty {
+        Type::Array(inner) => 1 + rank(inner),
+        _ => 0,
+    }
+}
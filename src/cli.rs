@@ -0,0 +1,292 @@
+// Command-line interface
+//
+// `blur <file>`, `blur -e <code>` and `blur -` run programs as they always
+// have; everything else is a subcommand with its own flags and --help. The
+// flags shared by everything that runs Blur code (blur factor, limits,
+// engine, tracing) are flattened into each subcommand that uses them, so a
+// flag a subcommand doesn't take is an error instead of being ignored.
+// Arguments after `--` are passed to the program.
+
+use crate::interpreter::{set_decay, set_raw_history, ExecutionLimits};
+use crate::VERSION;
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+
+const AFTER_HELP: &str = "\
+Blur is an esoteric language where every variable stores the average
+of all values it has ever been assigned. Welcome to regression to the mean!
+
+EXAMPLES:
+    blur program.blur -- one two     Run a program with two arguments
+    blur -e \"int x = 5; x++; x = 10; print(x);\"
+    echo \"int x = 5; print(x);\" | blur -
+
+    int money = 5;    // money = 5 (history: [5])
+    money++;          // money = 6 (history: [5, 6], avg = 5.5 -> ceil = 6)
+    money = 10;       // money = 7 (history: [5, 6, 10], avg = 7)
+
+FEATURES:
+    - Entry point: blur() function (not main!); int blur(string args[])
+      gets the arguments after --, and its int is the exit code (0-255;
+      65, 66, 70 and 73 are reserved for parse, input, runtime and output errors)
+    - Types: int (ceiling), float (exact), bool (ceiling of true ratio), char
+    - All C-style operators: +, -, *, /, %, ++, --, +=, -=, etc.
+    - Control flow: if/else, while, for, sharp for
+    - Functions with parameters (history travels!)
+    - Arrays with per-element history (len, push, pop)
+    - Built-in print() function
+
+ESCAPE HATCH:
+    sharp for (int i = 0; i < 10; i++) { ... }
+    The loop counter 'i' behaves normally (not averaged).
+
+WEBSITE:
+    https://esolangs.org/wiki/Blur";
+
+#[derive(Parser)]
+#[command(
+    name = "blur",
+    version = VERSION,
+    about = format!("blur - The Blur Programming Language Interpreter v{}", VERSION),
+    after_help = AFTER_HELP,
+    disable_version_flag = true,
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    /// Program to run ("-" reads it from stdin); with no program, start the REPL
+    #[arg(value_name = "FILE")]
+    pub file: Option<String>,
+
+    /// Execute code directly (statements, no blur() needed)
+    #[arg(short = 'e', value_name = "CODE", conflicts_with = "file")]
+    pub code: Option<String>,
+
+    /// Start the REPL (interactive mode)
+    #[arg(short = 'i', long = "repl", conflicts_with_all = ["file", "code"])]
+    pub repl: bool,
+
+    /// Print version information
+    #[arg(short = 'v', long = "version", action = ArgAction::Version)]
+    version: Option<bool>,
+
+    /// Arguments for the program
+    #[arg(last = true, value_name = "ARGS")]
+    pub args: Vec<String>,
+
+    #[command(flatten)]
+    pub run: RunFlags,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run a Blur program
+    Run {
+        /// Program to run ("-" reads it from stdin)
+        file: String,
+        /// Arguments for the program
+        #[arg(last = true, value_name = "ARGS")]
+        args: Vec<String>,
+        #[command(flatten)]
+        run: RunFlags,
+    },
+    /// Execute statements directly (no blur() needed)
+    Eval {
+        code: String,
+        /// Arguments for the program
+        #[arg(last = true, value_name = "ARGS")]
+        args: Vec<String>,
+        #[command(flatten)]
+        run: RunFlags,
+    },
+    /// Start the REPL (interactive mode)
+    Repl {
+        #[command(flatten)]
+        blur: BlurFlags,
+    },
+    /// Report parse errors, undefined names and wrong argument counts without running
+    Check {
+        #[arg(required = true)]
+        files: Vec<String>,
+    },
+    /// Run the test_* functions of .blur files, each in a fresh interpreter
+    Test {
+        /// Compare what blur() prints with the file's `// expect:` comments or
+        /// `/* expected-output: */` block instead
+        #[arg(long)]
+        golden: bool,
+        /// Rewrite the expectations to match the output
+        #[arg(long, requires = "golden")]
+        update: bool,
+        /// Files and directories to search (default: the current directory)
+        paths: Vec<String>,
+        /// Run golden files on the tree-walking interpreter
+        #[arg(long)]
+        tree_walk: bool,
+        #[command(flatten)]
+        blur: BlurFlags,
+        #[command(flatten)]
+        limits: LimitFlags,
+    },
+    /// Format programs in place (stdin to stdout if no files)
+    Fmt {
+        /// Change nothing; list the files that would change and fail if there are any
+        #[arg(long)]
+        check: bool,
+        files: Vec<String>,
+    },
+    /// Report code that runs but probably doesn't do what it says
+    Lint {
+        /// Don't report a rule ("all" names every rule)
+        #[arg(long, value_name = "RULE")]
+        allow: Vec<String>,
+        /// Report a rule as a warning
+        #[arg(long, value_name = "RULE")]
+        warn: Vec<String>,
+        /// Report a rule as an error that fails the run
+        #[arg(long, value_name = "RULE")]
+        deny: Vec<String>,
+        /// List the rules
+        #[arg(long, exclusive = true)]
+        rules: bool,
+        #[arg(required = true)]
+        files: Vec<String>,
+    },
+    /// Count the iterations of simple blurred for loops, and report those over the max-loop limit
+    Analyze {
+        #[arg(required = true)]
+        files: Vec<String>,
+        #[command(flatten)]
+        blur: BlurFlags,
+        #[command(flatten)]
+        limits: LimitFlags,
+    },
+    /// Run the Blur snippets of Markdown and MediaWiki documents, checking the values their comments give
+    Doctest {
+        #[arg(required = true)]
+        files: Vec<String>,
+        #[command(flatten)]
+        blur: BlurFlags,
+        #[command(flatten)]
+        limits: LimitFlags,
+    },
+    /// Run a program in the debugger (type help there)
+    Debug {
+        file: String,
+        #[command(flatten)]
+        blur: BlurFlags,
+        #[command(flatten)]
+        limits: LimitFlags,
+    },
+    /// Serve the Language Server Protocol on stdin/stdout
+    Lsp,
+    /// Serve the Debug Adapter Protocol on stdin/stdout
+    Dap {
+        #[command(flatten)]
+        blur: BlurFlags,
+        #[command(flatten)]
+        limits: LimitFlags,
+    },
+}
+
+/// How values are averaged
+#[derive(Args, Clone)]
+pub struct BlurFlags {
+    /// Blur factor for weighted averaging: 1.0 = pure average, 0.9 = slight
+    /// recency bias (default), 0.5 = strong recency bias, 0.0 = only the most
+    /// recent value
+    #[arg(long, value_name = "0.0-1.0")]
+    pub blur: Option<f64>,
+
+    /// Recent history entries always kept as raw values; older ones are folded
+    /// into a summary once their weight underflows to zero (default: 64,
+    /// 0 = keep all)
+    #[arg(long, value_name = "N")]
+    pub raw_history: Option<usize>,
+}
+
+impl BlurFlags {
+    /// Make these the settings of the running thread
+    pub fn apply(&self) {
+        if let Some(decay) = self.blur {
+            set_decay(decay);
+        }
+        if let Some(entries) = self.raw_history {
+            set_raw_history(entries);
+        }
+    }
+}
+
+/// Execution limits (0 = unlimited; also settable with directives like #fuel 5000).
+/// Their heading carries over to the arguments after them, so flatten them last.
+#[derive(Args, Clone)]
+#[command(next_help_heading = "Limits")]
+pub struct LimitFlags {
+    /// Iterations per for loop [default: 1000]
+    #[arg(long, value_name = "N")]
+    pub max_loop: Option<u64>,
+    /// Iterations per while or sharp for loop [default: unlimited]
+    #[arg(long, value_name = "N")]
+    pub max_iterations: Option<u64>,
+    /// Statements executed in total [default: unlimited]
+    #[arg(long, value_name = "N")]
+    pub fuel: Option<u64>,
+    /// Nested function calls [default: 1000]
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<u64>,
+    /// History entries per variable [default: unlimited]
+    #[arg(long, value_name = "N")]
+    pub max_history: Option<u64>,
+    /// Elements per declared array [default: unlimited]
+    #[arg(long, value_name = "N")]
+    pub max_array: Option<u64>,
+}
+
+impl LimitFlags {
+    pub fn limits(&self) -> ExecutionLimits {
+        let mut limits = ExecutionLimits::default();
+        let values = [self.max_loop, self.max_iterations, self.fuel, self.max_depth, self.max_history, self.max_array];
+        for (option, value) in ExecutionLimits::OPTIONS.iter().zip(values) {
+            if let Some(value) = value {
+                // Every option is known and every u64 is valid
+                let _ = limits.set(option, &value.to_string());
+            }
+        }
+        limits
+    }
+}
+
+/// Everything about how a program runs
+#[derive(Args, Clone)]
+pub struct RunFlags {
+    /// Run on the tree-walking interpreter instead of the bytecode VM (for
+    /// comparing the two engines)
+    #[arg(long)]
+    pub tree_walk: bool,
+
+    /// Log every history push, call, return and loop iteration to FILE, one
+    /// JSON object per line
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<String>,
+
+    /// Print the tokens, one per line, instead of running
+    #[arg(long, conflicts_with = "dump_ast")]
+    pub dump_tokens: bool,
+
+    /// Print the syntax tree (as Rust Debug output, or JSON) instead of running
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, require_equals = true, default_missing_value = "debug")]
+    pub dump_ast: Option<AstFormat>,
+
+    #[command(flatten)]
+    pub blur: BlurFlags,
+
+    #[command(flatten)]
+    pub limits: LimitFlags,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq)]
+pub enum AstFormat {
+    Debug,
+    Json,
+}
@@ -0,0 +1,717 @@
+// Bytecode compiler: lowers a resolved ast::Program to a flat instruction list
+// per function, mapping every (depth, slot) address to a slot of the frame.
+//
+// Scopes that are not active at the same time share frame slots. Names the
+// function doesn't declare compile to `Local::DYNAMIC`, which the VM looks up
+// by name in the declarations live in its frames, the callers' included.
+
+use crate::ast::*;
+use crate::builtins::{self, Param};
+use crate::host::HostFunction;
+use crate::interpreter::{RuntimeError, Value};
+use crate::symbol::Symbol;
+use crate::trace::LoopKind;
+use std::collections::HashMap;
+
+/// A local slot together with the name it was declared as (for error
+/// messages) and how many scopes out it was declared (for the trace)
+#[derive(Debug, Clone, Copy)]
+pub struct Local {
+    pub slot: u32,
+    pub name: Symbol,
+    pub depth: Option<u32>,
+}
+
+impl Local {
+    /// The slot of a name the function doesn't declare (`Addr::Dynamic`)
+    pub const DYNAMIC: u32 = u32::MAX;
+
+    pub fn is_dynamic(self) -> bool {
+        self.slot == Self::DYNAMIC
+    }
+}
+
+/// A declaration and the instructions it is visible from: `from` (the
+/// declaring instruction) up to `to`, the end of its scope
+#[derive(Debug, Clone, Copy)]
+pub struct Declared {
+    pub local: Local,
+    pub from: u32,
+    pub to: u32,
+}
+
+/// What an increment/decrement leaves on the stack
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IncResult {
+    None,
+    New,
+    Old,
+}
+
+/// How a declared variable gets its first value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Init {
+    None,
+    /// Pop a value
+    Value,
+    /// Pop a count and push the string constant that many times
+    Repeat(u32),
+}
+
+/// The variable a builtin works on in place
+#[derive(Debug, Clone, Copy)]
+pub enum TargetRef {
+    None,
+    Array(Local),
+    Var(Local),
+    /// The variable doesn't exist: raise UndefinedVar
+    Undefined(Symbol),
+}
+
+#[derive(Debug, Clone)]
+pub enum Op {
+    // Stack values
+    Const(u32),
+    Pop,
+    BinOp(BinOp),
+    Neg,
+    Not,
+    Repeat,
+
+    // Scalar variables
+    LoadVar(Local),
+    DeclVar { local: Local, ty: Type, init: Init, sharp: bool },
+    StoreVar(Local),
+    StoreVarRepeat { local: Local, text: u32 },
+    CompoundVar(Local, CompoundOp),
+    IncVar { local: Local, delta: f64, result: IncResult },
+
+    // Arrays: `dims` and `indices` count the values popped from the stack
+    LoadArray(Local),
+    ArraySize,
+    NewArray { ty: Type, dims: u32, list_len: Option<u32> },
+    JumpIfFull { index: u32, target: u32 },
+    /// Push a value into element `index` of the array being built (named `name`)
+    InitElem { index: u32, name: Symbol },
+    CopyArray { name: Symbol, dims: u32 },
+    StoreArray(Local),
+    LoadElem(Local, u32),
+    StoreElem(Local, u32),
+    CompoundElem(Local, u32, CompoundOp),
+    IncElem { local: Local, indices: u32, delta: f64, result: IncResult },
+
+    // Control flow
+    /// Count a statement against the fuel limit; the span is its location
+    Step(Span),
+    Jump(u32),
+    JumpIfFalse(u32),
+    /// Forget the locals of a scope that is (re)entered
+    EnterScope { start: u32, end: u32 },
+    LoopStart(u32),
+    /// Count an iteration of a loop, failing past the limit for its kind
+    LoopGuard { counter: u32, kind: LoopKind },
+    /// The loop at `span` is about to run its body (recorded in the trace)
+    Iteration { counter: u32, kind: LoopKind, span: Span },
+    Print(u32),
+    Return,
+    ReturnVoid,
+
+    // Calls: arguments go to a separate stack so they keep their history
+    ArgVar(Local),
+    ArgArray(Local),
+    ArgElem(Local, u32),
+    ArgValue,
+    Call { func: u32, args: u32 },
+    Builtin { index: u32, args: u32, target: TargetRef },
+    /// Call a host function; `as_arg` passes the result, history and all, to
+    /// the argument stack instead of pushing its value
+    Host { index: u32, args: u32, as_arg: bool },
+    BlurStr(u32),
+    Fail(u32),
+}
+
+pub struct CompiledFunction {
+    pub name: String,
+    pub params: Vec<(Type, Ident)>,
+    pub param_slots: Vec<u32>,
+    /// Every declaration, in the order they compiled, for dynamic lookups
+    pub declared: Vec<Declared>,
+    pub slots: usize,
+    pub loops: usize,
+    pub code: Vec<Op>,
+}
+
+pub struct CompiledProgram {
+    pub functions: Vec<CompiledFunction>,
+    pub entry: Option<u32>,
+    pub constants: Vec<Value>,
+    pub errors: Vec<RuntimeError>,
+}
+
+/// Compile every function; calls resolve to the last definition of a name.
+/// `hosts` are the host functions the program may call, by index.
+pub fn compile(program: &Program, hosts: &[HostFunction]) -> CompiledProgram {
+    let mut index = HashMap::new();
+    for (i, func) in program.functions.iter().enumerate() {
+        index.insert(func.name.clone(), i as u32);
+    }
+    let mut compiler = Compiler {
+        index,
+        hosts: hosts.iter().enumerate().map(|(i, h)| (h.name.clone(), i as u32)).collect(),
+        constants: Vec::new(),
+        errors: Vec::new(),
+    };
+    let functions = program.functions.iter().map(|f| compiler.function(f)).collect();
+    CompiledProgram {
+        functions,
+        entry: compiler.index.get("blur").copied(),
+        constants: compiler.constants,
+        errors: compiler.errors,
+    }
+}
+
+struct Compiler {
+    index: HashMap<String, u32>,
+    hosts: HashMap<String, u32>,
+    constants: Vec<Value>,
+    errors: Vec<RuntimeError>,
+}
+
+/// Frame slots of a scope's variables and arrays, by resolver slot
+#[derive(Default)]
+struct Scope {
+    vars: Vec<u32>,
+    arrays: Vec<u32>,
+    start: u32,
+    /// Index of its first entry in `FnState::declared`
+    declared: usize,
+}
+
+/// Per-function compilation state
+struct FnState {
+    code: Vec<Op>,
+    scopes: Vec<Scope>,
+    declared: Vec<Declared>,
+    next_slot: u32,
+    max_slots: u32,
+    loops: u32,
+}
+
+impl Compiler {
+    fn constant(&mut self, value: Value) -> u32 {
+        self.constants.push(value);
+        (self.constants.len() - 1) as u32
+    }
+
+    fn fail(&mut self, f: &mut FnState, error: RuntimeError) {
+        self.errors.push(error);
+        f.code.push(Op::Fail((self.errors.len() - 1) as u32));
+    }
+
+    fn function(&mut self, func: &Function) -> CompiledFunction {
+        let mut f = FnState {
+            code: Vec::new(),
+            scopes: vec![Scope::default()],
+            declared: Vec::new(),
+            next_slot: 0,
+            max_slots: 0,
+            loops: 0,
+        };
+        let param_slots = func
+            .params
+            .iter()
+            .map(|(_, param)| self.declare(&mut f, param).slot)
+            .collect();
+        for stmt in &func.body {
+            self.stmt(&mut f, stmt);
+        }
+        f.code.push(Op::ReturnVoid);
+        Self::end_declarations(&mut f, 0);
+        CompiledFunction {
+            name: func.name.clone(),
+            params: func.params.clone(),
+            param_slots,
+            declared: f.declared,
+            slots: f.max_slots as usize,
+            loops: f.loops as usize,
+            code: f.code,
+        }
+    }
+
+    // --- Slots ---
+
+    /// Give a declared name a frame slot. The resolver numbers slots per scope
+    /// in declaration order, which is also the order declarations compile in.
+    fn declare(&mut self, f: &mut FnState, ident: &Ident) -> Local {
+        let scope = f.scopes.last_mut().unwrap();
+        let (slots, slot) = match ident.addr {
+            Addr::Array { slot, .. } => (&mut scope.arrays, slot),
+            Addr::Var { slot, .. } => (&mut scope.vars, slot),
+            // Not resolved: a fresh slot that nothing refers to
+            Addr::Dynamic => {
+                let slot = scope.vars.len() as u32;
+                (&mut scope.vars, slot)
+            }
+        };
+        while slots.len() <= slot as usize {
+            slots.push(f.next_slot);
+            f.next_slot += 1;
+        }
+        f.max_slots = f.max_slots.max(f.next_slot);
+        let local = Local { slot: slots[slot as usize], name: ident.name, depth: Some(0) };
+        f.declared.push(Declared { local, from: Self::here(f), to: u32::MAX });
+        local
+    }
+
+    /// End the visibility of the declarations from `first` on that are still open
+    fn end_declarations(f: &mut FnState, first: usize) {
+        let end = Self::here(f);
+        for declared in &mut f.declared[first..] {
+            declared.to = declared.to.min(end);
+        }
+    }
+
+    /// The frame slot of a resolved scalar (array = false) or array
+    fn resolve(&self, f: &FnState, ident: &Ident, array: bool) -> Option<Local> {
+        let (depth, slot) = match (ident.addr, array) {
+            (Addr::Var { depth, slot }, false) | (Addr::Array { depth, slot }, true) => (depth, slot),
+            (Addr::Dynamic, _) => return Some(Local { slot: Local::DYNAMIC, name: ident.name, depth: None }),
+            _ => return None,
+        };
+        let scope = &f.scopes[f.scopes.len().checked_sub(depth as usize + 1)?];
+        let slots = if array { &scope.arrays } else { &scope.vars };
+        Some(Local { slot: *slots.get(slot as usize)?, name: ident.name, depth: Some(depth) })
+    }
+
+    fn resolve_var(&self, f: &FnState, ident: &Ident) -> Option<Local> {
+        self.resolve(f, ident, false)
+    }
+
+    fn resolve_array(&self, f: &FnState, ident: &Ident) -> Option<Local> {
+        self.resolve(f, ident, true)
+    }
+
+    fn undefined(&mut self, f: &mut FnState, ident: &Ident) {
+        self.fail(f, RuntimeError::UndefinedVar(ident.to_string()));
+    }
+
+    /// Emit `op` for a resolved variable, or raise UndefinedVar
+    fn with_var(&mut self, f: &mut FnState, ident: &Ident, op: impl FnOnce(Local) -> Op) {
+        match self.resolve_var(f, ident) {
+            Some(local) => f.code.push(op(local)),
+            None => self.undefined(f, ident),
+        }
+    }
+
+    fn with_array(&mut self, f: &mut FnState, ident: &Ident, op: impl FnOnce(Local) -> Op) {
+        match self.resolve_array(f, ident) {
+            Some(local) => f.code.push(op(local)),
+            None => self.undefined(f, ident),
+        }
+    }
+
+    fn push_scope(&mut self, f: &mut FnState) -> usize {
+        f.scopes.push(Scope { start: f.next_slot, declared: f.declared.len(), ..Scope::default() });
+        f.code.push(Op::EnterScope { start: f.next_slot, end: f.next_slot });
+        f.code.len() - 1
+    }
+
+    fn pop_scope(&mut self, f: &mut FnState, enter: usize) {
+        let scope = f.scopes.pop().unwrap();
+        Self::end_declarations(f, scope.declared);
+        // Clear every slot the scope (or a nested one) may have used
+        f.code[enter] = Op::EnterScope { start: scope.start, end: f.max_slots };
+        f.next_slot = scope.start;
+    }
+
+    fn here(f: &FnState) -> u32 {
+        f.code.len() as u32
+    }
+
+    fn patch(f: &mut FnState, at: usize, target: u32) {
+        match &mut f.code[at] {
+            Op::Jump(t) | Op::JumpIfFalse(t) => *t = target,
+            Op::JumpIfFull { target: t, .. } => *t = target,
+            _ => {}
+        }
+    }
+
+    // --- Statements ---
+
+    fn stmt(&mut self, f: &mut FnState, stmt: &Stmt) {
+        f.code.push(Op::Step(stmt.span));
+        match &stmt.kind {
+            StmtKind::VarDecl(ty, name, init) => self.var_decl(f, ty, name, init.as_ref(), false),
+
+            StmtKind::ArrayDecl(ty, name, dims, init) => {
+                for dim in dims {
+                    match dim {
+                        Some(expr) => {
+                            self.expr(f, expr);
+                            f.code.push(Op::ArraySize);
+                        }
+                        None => {
+                            let void = self.constant(Value::Void);
+                            f.code.push(Op::Const(void));
+                        }
+                    }
+                }
+                let dims = dims.len() as u32;
+                match init {
+                    Some(ArrayInit::List(values)) => {
+                        f.code.push(Op::NewArray { ty: ty.clone(), dims, list_len: Some(values.len() as u32) });
+                        // Values beyond the array's size are not evaluated
+                        for (i, value) in values.iter().enumerate() {
+                            let guard = f.code.len();
+                            f.code.push(Op::JumpIfFull { index: i as u32, target: 0 });
+                            self.expr(f, value);
+                            f.code.push(Op::InitElem { index: i as u32, name: name.name });
+                            let after = Self::here(f);
+                            Self::patch(f, guard, after);
+                        }
+                    }
+                    Some(ArrayInit::Expr(expr)) => {
+                        self.expr(f, expr);
+                        f.code.push(Op::CopyArray { name: name.name, dims });
+                    }
+                    None => f.code.push(Op::NewArray { ty: ty.clone(), dims, list_len: None }),
+                }
+                let local = self.declare(f, name);
+                f.code.push(Op::StoreArray(local));
+            }
+
+            StmtKind::Assign(name, expr) => {
+                if let Expr::StringRepeat(str_expr, count_expr) = expr {
+                    if let Expr::StringLit(s) = str_expr.as_ref() {
+                        self.expr(f, count_expr);
+                        let text = self.constant(Value::String(s.clone()));
+                        self.with_var(f, name, |local| Op::StoreVarRepeat { local, text });
+                        return;
+                    }
+                }
+                self.expr(f, expr);
+                self.with_var(f, name, Op::StoreVar);
+            }
+
+            StmtKind::ArrayAssign(name, indices, value) => {
+                self.exprs(f, indices);
+                self.expr(f, value);
+                let n = indices.len() as u32;
+                self.with_array(f, name, |local| Op::StoreElem(local, n));
+            }
+
+            StmtKind::CompoundAssign(name, op, expr) => {
+                self.expr(f, expr);
+                let op = *op;
+                self.with_var(f, name, |local| Op::CompoundVar(local, op));
+            }
+
+            StmtKind::ArrayCompoundAssign(name, indices, op, value) => {
+                self.exprs(f, indices);
+                self.expr(f, value);
+                let (n, op) = (indices.len() as u32, *op);
+                self.with_array(f, name, |local| Op::CompoundElem(local, n, op));
+            }
+
+            StmtKind::PreIncrement(name) | StmtKind::PostIncrement(name) => {
+                self.with_var(f, name, |local| Op::IncVar { local, delta: 1.0, result: IncResult::None });
+            }
+            StmtKind::PreDecrement(name) | StmtKind::PostDecrement(name) => {
+                self.with_var(f, name, |local| Op::IncVar { local, delta: -1.0, result: IncResult::None });
+            }
+            StmtKind::ArrayPreIncrement(name, indices) | StmtKind::ArrayPostIncrement(name, indices) => {
+                self.inc_elem(f, name, indices, 1.0, IncResult::None);
+            }
+            StmtKind::ArrayPreDecrement(name, indices) | StmtKind::ArrayPostDecrement(name, indices) => {
+                self.inc_elem(f, name, indices, -1.0, IncResult::None);
+            }
+
+            StmtKind::If(cond, then_branch, else_branch) => {
+                self.expr(f, cond);
+                let jump_else = f.code.len();
+                f.code.push(Op::JumpIfFalse(0));
+                self.stmt(f, then_branch);
+                match else_branch {
+                    Some(else_branch) => {
+                        let jump_end = f.code.len();
+                        f.code.push(Op::Jump(0));
+                        let else_start = Self::here(f);
+                        Self::patch(f, jump_else, else_start);
+                        self.stmt(f, else_branch);
+                        let end = Self::here(f);
+                        Self::patch(f, jump_end, end);
+                    }
+                    None => {
+                        let end = Self::here(f);
+                        Self::patch(f, jump_else, end);
+                    }
+                }
+            }
+
+            StmtKind::While(cond, body) => {
+                let counter = f.loops;
+                f.loops += 1;
+                f.code.push(Op::LoopStart(counter));
+                let start = Self::here(f);
+                f.code.push(Op::LoopGuard { counter, kind: LoopKind::While });
+                self.expr(f, cond);
+                let exit = f.code.len();
+                f.code.push(Op::JumpIfFalse(0));
+                f.code.push(Op::Iteration { counter, kind: LoopKind::While, span: stmt.span });
+                self.stmt(f, body);
+                f.code.push(Op::Jump(start));
+                let end = Self::here(f);
+                Self::patch(f, exit, end);
+            }
+
+            StmtKind::For(..) | StmtKind::SharpFor(..) => self.for_loop(f, stmt),
+
+            StmtKind::Block(stmts) => {
+                let enter = self.push_scope(f);
+                for stmt in stmts {
+                    self.stmt(f, stmt);
+                }
+                self.pop_scope(f, enter);
+            }
+
+            StmtKind::Expr(expr) => {
+                self.expr(f, expr);
+                f.code.push(Op::Pop);
+            }
+
+            StmtKind::Print(exprs) => {
+                self.exprs(f, exprs);
+                f.code.push(Op::Print(exprs.len() as u32));
+            }
+
+            StmtKind::Return(Some(expr)) => {
+                self.expr(f, expr);
+                f.code.push(Op::Return);
+            }
+            StmtKind::Return(None) => f.code.push(Op::ReturnVoid),
+        }
+    }
+
+    fn var_decl(&mut self, f: &mut FnState, ty: &Type, name: &Ident, init: Option<&Expr>, sharp: bool) {
+        let init = match init {
+            // "str" * n pushes the string n times (sharp declarations just evaluate it)
+            Some(Expr::StringRepeat(str_expr, count_expr)) if !sharp => match str_expr.as_ref() {
+                Expr::StringLit(s) => {
+                    self.expr(f, count_expr);
+                    Init::Repeat(self.constant(Value::String(s.clone())))
+                }
+                _ => Init::None,
+            },
+            Some(expr) => {
+                self.expr(f, expr);
+                Init::Value
+            }
+            None => Init::None,
+        };
+        let local = self.declare(f, name);
+        f.code.push(Op::DeclVar { local, ty: ty.clone(), init, sharp });
+    }
+
+    /// A for or sharp for statement
+    fn for_loop(&mut self, f: &mut FnState, stmt: &Stmt) {
+        let (StmtKind::For(init, cond, update, body) | StmtKind::SharpFor(init, cond, update, body)) = &stmt.kind else {
+            return;
+        };
+        let sharp = matches!(stmt.kind, StmtKind::SharpFor(..));
+        let enter = self.push_scope(f);
+        match init.as_deref() {
+            // Sharp for: the loop variable is not averaged
+            Some(Stmt { kind: StmtKind::VarDecl(ty, name, init), .. }) if sharp => self.var_decl(f, ty, name, init.as_ref(), true),
+            Some(stmt) => self.stmt(f, stmt),
+            None => {}
+        }
+
+        let counter = f.loops;
+        let mut exits = Vec::new();
+        f.loops += 1;
+        f.code.push(Op::LoopStart(counter));
+        let start = Self::here(f);
+        let kind = if sharp { LoopKind::SharpFor } else { LoopKind::For };
+        f.code.push(Op::LoopGuard { counter, kind });
+        if let Some(cond) = cond {
+            self.expr(f, cond);
+            exits.push(f.code.len());
+            f.code.push(Op::JumpIfFalse(0));
+        }
+        f.code.push(Op::Iteration { counter, kind, span: stmt.span });
+        self.stmt(f, body);
+        if let Some(update) = update {
+            self.stmt(f, update);
+        }
+        f.code.push(Op::Jump(start));
+        let end = Self::here(f);
+        for exit in exits {
+            Self::patch(f, exit, end);
+        }
+        self.pop_scope(f, enter);
+    }
+
+    fn inc_elem(&mut self, f: &mut FnState, name: &Ident, indices: &[Expr], delta: f64, result: IncResult) {
+        self.exprs(f, indices);
+        let n = indices.len() as u32;
+        self.with_array(f, name, |local| Op::IncElem { local, indices: n, delta, result });
+    }
+
+    // --- Expressions ---
+
+    fn exprs(&mut self, f: &mut FnState, exprs: &[Expr]) {
+        for expr in exprs {
+            self.expr(f, expr);
+        }
+    }
+
+    fn expr(&mut self, f: &mut FnState, expr: &Expr) {
+        match expr {
+            Expr::IntLit(n) => self.push_const(f, Value::Int(*n)),
+            Expr::FloatLit(x) => self.push_const(f, Value::Float(*x)),
+            Expr::BoolLit(b) => self.push_const(f, Value::Bool(*b)),
+            Expr::CharLit(c) => self.push_const(f, Value::Char(*c)),
+            Expr::StringLit(s) => self.push_const(f, Value::String(s.clone())),
+
+            Expr::StringRepeat(str_expr, count_expr) => {
+                self.expr(f, str_expr);
+                self.expr(f, count_expr);
+                f.code.push(Op::Repeat);
+            }
+
+            Expr::Var(name) if matches!(name.addr, Addr::Array { .. }) => self.with_array(f, name, Op::LoadArray),
+            Expr::Var(name) => self.with_var(f, name, Op::LoadVar),
+
+            Expr::ArrayAccess(name, indices) => {
+                self.exprs(f, indices);
+                let n = indices.len() as u32;
+                self.with_array(f, name, |local| Op::LoadElem(local, n));
+            }
+
+            Expr::BinOp(left, op, right) => {
+                self.expr(f, left);
+                self.expr(f, right);
+                f.code.push(Op::BinOp(*op));
+            }
+
+            Expr::UnaryOp(op, operand) => {
+                self.expr(f, operand);
+                f.code.push(match op {
+                    UnaryOp::Neg => Op::Neg,
+                    UnaryOp::Not => Op::Not,
+                });
+            }
+
+            Expr::PreIncrement(name) => self.inc_var(f, name, 1.0, IncResult::New),
+            Expr::PreDecrement(name) => self.inc_var(f, name, -1.0, IncResult::New),
+            Expr::PostIncrement(name) => self.inc_var(f, name, 1.0, IncResult::Old),
+            Expr::PostDecrement(name) => self.inc_var(f, name, -1.0, IncResult::Old),
+            Expr::ArrayPreIncrement(name, indices) => self.inc_elem(f, name, indices, 1.0, IncResult::New),
+            Expr::ArrayPreDecrement(name, indices) => self.inc_elem(f, name, indices, -1.0, IncResult::New),
+            Expr::ArrayPostIncrement(name, indices) => self.inc_elem(f, name, indices, 1.0, IncResult::Old),
+            Expr::ArrayPostDecrement(name, indices) => self.inc_elem(f, name, indices, -1.0, IncResult::Old),
+
+            Expr::Call(name, args) => self.call(f, name, args),
+        }
+    }
+
+    fn push_const(&mut self, f: &mut FnState, value: Value) {
+        let index = self.constant(value);
+        f.code.push(Op::Const(index));
+    }
+
+    fn inc_var(&mut self, f: &mut FnState, name: &Ident, delta: f64, result: IncResult) {
+        self.with_var(f, name, |local| Op::IncVar { local, delta, result });
+    }
+
+    fn call(&mut self, f: &mut FnState, name: &str, args: &[Expr]) {
+        // blurstr(): each argument is a (string, times) pair
+        if name == "blurstr" {
+            for arg in args {
+                match arg {
+                    Expr::StringRepeat(str_expr, count_expr) if matches!(str_expr.as_ref(), Expr::StringLit(_)) => {
+                        self.expr(f, str_expr);
+                        self.expr(f, count_expr);
+                    }
+                    _ => {
+                        self.expr(f, arg);
+                        self.push_const(f, Value::Int(1));
+                    }
+                }
+            }
+            f.code.push(Op::BlurStr(args.len() as u32));
+            return;
+        }
+
+        if let Some(builtin) = builtins::lookup(name) {
+            if let Err(e) = builtin.check_args(args.len(), |i| matches!(args[i], Expr::Var(_))) {
+                self.fail(f, e);
+                return;
+            }
+            let mut count = 0;
+            let mut target = TargetRef::None;
+            for (param, arg) in builtin.params.iter().zip(args) {
+                match (param, arg) {
+                    (Param::Value, _) => {
+                        self.expr(f, arg);
+                        count += 1;
+                    }
+                    (Param::Array, Expr::Var(var)) => {
+                        target = match self.resolve_array(f, var) {
+                            Some(local) => TargetRef::Array(local),
+                            None => TargetRef::Undefined(var.name),
+                        };
+                    }
+                    (_, Expr::Var(var)) => {
+                        target = match self.resolve_var(f, var) {
+                            Some(local) => TargetRef::Var(local),
+                            None => TargetRef::Undefined(var.name),
+                        };
+                    }
+                    _ => {}
+                }
+            }
+            let index = builtins::BUILTINS.iter().position(|b| b.name == name).unwrap() as u32;
+            f.code.push(Op::Builtin { index, args: count, target });
+            return;
+        }
+
+        if let Some(&index) = self.hosts.get(name) {
+            self.call_args(f, args);
+            f.code.push(Op::Host { index, args: args.len() as u32, as_arg: false });
+            return;
+        }
+
+        // User function: arguments keep their history
+        self.call_args(f, args);
+        match self.index.get(name) {
+            Some(&func) => f.code.push(Op::Call { func, args: args.len() as u32 }),
+            None => self.fail(f, RuntimeError::UndefinedFunc(name.to_string())),
+        }
+    }
+
+    /// Push the arguments of a user or host function call to the argument stack
+    fn call_args(&mut self, f: &mut FnState, args: &[Expr]) {
+        for arg in args {
+            match arg {
+                Expr::Var(var) if matches!(var.addr, Addr::Array { .. }) => self.with_array(f, var, Op::ArgArray),
+                Expr::Var(var) => self.with_var(f, var, Op::ArgVar),
+                Expr::ArrayAccess(var, indices) => {
+                    self.exprs(f, indices);
+                    let n = indices.len() as u32;
+                    self.with_array(f, var, |local| Op::ArgElem(local, n));
+                }
+                // A host function may return a value with history
+                Expr::Call(name, host_args) if self.hosts.contains_key(name) => {
+                    let index = self.hosts[name];
+                    self.call_args(f, host_args);
+                    f.code.push(Op::Host { index, args: host_args.len() as u32, as_arg: true });
+                }
+                _ => {
+                    self.expr(f, arg);
+                    f.code.push(Op::ArgValue);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,493 @@
+// Debug Adapter Protocol server (`blur dap`)
+//
+// Speaks DAP over stdin/stdout so editors can debug a Blur program on the
+// tree-walker. Like `blur debug`, it runs as the interpreter's statement
+// hook: when the program stops, the hook answers requests until one resumes
+// it. There is one thread; frame 0 is the innermost call. A variable's
+// children are its raw history entries, an array's children its elements
+// (or rows), so the whole history can be browsed from the Variables view.
+
+use crate::ast::{Program, Type};
+use crate::debugger::{describe, evolution, frame_scopes, lookup, Breakpoint, Lookup, Mode, Stop, Stops};
+use crate::interpreter::{ExecutionLimits, Interpreter, RuntimeError};
+use crate::parser::Parser;
+use crate::rpc::{read_message, write_message};
+use crate::{process_directives, resolver};
+use serde_json::{json, Value as Json};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+const THREAD: i64 = 1;
+
+/// Writes framed messages to the client; shared with the program's output sinks
+struct Channel {
+    out: Box<dyn Write>,
+    seq: i64,
+}
+
+impl Channel {
+    fn send(&mut self, mut message: Json) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = self.seq.into();
+        write_message(&mut self.out, &message)
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn respond(&mut self, request: &Json, result: Result<Json, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = message.into(),
+        }
+        self.send(response)
+    }
+}
+
+/// Program output sent as `output` events, a line at a time
+struct OutputEvents {
+    channel: Rc<RefCell<Channel>>,
+    category: &'static str,
+    pending: Vec<u8>,
+}
+
+impl OutputEvents {
+    fn new(channel: &Rc<RefCell<Channel>>, category: &'static str) -> Self {
+        OutputEvents { channel: Rc::clone(channel), category, pending: Vec::new() }
+    }
+
+    fn send(&mut self, bytes: Vec<u8>) -> io::Result<()> {
+        let output = String::from_utf8_lossy(&bytes).into_owned();
+        self.channel.borrow_mut().event("output", json!({ "category": self.category, "output": output }))
+    }
+}
+
+impl Write for OutputEvents {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        if let Some(end) = self.pending.iter().rposition(|&b| b == b'\n') {
+            let rest = self.pending.split_off(end + 1);
+            let lines = std::mem::replace(&mut self.pending, rest);
+            self.send(lines)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            let rest = std::mem::take(&mut self.pending);
+            self.send(rest)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a variablesReference expands to
+enum Expand {
+    /// The variables of a frame
+    Locals(usize),
+    /// The history of a variable or array element
+    History { frame: usize, name: String, indices: Vec<i64> },
+    /// The elements (or rows) of an array below the given indices
+    Elements { frame: usize, name: String, indices: Vec<i64> },
+}
+
+/// What a request does to the program
+enum Flow {
+    Stay,
+    /// configurationDone: run the launched program
+    Start,
+    Resume(Mode),
+    Disconnect,
+}
+
+struct Session {
+    input: Box<dyn BufRead>,
+    channel: Rc<RefCell<Channel>>,
+    stops: Stops,
+    limits: ExecutionLimits,
+    /// The launched program, its path and number of lines
+    program: Option<(Program, String, usize)>,
+    /// The next stop is the one asked for by stopOnEntry
+    entry: bool,
+    /// Reference n is refs[n - 1]; cleared whenever the program resumes
+    refs: Vec<Expand>,
+}
+
+/// Serve one debugging session: `input` carries the client's requests and
+/// `output` gets responses, events and the program's output
+pub fn serve(input: impl BufRead + 'static, output: impl Write + 'static, limits: ExecutionLimits) -> io::Result<()> {
+    let channel = Rc::new(RefCell::new(Channel { out: Box::new(output), seq: 0 }));
+    let session = Rc::new(RefCell::new(Session {
+        input: Box::new(input),
+        channel,
+        stops: Stops::new(Mode::Continue),
+        limits,
+        program: None,
+        entry: false,
+        refs: Vec::new(),
+    }));
+    loop {
+        let Some(request) = session.borrow_mut().read()? else {
+            return Ok(());
+        };
+        let flow = session.borrow_mut().handle(&request, None)?;
+        match flow {
+            Flow::Start => {
+                if !launch(&session)? {
+                    return Ok(());
+                }
+            }
+            Flow::Disconnect => return Ok(()),
+            Flow::Stay | Flow::Resume(_) => {}
+        }
+    }
+}
+
+/// Run the launched program to the end; false if the client disconnected
+fn launch(session: &Rc<RefCell<Session>>) -> io::Result<bool> {
+    let (program, limits, channel) = {
+        let session = session.borrow();
+        match &session.program {
+            Some((program, _, _)) => (program.clone(), session.limits, Rc::clone(&session.channel)),
+            None => return Ok(true),
+        }
+    };
+    let mut interpreter =
+        Interpreter::with_output(OutputEvents::new(&channel, "stdout"), OutputEvents::new(&channel, "stderr"));
+    interpreter.limits = limits;
+    let hook = Rc::clone(session);
+    interpreter.hook = Some(Box::new(move |interpreter, stmt| hook.borrow_mut().before(interpreter, stmt)));
+    let result = interpreter.run(&program);
+    interpreter.hook = None;
+    interpreter.output.flush()?;
+
+    let mut session = session.borrow_mut();
+    let code = match result {
+        Ok(_) => 0,
+        Err(RuntimeError::Stopped) => return Ok(false),
+        Err(e) => {
+            // Stop once more so the variables can be inspected
+            let line = interpreter.frames.last().map_or(0, |f| f.span.line);
+            let text = format!("Runtime error at line {}: {}", line, e);
+            channel.borrow_mut().event("output", json!({ "category": "stderr", "output": format!("{}\n", text) }))?;
+            let stopped = json!({ "reason": "exception", "description": "Runtime error", "text": text });
+            if session.pause(&interpreter, stopped).is_err() {
+                return Ok(false);
+            }
+            1
+        }
+    };
+    let mut channel = channel.borrow_mut();
+    channel.event("exited", json!({ "exitCode": code }))?;
+    channel.event("terminated", json!({}))?;
+    Ok(true)
+}
+
+impl Session {
+    /// The next request, or None at the end of the input
+    fn read(&mut self) -> io::Result<Option<Json>> {
+        read_message(&mut self.input)
+    }
+
+    fn before(&mut self, interpreter: &mut Interpreter, stmt: &crate::ast::Stmt) -> Result<(), RuntimeError> {
+        let Some(stop) = self.stops.check(interpreter, stmt) else {
+            return Ok(());
+        };
+        let stopped = match stop {
+            Stop::Breakpoint(n) => json!({ "reason": "breakpoint", "hitBreakpointIds": [n + 1] }),
+            Stop::Step if self.entry => json!({ "reason": "entry" }),
+            Stop::Step => json!({ "reason": "step" }),
+        };
+        self.entry = false;
+        self.pause(interpreter, stopped)
+    }
+
+    /// Report a stop, then answer requests until one resumes the program
+    fn pause(&mut self, interpreter: &Interpreter, mut stopped: Json) -> Result<(), RuntimeError> {
+        let io_error = |e: io::Error| RuntimeError::Io(e.to_string());
+        stopped["threadId"] = THREAD.into();
+        stopped["allThreadsStopped"] = true.into();
+        self.channel.borrow_mut().event("stopped", stopped).map_err(io_error)?;
+        loop {
+            let Some(request) = self.read().map_err(io_error)? else {
+                return Err(RuntimeError::Stopped);
+            };
+            match self.handle(&request, Some(interpreter)).map_err(io_error)? {
+                Flow::Stay | Flow::Start => {}
+                Flow::Resume(mode) => {
+                    self.stops.mode = mode;
+                    self.refs.clear();
+                    return Ok(());
+                }
+                Flow::Disconnect => return Err(RuntimeError::Stopped),
+            }
+        }
+    }
+
+    /// Answer one request; `interpreter` is the stopped program, if any
+    fn handle(&mut self, request: &Json, interpreter: Option<&Interpreter>) -> io::Result<Flow> {
+        let args = &request["arguments"];
+        let mut flow = Flow::Stay;
+        let result = match request["command"].as_str().unwrap_or("") {
+            "initialize" => {
+                let capabilities = json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsFunctionBreakpoints": true,
+                });
+                self.channel.borrow_mut().respond(request, Ok(capabilities))?;
+                return self.channel.borrow_mut().event("initialized", json!({})).map(|_| Flow::Stay);
+            }
+            "launch" => self.launch(args),
+            "setBreakpoints" => Ok(self.set_breakpoints(args)),
+            "setFunctionBreakpoints" => Ok(self.set_function_breakpoints(args)),
+            "configurationDone" => {
+                flow = Flow::Start;
+                Ok(json!({}))
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD, "name": "blur" }] })),
+            "stackTrace" => Ok(self.stack_trace(interpreter, args)),
+            "scopes" => {
+                let frame = args["frameId"].as_u64().unwrap_or(0) as usize;
+                let locals = self.reference(Expand::Locals(frame));
+                Ok(json!({ "scopes": [{ "name": "Locals", "variablesReference": locals, "expensive": false }] }))
+            }
+            "variables" => match interpreter {
+                Some(interpreter) => self.variables(interpreter, args["variablesReference"].as_u64().unwrap_or(0) as usize),
+                None => Err("The program is not running.".to_string()),
+            },
+            command @ ("continue" | "next" | "stepIn" | "stepOut") => match interpreter {
+                Some(interpreter) => {
+                    let depth = interpreter.frames.len();
+                    flow = Flow::Resume(match command {
+                        "continue" => Mode::Continue,
+                        "next" => Mode::Next(depth),
+                        "stepIn" => Mode::Step,
+                        _ => Mode::Next(depth.saturating_sub(1)),
+                    });
+                    Ok(json!({ "allThreadsContinued": true }))
+                }
+                None => Err("The program is not running.".to_string()),
+            },
+            "disconnect" | "terminate" => {
+                flow = Flow::Disconnect;
+                Ok(json!({}))
+            }
+            command => Err(format!("Unsupported request '{}'", command)),
+        };
+        self.channel.borrow_mut().respond(request, result)?;
+        Ok(flow)
+    }
+
+    /// Load, parse and resolve the program at `program`
+    fn launch(&mut self, args: &Json) -> Result<Json, String> {
+        let path = args["program"].as_str().ok_or("launch needs a \"program\" path")?;
+        let source = fs::read_to_string(path).map_err(|e| format!("Error reading file '{}': {}", path, e))?;
+        let source = process_directives(&source, &mut self.limits);
+        let mut program = Parser::new(&source).parse_program().map_err(|e| format!("Parse error: {}", e))?;
+        if let Some(name) = resolver::resolve(&mut program).first() {
+            return Err(format!("{}: {}", name.span, name));
+        }
+        if args["stopOnEntry"].as_bool().unwrap_or(false) {
+            self.stops.mode = Mode::Step;
+            self.entry = true;
+        }
+        self.program = Some((program, path.to_string(), source.lines().count()));
+        Ok(json!({}))
+    }
+
+    /// Replace the line breakpoints; ids are 1 + their index in `stops`
+    fn set_breakpoints(&mut self, args: &Json) -> Json {
+        for bp in &mut self.stops.breakpoints {
+            if matches!(bp, Some(Breakpoint::Line(_))) {
+                *bp = None;
+            }
+        }
+        let lines = self.program.as_ref().map_or(0, |(_, _, lines)| *lines);
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let breakpoints: Vec<Json> = requested
+            .iter()
+            .map(|bp| {
+                let line = bp["line"].as_u64().unwrap_or(0) as u32;
+                self.stops.breakpoints.push(Some(Breakpoint::Line(line)));
+                let verified = line >= 1 && line as usize <= lines;
+                json!({ "id": self.stops.breakpoints.len(), "verified": verified, "line": line })
+            })
+            .collect();
+        json!({ "breakpoints": breakpoints })
+    }
+
+    /// Replace the function breakpoints
+    fn set_function_breakpoints(&mut self, args: &Json) -> Json {
+        for bp in &mut self.stops.breakpoints {
+            if matches!(bp, Some(Breakpoint::Function(_))) {
+                *bp = None;
+            }
+        }
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let breakpoints: Vec<Json> = requested
+            .iter()
+            .map(|bp| {
+                let name = bp["name"].as_str().unwrap_or("").trim_end_matches("()").to_string();
+                let verified = self
+                    .program
+                    .as_ref()
+                    .is_some_and(|(program, _, _)| program.functions.iter().any(|f| f.name == name));
+                self.stops.breakpoints.push(Some(Breakpoint::Function(name)));
+                json!({ "id": self.stops.breakpoints.len(), "verified": verified })
+            })
+            .collect();
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn stack_trace(&self, interpreter: Option<&Interpreter>, args: &Json) -> Json {
+        let frames = interpreter.map_or(&[][..], |interpreter| &interpreter.frames[..]);
+        let path = self.program.as_ref().map_or("", |(_, path, _)| path.as_str());
+        let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+        let start = args["startFrame"].as_u64().unwrap_or(0) as usize;
+        let levels = match args["levels"].as_u64() {
+            Some(0) | None => frames.len(),
+            Some(levels) => levels as usize,
+        };
+        let stack: Vec<Json> = frames
+            .iter()
+            .rev()
+            .enumerate()
+            .skip(start)
+            .take(levels)
+            .map(|(id, frame)| {
+                json!({
+                    "id": id,
+                    "name": frame.name,
+                    "line": frame.span.line,
+                    "column": frame.span.col,
+                    "source": { "name": name, "path": path },
+                })
+            })
+            .collect();
+        json!({ "stackFrames": stack, "totalFrames": frames.len() })
+    }
+
+    fn reference(&mut self, expand: Expand) -> usize {
+        self.refs.push(expand);
+        self.refs.len()
+    }
+
+    fn variables(&mut self, interpreter: &Interpreter, reference: usize) -> Result<Json, String> {
+        let expand = match self.refs.get(reference.wrapping_sub(1)) {
+            Some(Expand::Locals(frame)) => Expand::Locals(*frame),
+            Some(Expand::History { frame, name, indices }) => {
+                Expand::History { frame: *frame, name: name.clone(), indices: indices.clone() }
+            }
+            Some(Expand::Elements { frame, name, indices }) => {
+                Expand::Elements { frame: *frame, name: name.clone(), indices: indices.clone() }
+            }
+            None => return Err(format!("Unknown variablesReference {}", reference)),
+        };
+        let variables = match expand {
+            Expand::Locals(frame) => self.locals(interpreter, frame),
+            Expand::History { frame, name, indices } => match lookup(interpreter, frame, &name, &indices) {
+                Some(Lookup::Value(var)) => history(var),
+                _ => Vec::new(),
+            },
+            Expand::Elements { frame, name, indices } => match lookup(interpreter, frame, &name, &[]) {
+                Some(Lookup::Array(arr)) => {
+                    let level = indices.len();
+                    let mut children = Vec::new();
+                    for i in 0..arr.dims.get(level).copied().unwrap_or(0) as i64 {
+                        let mut path = indices.clone();
+                        path.push(i);
+                        let child = if level + 1 < arr.dims.len() {
+                            let rows: String = arr.dims[level + 1..].iter().map(|d| format!("[{}]", d)).collect();
+                            let value = format!("{}{}", arr.elem_type, rows);
+                            let reference = self.reference(Expand::Elements { frame, name: name.clone(), indices: path });
+                            json!({ "name": format!("[{}]", i), "value": value, "variablesReference": reference })
+                        } else {
+                            let var = arr.elem(&path).map_err(|e| e.to_string())?;
+                            let value = var.get().to_string();
+                            let kind = describe(var);
+                            let reference = self.reference(Expand::History { frame, name: name.clone(), indices: path });
+                            json!({ "name": format!("[{}]", i), "value": value, "type": kind, "variablesReference": reference })
+                        };
+                        children.push(child);
+                    }
+                    children
+                }
+                _ => Vec::new(),
+            },
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    /// The variables visible in a frame, outermost scope first; shadowed ones are left out
+    fn locals(&mut self, interpreter: &Interpreter, frame: usize) -> Vec<Json> {
+        let mut seen = HashSet::new();
+        let mut variables = Vec::new();
+        for scope in frame_scopes(interpreter, frame) {
+            let mut inner = Vec::new();
+            for (name, var) in scope.vars.iter() {
+                if seen.insert(name.as_str().to_string()) {
+                    let reference =
+                        self.reference(Expand::History { frame, name: name.as_str().to_string(), indices: Vec::new() });
+                    inner.push(json!({
+                        "name": name.as_str(),
+                        "value": var.get().to_string(),
+                        "type": describe(var),
+                        "variablesReference": reference,
+                    }));
+                }
+            }
+            for (name, arr) in scope.arrays.iter() {
+                if seen.insert(name.as_str().to_string()) {
+                    let dims: String = arr.dims.iter().map(|d| format!("[{}]", d)).collect();
+                    let reference =
+                        self.reference(Expand::Elements { frame, name: name.as_str().to_string(), indices: Vec::new() });
+                    inner.push(json!({
+                        "name": name.as_str(),
+                        "value": arr.to_string(),
+                        "type": format!("{}{}", arr.elem_type, dims),
+                        "variablesReference": reference,
+                        "indexedVariables": arr.dims.first().copied().unwrap_or(0),
+                    }));
+                }
+            }
+            inner.extend(variables);
+            variables = inner;
+        }
+        variables
+    }
+}
+
+/// A variable's raw history entries, numbered from 1 (strings: one history per position)
+fn history(var: &crate::interpreter::BlurValue) -> Vec<Json> {
+    if var.var_type == Type::String {
+        return var
+            .string_history
+            .iter()
+            .enumerate()
+            .map(|(i, chars)| {
+                let chars: Vec<String> = chars.iter().map(|c| format!("'{}'", c)).collect();
+                json!({ "name": format!("[{}]", i), "value": chars.join(" "), "variablesReference": 0 })
+            })
+            .collect();
+    }
+    let mut entries = Vec::new();
+    if var.summary.count > 0 {
+        let folded = format!("{} older entries, mean {}", var.summary.count, var.summary.mean());
+        entries.push(json!({ "name": "folded", "value": folded, "variablesReference": 0 }));
+    }
+    for (i, (raw, _)) in evolution(var).into_iter().enumerate() {
+        entries.push(json!({ "name": (var.summary.count + i + 1).to_string(), "value": raw, "variablesReference": 0 }));
+    }
+    entries
+}
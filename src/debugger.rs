@@ -0,0 +1,523 @@
+// Interactive debugger for the tree-walking interpreter (`blur debug`, `.debug`)
+//
+// Runs as the interpreter's statement hook: before each statement it checks
+// the breakpoints and the stepping mode, and when it stops it reads commands
+// until one resumes the program. A location is a (call depth, line) pair;
+// stepping stops at the next statement with a different location, so a loop
+// that fits on one line is stepped over as a whole. `Stops` and the variable
+// lookups are shared with the DAP server (dap.rs).
+
+use crate::ast::{Stmt, Type};
+use crate::interpreter::{BlurArray, BlurValue, Interpreter, RuntimeError, Scope, Value};
+use std::cell::RefCell;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+const HELP: &str = "\
+Commands:
+    s, step              Run to the next line, entering calls
+    n, next              Run to the next line in this function (or its caller)
+    c, continue          Run to the next breakpoint
+    b, break <line|fn>   Stop at a line or at the start of a function
+    b, break             List breakpoints
+    d, delete [n]        Delete breakpoint n (all without n)
+    bt, backtrace        Show the call stack
+    f, frame <n>         Select frame n for print and history
+    p, print <var>       Show a variable, array or element (a[1][2])
+    h, history <var>     Show each history entry and the average after it
+    i, locals            Show the variables of the selected frame
+    l, list [line]       Show the source around the current (or given) line
+    q, quit              Stop the program
+An empty line repeats the last command.";
+
+pub enum Breakpoint {
+    Line(u32),
+    Function(String),
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Breakpoint::Line(line) => write!(f, "line {}", line),
+            Breakpoint::Function(name) => write!(f, "{}()", name),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Stop at the next statement at another location
+    Step,
+    /// The same, skipping calls deeper than the given depth
+    Next(usize),
+    /// Stop only at breakpoints
+    Continue,
+}
+
+/// Why the program stopped
+pub enum Stop {
+    /// At the breakpoint with this index
+    Breakpoint(usize),
+    Step,
+}
+
+/// Breakpoints and the stepping mode: where the program stops
+pub struct Stops {
+    /// Numbered from 1; deleted ones stay as None so numbers don't shift
+    pub breakpoints: Vec<Option<Breakpoint>>,
+    pub mode: Mode,
+    /// Location of the previous statement
+    last: (usize, u32),
+    /// Where the program last stopped
+    stopped_at: (usize, u32),
+}
+
+impl Stops {
+    pub fn new(mode: Mode) -> Self {
+        Stops { breakpoints: Vec::new(), mode, last: (0, 0), stopped_at: (0, 0) }
+    }
+
+    /// Whether to stop before `stmt`
+    pub fn check(&mut self, interpreter: &Interpreter, stmt: &Stmt) -> Option<Stop> {
+        let depth = interpreter.frames.len();
+        let here = (depth, stmt.span.line);
+        let entered = depth > self.last.0;
+        let moved = here != self.last;
+        self.last = here;
+
+        let hit = self.breakpoints.iter().position(|bp| match bp {
+            Some(Breakpoint::Line(line)) => moved && *line == here.1,
+            Some(Breakpoint::Function(name)) => entered && interpreter.frames.last().is_some_and(|f| &f.name == name),
+            None => false,
+        });
+        let step = match self.mode {
+            Mode::Step => here != self.stopped_at,
+            Mode::Next(max) => depth <= max && here != self.stopped_at,
+            Mode::Continue => false,
+        };
+        let stop = match hit {
+            Some(n) => Stop::Breakpoint(n),
+            None if step => Stop::Step,
+            None => return None,
+        };
+        self.stopped_at = here;
+        Some(stop)
+    }
+
+    /// The line the program last stopped at
+    pub fn stopped_line(&self) -> u32 {
+        self.stopped_at.1
+    }
+
+    /// The line of the last statement that started
+    pub fn last_line(&self) -> u32 {
+        self.last.1
+    }
+}
+
+/// What a command asks the debugger to do next
+enum Action {
+    Prompt,
+    Resume(Mode),
+    Quit,
+}
+
+pub struct Debugger {
+    lines: Vec<String>,
+    stops: Stops,
+    /// Frame shown by print and history, counting from the innermost (0)
+    selected: usize,
+    last_command: String,
+    input: Box<dyn BufRead>,
+    out: Box<dyn Write>,
+}
+
+impl Debugger {
+    /// A debugger for `source` that stops at the first statement
+    pub fn new(source: &str, input: impl BufRead + 'static, out: impl Write + 'static) -> Self {
+        Debugger {
+            lines: source.lines().map(str::to_string).collect(),
+            stops: Stops::new(Mode::Step),
+            selected: 0,
+            last_command: String::new(),
+            input: Box::new(input),
+            out: Box::new(out),
+        }
+    }
+
+    /// A debugger on stdin/stdout
+    pub fn stdio(source: &str) -> Self {
+        Self::new(source, io::stdin().lock(), io::stdout())
+    }
+
+    /// Install the debugger as the interpreter's statement hook
+    pub fn attach(debugger: &Rc<RefCell<Debugger>>, interpreter: &mut Interpreter) {
+        let debugger = Rc::clone(debugger);
+        interpreter.hook = Some(Box::new(move |interpreter, stmt| debugger.borrow_mut().before(interpreter, stmt)));
+    }
+
+    fn before(&mut self, interpreter: &mut Interpreter, stmt: &Stmt) -> Result<(), RuntimeError> {
+        let Some(stop) = self.stops.check(interpreter, stmt) else {
+            return Ok(());
+        };
+        self.selected = 0;
+        let io_error = |e: io::Error| RuntimeError::Io(e.to_string());
+        if let Stop::Breakpoint(n) = stop {
+            write!(self.out, "Breakpoint {}, ", n + 1).map_err(io_error)?;
+        }
+        let function = interpreter.frames.last().map_or("top level".to_string(), |f| format!("{}()", f.name));
+        writeln!(self.out, "{} at line {}", function, stmt.span.line).map_err(io_error)?;
+        self.show_line(stmt.span.line).map_err(io_error)?;
+        self.prompt(interpreter, true)
+    }
+
+    /// Report how the program ended; after a runtime error, the variables can
+    /// still be inspected until the user quits
+    pub fn finished(&mut self, interpreter: &Interpreter, result: &Result<Value, RuntimeError>) -> Result<(), RuntimeError> {
+        let io_error = |e: io::Error| RuntimeError::Io(e.to_string());
+        match result {
+            Ok(_) => writeln!(self.out, "Program finished.").map_err(io_error),
+            Err(RuntimeError::Stopped) => Ok(()),
+            Err(e) => {
+                let line = interpreter.frames.last().map_or(self.stops.last_line(), |f| f.span.line);
+                writeln!(self.out, "Runtime error at line {}: {}", line, e).map_err(io_error)?;
+                self.show_line(line).map_err(io_error)?;
+                self.selected = 0;
+                match self.prompt(interpreter, false) {
+                    Err(RuntimeError::Stopped) => Ok(()),
+                    other => other,
+                }
+            }
+        }
+    }
+
+    /// Read commands until one resumes (or stops) the program
+    fn prompt(&mut self, interpreter: &Interpreter, running: bool) -> Result<(), RuntimeError> {
+        let io_error = |e: io::Error| RuntimeError::Io(e.to_string());
+        loop {
+            write!(self.out, "(blur-db) ").map_err(io_error)?;
+            self.out.flush().map_err(io_error)?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line).map_err(io_error)? == 0 {
+                // End of input: like quit
+                writeln!(self.out).map_err(io_error)?;
+                return Err(RuntimeError::Stopped);
+            }
+            let line = line.trim();
+            if !line.is_empty() {
+                self.last_command = line.to_string();
+            }
+            let command = self.last_command.clone();
+            match self.command(interpreter, &command, running).map_err(io_error)? {
+                Action::Prompt => {}
+                Action::Resume(mode) => {
+                    self.stops.mode = mode;
+                    return Ok(());
+                }
+                Action::Quit => return Err(RuntimeError::Stopped),
+            }
+        }
+    }
+
+    fn command(&mut self, interpreter: &Interpreter, line: &str, running: bool) -> io::Result<Action> {
+        let (command, arg) = line.split_once(' ').map_or((line, ""), |(c, a)| (c, a.trim()));
+        let resume = match command {
+            "" => return Ok(Action::Prompt),
+            "s" | "step" => Some(Mode::Step),
+            "n" | "next" => Some(Mode::Next(interpreter.frames.len())),
+            "c" | "continue" => Some(Mode::Continue),
+            "q" | "quit" => return Ok(Action::Quit),
+            "b" | "break" => {
+                self.set_breakpoint(interpreter, arg)?;
+                None
+            }
+            "d" | "delete" => {
+                self.delete_breakpoint(arg)?;
+                None
+            }
+            "bt" | "backtrace" | "where" => {
+                self.backtrace(interpreter)?;
+                None
+            }
+            "f" | "frame" => {
+                self.select_frame(interpreter, arg)?;
+                None
+            }
+            "p" | "print" => {
+                self.print(interpreter, arg)?;
+                None
+            }
+            "h" | "history" => {
+                self.history(interpreter, arg)?;
+                None
+            }
+            "i" | "locals" => {
+                self.locals(interpreter)?;
+                None
+            }
+            "l" | "list" => {
+                let center = arg.parse().unwrap_or(self.stops.stopped_line());
+                self.list(center)?;
+                None
+            }
+            "help" => {
+                writeln!(self.out, "{}", HELP)?;
+                None
+            }
+            _ => {
+                writeln!(self.out, "Unknown command '{}'. Type help for a list.", command)?;
+                None
+            }
+        };
+        match resume {
+            Some(_) if !running => {
+                writeln!(self.out, "The program is not running.")?;
+                Ok(Action::Prompt)
+            }
+            Some(mode) => Ok(Action::Resume(mode)),
+            None => Ok(Action::Prompt),
+        }
+    }
+
+    fn set_breakpoint(&mut self, interpreter: &Interpreter, arg: &str) -> io::Result<()> {
+        if arg.is_empty() {
+            let mut any = false;
+            for (i, bp) in self.stops.breakpoints.iter().enumerate() {
+                if let Some(bp) = bp {
+                    writeln!(self.out, "  {}: {}", i + 1, bp)?;
+                    any = true;
+                }
+            }
+            if !any {
+                writeln!(self.out, "No breakpoints.")?;
+            }
+            return Ok(());
+        }
+        let bp = match arg.parse::<u32>() {
+            Ok(line) if line == 0 || line as usize > self.lines.len() => {
+                return writeln!(self.out, "No line {} in the source.", line);
+            }
+            Ok(line) => Breakpoint::Line(line),
+            Err(_) => {
+                let name = arg.trim_end_matches("()");
+                if !interpreter.functions.contains_key(name) {
+                    return writeln!(self.out, "No function named {}.", name);
+                }
+                Breakpoint::Function(name.to_string())
+            }
+        };
+        writeln!(self.out, "Breakpoint {} at {}", self.stops.breakpoints.len() + 1, bp)?;
+        self.stops.breakpoints.push(Some(bp));
+        Ok(())
+    }
+
+    fn delete_breakpoint(&mut self, arg: &str) -> io::Result<()> {
+        if arg.is_empty() {
+            self.stops.breakpoints.iter_mut().for_each(|bp| *bp = None);
+            return writeln!(self.out, "Deleted all breakpoints.");
+        }
+        match arg.parse::<usize>().ok().and_then(|n| self.stops.breakpoints.get_mut(n.wrapping_sub(1))) {
+            Some(bp @ Some(_)) => {
+                *bp = None;
+                writeln!(self.out, "Deleted breakpoint {}.", arg)
+            }
+            _ => writeln!(self.out, "No breakpoint {}.", arg),
+        }
+    }
+
+    fn backtrace(&mut self, interpreter: &Interpreter) -> io::Result<()> {
+        if interpreter.frames.is_empty() {
+            return writeln!(self.out, "#0  top level at line {}", self.stops.stopped_line());
+        }
+        for (i, frame) in interpreter.frames.iter().rev().enumerate() {
+            let marker = if i == self.selected { '>' } else { ' ' };
+            writeln!(self.out, "{}#{}  {}() at line {}", marker, i, frame.name, frame.span.line)?;
+        }
+        Ok(())
+    }
+
+    fn select_frame(&mut self, interpreter: &Interpreter, arg: &str) -> io::Result<()> {
+        match arg.parse::<usize>() {
+            Ok(n) if n < interpreter.frames.len().max(1) => {
+                self.selected = n;
+                self.backtrace(interpreter)
+            }
+            _ => writeln!(self.out, "No frame {}. Use backtrace to see the frames.", arg),
+        }
+    }
+
+    /// The variable or element `target` names ("x", "a[1][2]")
+    fn lookup<'a>(&self, interpreter: &'a Interpreter, target: &str) -> Result<Lookup<'a>, String> {
+        let (name, indices) = parse_target(target).ok_or_else(|| format!("Can't read '{}'; expected a name like x or a[1]", target))?;
+        if let Some(found) = lookup(interpreter, self.selected, name, &indices) {
+            return Ok(found);
+        }
+        match find_array(interpreter, self.selected, name) {
+            // Out of bounds: let the array say why
+            Some(arr) => arr.elem(&indices).map(Lookup::Value).map_err(|e| e.to_string()),
+            None => Err(format!("No variable named {} in this frame", name)),
+        }
+    }
+
+    fn print(&mut self, interpreter: &Interpreter, arg: &str) -> io::Result<()> {
+        match self.lookup(interpreter, arg) {
+            Ok(Lookup::Value(var)) => writeln!(self.out, "{} = {}  ({})", arg, var.get(), describe(var)),
+            Ok(Lookup::Array(arr)) => writeln!(self.out, "{} = {}", arg, arr),
+            Err(e) => writeln!(self.out, "{}", e),
+        }
+    }
+
+    fn history(&mut self, interpreter: &Interpreter, arg: &str) -> io::Result<()> {
+        let var = match self.lookup(interpreter, arg) {
+            Ok(Lookup::Value(var)) => var,
+            Ok(Lookup::Array(_)) => return writeln!(self.out, "{} is an array; pick an element, like {}[0]", arg, arg),
+            Err(e) => return writeln!(self.out, "{}", e),
+        };
+        writeln!(self.out, "{}: {}", arg, describe(var))?;
+        if var.var_type == Type::String {
+            // Every character position has its own history
+            for (i, chars) in var.string_history.iter().enumerate() {
+                let chars: Vec<String> = chars.iter().map(|c| format!("'{}'", c)).collect();
+                writeln!(self.out, "  [{}] {}", i, chars.join(" "))?;
+            }
+            return Ok(());
+        }
+        if var.summary.count > 0 {
+            writeln!(self.out, "  {} older entries folded into a mean of {}", var.summary.count, var.summary.mean())?;
+        }
+        writeln!(self.out, "  {:>5}  {:<20} average", "#", "raw")?;
+        for (i, (raw, average)) in evolution(var).into_iter().enumerate() {
+            writeln!(self.out, "  {:>5}  {:<20} {}", var.summary.count + i + 1, raw, average)?;
+        }
+        Ok(())
+    }
+
+    fn locals(&mut self, interpreter: &Interpreter) -> io::Result<()> {
+        let mut any = false;
+        for scope in frame_scopes(interpreter, self.selected).collect::<Vec<_>>().into_iter().rev() {
+            for (name, var) in scope.vars.iter() {
+                writeln!(self.out, "  {} = {}  ({})", name, var.get(), describe(var))?;
+                any = true;
+            }
+            for (name, arr) in scope.arrays.iter() {
+                writeln!(self.out, "  {} = {}", name, arr)?;
+                any = true;
+            }
+        }
+        if !any {
+            writeln!(self.out, "No variables.")?;
+        }
+        Ok(())
+    }
+
+    fn show_line(&mut self, line: u32) -> io::Result<()> {
+        if let Some(text) = self.lines.get((line as usize).wrapping_sub(1)) {
+            writeln!(self.out, "{:>5} | {}", line, text)?;
+        }
+        Ok(())
+    }
+
+    fn list(&mut self, center: u32) -> io::Result<()> {
+        let first = center.saturating_sub(5).max(1);
+        let last = (center + 5).min(self.lines.len() as u32);
+        for line in first..=last {
+            let marker = if line == self.stops.stopped_line() { '>' } else { ' ' };
+            writeln!(self.out, "{}{:>4} | {}", marker, line, self.lines[line as usize - 1])?;
+        }
+        Ok(())
+    }
+}
+
+pub enum Lookup<'a> {
+    Value(&'a BlurValue),
+    Array(&'a BlurArray),
+}
+
+/// The scopes of a frame (0 = innermost), innermost first; with no calls in
+/// progress, the REPL's top level
+pub fn frame_scopes(interpreter: &Interpreter, frame: usize) -> impl Iterator<Item = &Scope> {
+    let frames = &interpreter.frames;
+    let (start, end) = match frames.len().checked_sub(frame + 1) {
+        Some(i) => (frames[i].scope, frames.get(i + 1).map_or(interpreter.scopes.len(), |f| f.scope)),
+        None => (0, interpreter.scopes.len()),
+    };
+    interpreter.scopes[start.min(end)..end.min(interpreter.scopes.len())].iter().rev()
+}
+
+fn find_var<'a>(interpreter: &'a Interpreter, frame: usize, name: &str) -> Option<&'a BlurValue> {
+    frame_scopes(interpreter, frame).find_map(|scope| scope.vars.iter().find(|(n, _)| n.as_str() == name).map(|(_, v)| v))
+}
+
+fn find_array<'a>(interpreter: &'a Interpreter, frame: usize, name: &str) -> Option<&'a BlurArray> {
+    frame_scopes(interpreter, frame).find_map(|scope| scope.arrays.iter().find(|(n, _)| n.as_str() == name).map(|(_, a)| a))
+}
+
+/// A variable, array or (with indices) array element visible in a frame
+pub fn lookup<'a>(interpreter: &'a Interpreter, frame: usize, name: &str, indices: &[i64]) -> Option<Lookup<'a>> {
+    if indices.is_empty() {
+        if let Some(var) = find_var(interpreter, frame, name) {
+            return Some(Lookup::Value(var));
+        }
+    }
+    let arr = find_array(interpreter, frame, name)?;
+    if indices.is_empty() {
+        Some(Lookup::Array(arr))
+    } else {
+        arr.elem(indices).ok().map(Lookup::Value)
+    }
+}
+
+/// Split "a[1][2]" into the name and its indices
+fn parse_target(target: &str) -> Option<(&str, Vec<i64>)> {
+    let (name, mut rest) = target.split_once('[').map_or((target, ""), |(name, rest)| (name, rest));
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+    let mut indices = Vec::new();
+    while !rest.is_empty() {
+        let (index, after) = rest.split_once(']')?;
+        indices.push(index.trim().parse().ok()?);
+        rest = after.trim_start();
+        if !rest.is_empty() {
+            rest = rest.strip_prefix('[')?;
+        }
+    }
+    Some((name, indices))
+}
+
+/// "int, 5 entries, sharp"
+pub fn describe(var: &BlurValue) -> String {
+    let len = var.history_len();
+    let entries = if len == 1 { "entry" } else { "entries" };
+    let sharp = if var.sharp { ", sharp" } else { "" };
+    format!("{}, {} {}{}", var.var_type, len, entries, sharp)
+}
+
+/// Each raw entry still in the history, with the variable's value right after it was pushed
+pub fn evolution(var: &BlurValue) -> Vec<(String, Value)> {
+    let mut replay = BlurValue::new(var.var_type.clone());
+    replay.summary = var.summary;
+    match var.var_type {
+        Type::Bool => var
+            .bool_history
+            .iter()
+            .map(|&b| {
+                replay.push_bool(b);
+                (b.to_string(), replay.get())
+            })
+            .collect(),
+        _ => var
+            .history
+            .iter()
+            .map(|&x| {
+                replay.push(x);
+                let raw = match var.var_type {
+                    Type::Char => format!("'{}'", char::from_u32(x as u32).unwrap_or('?')),
+                    _ => x.to_string(),
+                };
+                (raw, replay.get())
+            })
+            .collect(),
+    }
+}
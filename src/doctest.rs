@@ -0,0 +1,383 @@
+// Doctests (`blur doctest`): run the Blur snippets of Markdown and MediaWiki
+// documents and check the results written in their comments
+//
+// Snippets are ```c and ```blur fences in Markdown, and <pre> blocks (or
+// <syntaxhighlight lang="c">) in MediaWiki. A snippet with functions runs as
+// a program; anything else is wrapped in blur() the way `-e` wraps its code.
+// <pre> blocks also hold shell commands and sample output, so one that
+// doesn't parse is skipped rather than failed.
+//
+// A trailing comment on a print() line gives the values it prints:
+//
+//     print(s);        // "hcllo" - pos 1 averages 'e' and 'a'
+//     print(len(m), len(m, 1));  // 2 2
+//
+// The printed line has to end with them, so `print("Start:", money); // 5`
+// checks the 5. On a line that assigns a variable, `x = 16` or `value = 16`
+// anywhere in the comment gives the variable's value right after it:
+//
+//     x++;           // history: [5, 6], value = 6 (ceil of 5.5)
+//
+// Other comments are prose. The snippet runs on the tree-walker, whose hook
+// sees each statement start: a statement is over when the next one starts
+// in the same call or a caller, which is when its checks are made.
+
+use crate::ast::{Expr, Stmt, StmtKind};
+use crate::debugger::{self, Lookup};
+use crate::fmt;
+use crate::interpreter::Interpreter;
+use crate::lexer::Token;
+use crate::output::Capture;
+use crate::parser::Parser;
+use crate::resolver;
+use crate::testing::Settings;
+use crate::{process_directives, wrap_statements};
+use logos::Logos;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::rc::Rc;
+
+/// The markup a document is written in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Markup {
+    Markdown,
+    MediaWiki,
+}
+
+impl Markup {
+    /// MediaWiki for .wiki and .mediawiki files, Markdown for anything else
+    pub fn of(path: &str) -> Self {
+        if path.ends_with(".wiki") || path.ends_with(".mediawiki") {
+            Markup::MediaWiki
+        } else {
+            Markup::Markdown
+        }
+    }
+}
+
+/// A code block of a document
+pub struct Snippet {
+    /// Line of the document the code starts on
+    pub line: usize,
+    pub code: String,
+    /// Whether the block is marked as Blur (or C); unmarked blocks that don't
+    /// parse are skipped
+    pub marked: bool,
+}
+
+/// The code blocks of a document that may be Blur
+pub fn snippets(text: &str, markup: Markup) -> Vec<Snippet> {
+    let mut snippets = Vec::new();
+    // The line that closes the block we are in, whether to keep it, and its code
+    let mut open: Option<(&str, bool, Snippet)> = None;
+    for (i, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if let Some((close, keep, mut snippet)) = open.take() {
+            if trimmed != close {
+                snippet.code.push_str(&if markup == Markup::MediaWiki { unescape(line) } else { line.to_string() });
+                snippet.code.push('\n');
+                open = Some((close, keep, snippet));
+            } else if keep {
+                snippets.push(snippet);
+            }
+            continue;
+        }
+        let start = |marked| Snippet { line: i + 2, code: String::new(), marked };
+        let is_blur = |lang: &str| lang.eq_ignore_ascii_case("c") || lang.eq_ignore_ascii_case("blur");
+        match markup {
+            Markup::Markdown => {
+                if let Some(fence) = ["```", "~~~"].into_iter().find(|f| trimmed.starts_with(f)) {
+                    let lang = trimmed[3..].split_whitespace().next().unwrap_or("");
+                    open = Some((fence, is_blur(lang), start(true)));
+                }
+            }
+            Markup::MediaWiki => {
+                if trimmed == "<pre>" {
+                    open = Some(("</pre>", true, start(false)));
+                } else if let Some(tag) = ["syntaxhighlight", "source"].into_iter().find(|t| trimmed.starts_with(&format!("<{} ", t))) {
+                    let lang = trimmed.split("lang=\"").nth(1).and_then(|l| l.split('"').next()).unwrap_or("");
+                    let close = if tag == "source" { "</source>" } else { "</syntaxhighlight>" };
+                    open = Some((close, is_blur(lang), start(true)));
+                }
+            }
+        }
+    }
+    snippets
+}
+
+/// Undo the HTML escapes MediaWiki needs in <pre>
+fn unescape(line: &str) -> String {
+    line.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&amp;", "&")
+}
+
+/// How a snippet went
+pub enum Outcome {
+    /// It ran, making this many checks
+    Passed(usize),
+    /// It failed after this many checks; each failure has the line of the
+    /// snippet it is on
+    Failed(usize, Vec<(usize, String)>),
+    /// An unmarked block that isn't Blur
+    Skipped,
+}
+
+/// What a comment expects of the statement on its line
+#[derive(Debug, Clone)]
+enum Expect {
+    /// The values a print() prints, joined by spaces
+    Printed(String),
+    /// The value of a variable (or "value": whatever the statement assigns)
+    Value(String, String),
+}
+
+/// A check waiting for its statement to finish
+struct Pending {
+    line: usize,
+    depth: usize,
+    /// How much output there was when the statement started
+    output: usize,
+    expect: Expect,
+    /// The variable the statement assigns, like "x" or "a[1]"
+    target: Option<String>,
+}
+
+#[derive(Default)]
+struct State {
+    pending: Vec<Pending>,
+    checks: usize,
+    failures: Vec<(usize, String)>,
+}
+
+/// Run a snippet and check its annotations
+pub fn run(snippet: &Snippet, settings: Settings) -> Outcome {
+    let expects = annotations(&snippet.code);
+
+    settings.apply();
+    let mut limits = settings.limits;
+    let code = process_directives(&snippet.code, &mut limits);
+    let mut program = match Parser::new(&code).parse_program() {
+        Ok(program) => program,
+        // The final statement is a no-op for the hook to see the one before it finish
+        Err(_) => {
+            let wrapped = wrap_statements(&format!("{}\nreturn;", code));
+            let mut parser = Parser::new(&wrapped);
+            match parser.parse_program() {
+                Ok(program) => program,
+                Err(_) if !snippet.marked => return Outcome::Skipped,
+                Err(_) => {
+                    // Report the error without the extra statement
+                    let wrapped = wrap_statements(&code);
+                    let mut parser = Parser::new(&wrapped);
+                    let error = parser.parse_program().err().map_or(String::new(), |e| e.to_string());
+                    let line = parser.error_span().line as usize;
+                    return Outcome::Failed(0, vec![(line, format!("Parse error: {}", error))]);
+                }
+            }
+        }
+    };
+    resolver::resolve(&mut program);
+
+    let output = Capture::new();
+    let mut interpreter = Interpreter::with_output(output.clone(), io::stderr());
+    interpreter.limits = limits;
+    let state = Rc::new(RefCell::new(State::default()));
+    let hook_state = state.clone();
+    let hook_output = output.clone();
+    interpreter.hook = Some(Box::new(move |interpreter, stmt| {
+        let mut state = hook_state.borrow_mut();
+        let depth = interpreter.frames.len();
+        let printed = hook_output.contents();
+        state.finish(depth, interpreter, &printed);
+        let line = stmt.span.line as usize;
+        if let Some(expect) = expects.get(&line).and_then(|e| applies(e, stmt)) {
+            state.pending.push(Pending { line, depth, output: printed.len(), expect, target: target(stmt) });
+        }
+        Ok(())
+    }));
+    let result = interpreter.run(&program);
+
+    let mut state = state.take();
+    // Checks of values that are gone now can't be made; printed ones can
+    state.pending.retain(|p| matches!(p.expect, Expect::Printed(_)));
+    state.finish(0, &interpreter, &output.contents());
+    if let Err(e) = result {
+        let line = interpreter.frames.last().map_or(1, |f| f.span.line as usize);
+        state.failures.push((line, format!("Runtime error: {}", e)));
+    }
+    if state.failures.is_empty() {
+        Outcome::Passed(state.checks)
+    } else {
+        Outcome::Failed(state.checks, state.failures)
+    }
+}
+
+impl State {
+    /// Make the checks of the statements that are over once a statement at
+    /// `depth` starts
+    fn finish(&mut self, depth: usize, interpreter: &Interpreter, printed: &str) {
+        while self.pending.last().is_some_and(|p| p.depth >= depth) {
+            let Some(pending) = self.pending.pop() else { break };
+            self.checks += 1;
+            let failure = match &pending.expect {
+                Expect::Printed(expected) => printed[pending.output..]
+                    .lines()
+                    .find(|line| !ends_with(line, expected))
+                    .map(|line| format!("printed {:?}, expected {:?}", line, expected)),
+                Expect::Value(name, expected) => {
+                    let name = if name == "value" { pending.target.as_deref().unwrap_or(name) } else { name };
+                    match value(interpreter, name) {
+                        Some(actual) if same(&actual, expected) => None,
+                        Some(actual) => Some(format!("{} is {}, expected {}", name, actual, expected)),
+                        None => Some(format!("{} is not a variable here", name)),
+                    }
+                }
+            };
+            // A statement in a loop fails the same way each time: report it once
+            if !self.failures.iter().any(|(line, _)| *line == pending.line) {
+                self.failures.extend(failure.map(|f| (pending.line, f)));
+            }
+        }
+    }
+}
+
+/// Whether a printed line ends with the expected values (after a space, or
+/// as the whole line)
+fn ends_with(line: &str, expected: &str) -> bool {
+    line == expected || line.strip_suffix(expected).is_some_and(|rest| rest.ends_with(' '))
+}
+
+/// The same value, comparing numbers as numbers (so 8 matches 8.0)
+fn same(actual: &str, expected: &str) -> bool {
+    match (actual.parse::<f64>(), expected.parse::<f64>()) {
+        (Ok(a), Ok(e)) => a == e,
+        _ => actual == expected,
+    }
+}
+
+/// The current value of "x" or "a[1][2]" in the innermost call
+fn value(interpreter: &Interpreter, target: &str) -> Option<String> {
+    let (name, rest) = target.split_once('[').unwrap_or((target, ""));
+    let indices: Option<Vec<i64>> = rest.split(['[', ']']).filter(|s| !s.is_empty()).map(|i| i.parse().ok()).collect();
+    match debugger::lookup(interpreter, 0, name, &indices?)? {
+        Lookup::Value(value) => Some(value.get().to_string()),
+        Lookup::Array(_) => None,
+    }
+}
+
+/// The variable a statement assigns, if it is a plain variable or an array
+/// element with literal indices
+fn target(stmt: &Stmt) -> Option<String> {
+    match &stmt.kind {
+        StmtKind::VarDecl(_, ident, _)
+        | StmtKind::Assign(ident, _)
+        | StmtKind::CompoundAssign(ident, _, _)
+        | StmtKind::PreIncrement(ident)
+        | StmtKind::PreDecrement(ident)
+        | StmtKind::PostIncrement(ident)
+        | StmtKind::PostDecrement(ident) => Some(ident.name.to_string()),
+        StmtKind::ArrayAssign(ident, indices, _)
+        | StmtKind::ArrayCompoundAssign(ident, indices, _, _)
+        | StmtKind::ArrayPreIncrement(ident, indices)
+        | StmtKind::ArrayPreDecrement(ident, indices)
+        | StmtKind::ArrayPostIncrement(ident, indices)
+        | StmtKind::ArrayPostDecrement(ident, indices) => {
+            let mut target = ident.name.to_string();
+            for index in indices {
+                let Expr::IntLit(n) = index else { return None };
+                target.push_str(&format!("[{}]", n));
+            }
+            Some(target)
+        }
+        _ => None,
+    }
+}
+
+/// The expectation of a comment that applies to this statement: printed
+/// values for a print(), the value of what an assignment assigns
+fn applies(expects: &[Expect], stmt: &Stmt) -> Option<Expect> {
+    let print = matches!(stmt.kind, StmtKind::Print(_));
+    let target = target(stmt);
+    expects.iter().find_map(|expect| match expect {
+        Expect::Printed(_) if print => Some(expect.clone()),
+        Expect::Value(name, _) if target.is_some() && (name == "value" || Some(name) == target.as_ref()) => {
+            Some(expect.clone())
+        }
+        _ => None,
+    })
+}
+
+/// The possible expectations of the `//` comments that follow code, by line
+fn annotations(code: &str) -> HashMap<usize, Vec<Expect>> {
+    let mut found: HashMap<usize, Vec<Expect>> = HashMap::new();
+    let mut previous = 0;
+    let tokens = Token::lexer(code).spanned().map(|(_, range)| range);
+    for range in tokens.chain(std::iter::once(code.len()..code.len())) {
+        for comment in fmt::comments(code, previous, range.start) {
+            let line_start = code[..comment.start].rfind('\n').map_or(0, |i| i + 1);
+            let Some(text) = code[comment.clone()].strip_prefix("//") else { continue };
+            if code[line_start..comment.start].trim().is_empty() {
+                continue;
+            }
+            let line = code[..comment.start].matches('\n').count() + 1;
+            let expects = found.entry(line).or_default();
+            let values = values(text);
+            if !values.is_empty() {
+                expects.push(Expect::Printed(values.join(" ")));
+            }
+            expects.extend(assignments(text).into_iter().map(|(name, value)| Expect::Value(name, value)));
+        }
+        previous = range.end;
+    }
+    found
+}
+
+/// The values a comment starts with: numbers, true, false, 'c' and "strings"
+/// (unquoted), up to the first word that isn't one
+fn values(text: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let (value, after) = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => match rest[1..].find(quote) {
+                Some(end) => (rest[1..end + 1].to_string(), &rest[end + 2..]),
+                None => break,
+            },
+            _ => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                let word = &rest[..end];
+                if word != "true" && word != "false" && word.parse::<f64>().is_err() {
+                    break;
+                }
+                (word.to_string(), &rest[end..])
+            }
+        };
+        // A value ends at a space or the end of the comment
+        if !(after.is_empty() || after.starts_with(char::is_whitespace)) {
+            break;
+        }
+        values.push(value);
+        rest = after.trim_start();
+    }
+    values
+}
+
+/// Each `name = value` in a comment, where the name may have literal
+/// indices and the value is a number, true, false or a quoted string
+fn assignments(text: &str) -> Vec<(String, String)> {
+    let mut found = Vec::new();
+    for (i, _) in text.match_indices(" = ") {
+        let before = &text[..i];
+        let start = before
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '[' || c == ']'))
+            .map_or(0, |s| s + 1);
+        let name = &before[start..];
+        let valid = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_');
+        let value = values(&text[i + 3..]).into_iter().next();
+        // "value = 6 (ceil of 5.5)": only the first value counts
+        if let (true, Some(value)) = (valid, value) {
+            found.push((name.to_string(), value));
+        }
+    }
+    found
+}
@@ -0,0 +1,440 @@
+// Source formatter (`blur fmt`)
+//
+// Prints a parsed program back as source in one canonical style: four-space
+// indents, braces on the same line, one space around binary operators and
+// only the parentheses precedence needs. The lexer skips comments, so they
+// are recovered from the gaps between tokens, together with directive lines
+// (#blur, #max-loop, ...). Each one is printed before the first statement
+// that starts after it, or at the end of the line when it followed code on
+// the same line. A run of blank lines becomes one. Formatting formatted
+// source changes nothing.
+
+use crate::ast::*;
+use crate::is_directive;
+use crate::lexer::Token;
+use crate::parser::{ParseError, Parser};
+use logos::Logos;
+use std::ops::Range;
+use thiserror::Error;
+
+const INDENT: &str = "    ";
+
+#[derive(Error, Debug)]
+pub enum FormatError {
+    #[error("Parse error at {1}: {0}")]
+    Parse(ParseError, Span),
+    #[error("Unexpected character '{0}' at {1}")]
+    Character(char, Span),
+}
+
+/// A comment or directive line: source the parser doesn't see
+struct Trivia {
+    start: usize,
+    end: usize,
+}
+
+impl From<Range<usize>> for Trivia {
+    fn from(range: Range<usize>) -> Self {
+        Trivia { start: range.start, end: range.end }
+    }
+}
+
+/// Format a whole program
+pub fn format(source: &str) -> Result<String, FormatError> {
+    // Directive lines become spaces, so offsets stay the same
+    let mut directives = Vec::new();
+    let mut code = String::with_capacity(source.len());
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        let text = line.trim_end_matches(['\n', '\r']);
+        if is_directive(text) {
+            let start = offset + (text.len() - text.trim_start().len());
+            directives.push(Trivia { start, end: offset + text.trim_end().len() });
+            code.extend(text.chars().map(|c| if c.is_whitespace() { c } else { ' ' }));
+            code.push_str(&line[text.len()..]);
+        } else {
+            code.push_str(line);
+        }
+        offset += line.len();
+    }
+
+    let mut formatter = Formatter::new(source, &code);
+    let mut trivia = directives;
+    let mut previous = 0;
+    for (token, range) in Token::lexer(&code).spanned() {
+        if token.is_err() {
+            let c = code[range.start..].chars().next().unwrap_or(' ');
+            return Err(FormatError::Character(c, formatter.lines.span(range.start, range.start)));
+        }
+        trivia.extend(comments(&code, previous, range.start).into_iter().map(Trivia::from));
+        previous = range.end;
+    }
+    trivia.extend(comments(&code, previous, code.len()).into_iter().map(Trivia::from));
+    trivia.sort_by_key(|t| t.start);
+    formatter.trivia = trivia;
+
+    let mut parser = Parser::new(&code);
+    let program = parser.parse_program().map_err(|e| FormatError::Parse(e, parser.error_span()))?;
+    formatter.program(&program);
+    Ok(formatter.out)
+}
+
+/// The comments in `code[start..end]`, which holds only whitespace and comments
+pub(crate) fn comments(code: &str, start: usize, end: usize) -> Vec<Range<usize>> {
+    let gap = &code[start..end];
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i < gap.len() {
+        let rest = &gap[i..];
+        let length = if rest.starts_with("//") {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if rest.starts_with("/*") {
+            rest.find("*/").map_or(rest.len(), |e| e + 2)
+        } else {
+            i += rest.chars().next().map_or(1, char::len_utf8);
+            continue;
+        };
+        found.push(start + i..start + i + length);
+        i += length;
+    }
+    found
+}
+
+struct Formatter<'a> {
+    /// The original source (comments and directives are copied from it)
+    source: &'a str,
+    lines: LineIndex,
+    trivia: Vec<Trivia>,
+    /// The first trivia not printed yet
+    next: usize,
+    out: String,
+    depth: usize,
+    /// Source line of the end of what was printed last
+    last_line: usize,
+    /// Nothing printed yet in the current block
+    block_start: bool,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str, code: &str) -> Self {
+        Formatter {
+            source,
+            lines: LineIndex::new(code),
+            trivia: Vec::new(),
+            next: 0,
+            out: String::new(),
+            depth: 0,
+            last_line: 0,
+            block_start: true,
+        }
+    }
+
+
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    /// Start a new item on its own line at `offset`, keeping one blank line
+    /// if the source had any since the last item (always one with `blank`)
+    fn begin(&mut self, offset: usize, blank: bool) {
+        let line = self.lines.line(offset);
+        let had_blank = (self.last_line + 1..line).any(|l| {
+            let end = self.lines.start(l + 1).unwrap_or(self.source.len());
+            self.lines.start(l).is_some_and(|start| self.source[start..end].trim().is_empty())
+        });
+        if !self.block_start && (blank || had_blank) {
+            self.out.push('\n');
+        }
+        self.block_start = false;
+        self.indent();
+    }
+
+    /// Print the trivia before `offset`; the first on its own line gets a
+    /// blank line before it with `blank`. Returns whether none did
+    fn trivia_before(&mut self, offset: usize, mut blank: bool) -> bool {
+        while let Some(t) = self.trivia.get(self.next).filter(|t| t.start < offset) {
+            let (start, end) = (t.start, t.end);
+            let text = self.source[start..end].trim_end().to_string();
+            self.next += 1;
+            if !self.block_start && self.lines.line(start) == self.last_line {
+                // Follows code on the same line
+                self.out.pop();
+                self.out.push_str("  ");
+            } else {
+                self.begin(start, blank);
+                blank = false;
+            }
+            self.out.push_str(&text);
+            self.out.push('\n');
+            self.last_line = self.lines.line(end.saturating_sub(1).max(start));
+        }
+        blank
+    }
+
+    fn program(&mut self, program: &Program) {
+        for (i, func) in program.functions.iter().enumerate() {
+            // Functions are separated by a blank line
+            let blank = self.trivia_before(func.span.start, i > 0);
+            self.begin(func.span.start, blank);
+            let params: Vec<String> = func.params.iter().map(|(ty, name)| declaration(ty, &name.to_string())).collect();
+            self.out.push_str(&format!("{} {}({}) ", func.ret, func.name, params.join(", ")));
+            let open = func.name_span.end + self.source[func.name_span.end..].find('{').unwrap_or(0);
+            self.block(&func.body, open, func.span.end.saturating_sub(1));
+            self.out.push('\n');
+            self.last_line = self.lines.line(func.span.end.saturating_sub(1));
+        }
+        self.trivia_before(usize::MAX, false);
+    }
+
+    /// Print `{ stmts }`, where `open` and `close` are the offsets of the braces
+    fn block(&mut self, stmts: &[Stmt], open: usize, close: usize) {
+        let empty = stmts.is_empty() && self.trivia.get(self.next).is_none_or(|t| t.start >= close);
+        if empty {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push_str("{\n");
+        self.last_line = self.lines.line(open);
+        self.depth += 1;
+        self.block_start = true;
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+        self.trivia_before(close, false);
+        self.depth -= 1;
+        self.block_start = false;
+        self.indent();
+        self.out.push('}');
+        self.last_line = self.lines.line(close);
+    }
+
+    /// Print a statement on its own line
+    fn stmt(&mut self, stmt: &Stmt) {
+        self.trivia_before(stmt.span.start, false);
+        self.begin(stmt.span.start, false);
+        self.inline(stmt);
+        self.out.push('\n');
+        self.last_line = self.lines.line(stmt.span.end.saturating_sub(1).max(stmt.span.start));
+    }
+
+    /// Print a statement from the current position, without the final newline
+    fn inline(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::If(cond, then_branch, else_branch) => {
+                self.out.push_str(&format!("if ({}) ", expr(cond)));
+                self.body(then_branch);
+                if let Some(else_branch) = else_branch {
+                    if matches!(then_branch.kind, StmtKind::Block(_)) {
+                        self.out.push_str(" else ");
+                    } else {
+                        self.out.push('\n');
+                        self.indent();
+                        self.out.push_str("else ");
+                    }
+                    self.body(else_branch);
+                }
+            }
+            StmtKind::While(cond, body) => {
+                self.out.push_str(&format!("while ({}) ", expr(cond)));
+                self.body(body);
+            }
+            StmtKind::For(init, cond, update, body) | StmtKind::SharpFor(init, cond, update, body) => {
+                if matches!(stmt.kind, StmtKind::SharpFor(..)) {
+                    self.out.push_str("sharp ");
+                }
+                let init = init.as_ref().map_or(String::new(), |s| simple(s));
+                let cond = cond.as_ref().map_or(String::new(), |c| format!(" {}", expr(c)));
+                let update = update.as_ref().map_or(String::new(), |s| format!(" {}", simple(s)));
+                self.out.push_str(&format!("for ({};{};{}) ", init, cond, update));
+                self.body(body);
+            }
+            StmtKind::Block(stmts) => self.block(stmts, stmt.span.start, stmt.span.end.saturating_sub(1)),
+            _ => {
+                self.out.push_str(&simple(stmt));
+                self.out.push(';');
+            }
+        }
+    }
+
+    /// The body of a branch or loop: a block, or a statement on the same line
+    fn body(&mut self, stmt: &Stmt) {
+        self.inline(stmt);
+    }
+}
+
+/// A statement without control flow, without its semicolon
+pub(crate) fn simple(stmt: &Stmt) -> String {
+    let indexed = |ident: &Ident, indices: &[Expr]| format!("{}{}", ident, indices_text(indices));
+    match &stmt.kind {
+        StmtKind::VarDecl(ty, ident, init) => match init {
+            Some(init) => format!("{} {} = {}", ty, ident, expr(init)),
+            None => format!("{} {}", ty, ident),
+        },
+        StmtKind::ArrayDecl(ty, ident, dims, init) => {
+            let dims_text: String = dims.iter().map(|d| format!("[{}]", d.as_ref().map_or(String::new(), expr))).collect();
+            let init = match init {
+                Some(ArrayInit::List(values)) => format!(" = {}", list(dims, values)),
+                Some(ArrayInit::Expr(value)) => format!(" = {}", expr(value)),
+                None => String::new(),
+            };
+            format!("{} {}{}{}", ty, ident, dims_text, init)
+        }
+        StmtKind::Assign(ident, value) => format!("{} = {}", ident, expr(value)),
+        StmtKind::ArrayAssign(ident, indices, value) => format!("{} = {}", indexed(ident, indices), expr(value)),
+        StmtKind::CompoundAssign(ident, op, value) => format!("{} {} {}", ident, compound(*op), expr(value)),
+        StmtKind::ArrayCompoundAssign(ident, indices, op, value) => {
+            format!("{} {} {}", indexed(ident, indices), compound(*op), expr(value))
+        }
+        StmtKind::PreIncrement(ident) => format!("++{}", ident),
+        StmtKind::PreDecrement(ident) => format!("--{}", ident),
+        StmtKind::PostIncrement(ident) => format!("{}++", ident),
+        StmtKind::PostDecrement(ident) => format!("{}--", ident),
+        StmtKind::ArrayPreIncrement(ident, indices) => format!("++{}", indexed(ident, indices)),
+        StmtKind::ArrayPreDecrement(ident, indices) => format!("--{}", indexed(ident, indices)),
+        StmtKind::ArrayPostIncrement(ident, indices) => format!("{}++", indexed(ident, indices)),
+        StmtKind::ArrayPostDecrement(ident, indices) => format!("{}--", indexed(ident, indices)),
+        StmtKind::Expr(e) => expr(e),
+        StmtKind::Print(values) => format!("print({})", exprs(values)),
+        StmtKind::Return(Some(value)) => format!("return {}", expr(value)),
+        StmtKind::Return(None) => "return".to_string(),
+        // Control flow is printed by the Formatter
+        StmtKind::If(..) | StmtKind::While(..) | StmtKind::For(..) | StmtKind::SharpFor(..) | StmtKind::Block(_) => {
+            String::new()
+        }
+    }
+}
+
+/// An initializer list, nested again when every dimension is a literal
+fn list(dims: &[Option<Expr>], values: &[Expr]) -> String {
+    let sizes: Option<Vec<usize>> = dims
+        .iter()
+        .map(|d| match d {
+            Some(Expr::IntLit(n)) if *n > 0 => Some(*n as usize),
+            _ => None,
+        })
+        .collect();
+    match sizes {
+        Some(sizes) if sizes.len() > 1 && sizes.iter().product::<usize>() == values.len() => nested(&sizes, values),
+        _ => format!("{{{}}}", exprs(values)),
+    }
+}
+
+fn nested(sizes: &[usize], values: &[Expr]) -> String {
+    if sizes.len() == 1 {
+        return format!("{{{}}}", exprs(values));
+    }
+    let rows: Vec<String> = values.chunks(values.len() / sizes[0]).map(|row| nested(&sizes[1..], row)).collect();
+    format!("{{{}}}", rows.join(", "))
+}
+
+/// "int grid[][]"
+fn declaration(ty: &Type, name: &str) -> String {
+    let mut base = ty;
+    let mut brackets = String::new();
+    while let Type::Array(inner) = base {
+        base = inner;
+        brackets += "[]";
+    }
+    format!("{} {}{}", base, name, brackets)
+}
+
+fn compound(op: CompoundOp) -> &'static str {
+    match op {
+        CompoundOp::Add => "+=",
+        CompoundOp::Sub => "-=",
+        CompoundOp::Mul => "*=",
+        CompoundOp::Div => "/=",
+        CompoundOp::Mod => "%=",
+    }
+}
+
+fn indices_text(indices: &[Expr]) -> String {
+    indices.iter().map(|i| format!("[{}]", expr(i))).collect()
+}
+
+fn exprs(values: &[Expr]) -> String {
+    values.iter().map(expr).collect::<Vec<_>>().join(", ")
+}
+
+/// Binding strength, as in the parser: || is loosest, unary operators and
+/// postfix forms bind tightest
+fn precedence(e: &Expr) -> u8 {
+    match e {
+        Expr::BinOp(_, op, _) => binop(*op).1,
+        Expr::StringRepeat(..) => 6,
+        Expr::UnaryOp(..) | Expr::PreIncrement(_) | Expr::PreDecrement(_) => 7,
+        Expr::ArrayPreIncrement(..) | Expr::ArrayPreDecrement(..) => 7,
+        _ => 8,
+    }
+}
+
+fn binop(op: BinOp) -> (&'static str, u8) {
+    match op {
+        BinOp::Or => ("||", 1),
+        BinOp::And => ("&&", 2),
+        BinOp::Eq => ("==", 3),
+        BinOp::Ne => ("!=", 3),
+        BinOp::Lt => ("<", 4),
+        BinOp::Gt => (">", 4),
+        BinOp::Le => ("<=", 4),
+        BinOp::Ge => (">=", 4),
+        BinOp::Add => ("+", 5),
+        BinOp::Sub => ("-", 5),
+        BinOp::Mul => ("*", 6),
+        BinOp::Div => ("/", 6),
+        BinOp::Mod => ("%", 6),
+    }
+}
+
+/// `e` in parentheses if it binds looser than `min`
+fn operand(e: &Expr, min: u8) -> String {
+    if precedence(e) < min {
+        format!("({})", expr(e))
+    } else {
+        expr(e)
+    }
+}
+
+pub(crate) fn expr(e: &Expr) -> String {
+    match e {
+        Expr::IntLit(n) => n.to_string(),
+        Expr::FloatLit(x) => {
+            // The lexer needs digits on both sides of the point
+            let text = x.to_string();
+            if text.contains('.') { text } else { format!("{}.0", text) }
+        }
+        Expr::BoolLit(b) => b.to_string(),
+        Expr::CharLit(c) => format!("'{}'", c),
+        // Escapes are kept as written
+        Expr::StringLit(s) => format!("\"{}\"", s),
+        Expr::Var(ident) => ident.to_string(),
+        Expr::ArrayAccess(ident, indices) => format!("{}{}", ident, indices_text(indices)),
+        Expr::BinOp(left, op, right) => {
+            let (symbol, level) = binop(*op);
+            // Left-associative: a right operand at the same level needs parentheses
+            format!("{} {} {}", operand(left, level), symbol, operand(right, level + 1))
+        }
+        Expr::StringRepeat(text, count) => format!("{} * {}", operand(text, 6), operand(count, 7)),
+        Expr::UnaryOp(op, operand_expr) => {
+            let symbol = if *op == UnaryOp::Neg { "-" } else { "!" };
+            let text = operand(operand_expr, 7);
+            // "- -x" and "-(--x)" must not become "--x"
+            if *op == UnaryOp::Neg && text.starts_with('-') {
+                format!("-({})", text)
+            } else {
+                format!("{}{}", symbol, text)
+            }
+        }
+        Expr::PreIncrement(ident) => format!("++{}", ident),
+        Expr::PreDecrement(ident) => format!("--{}", ident),
+        Expr::PostIncrement(ident) => format!("{}++", ident),
+        Expr::PostDecrement(ident) => format!("{}--", ident),
+        Expr::ArrayPreIncrement(ident, indices) => format!("++{}{}", ident, indices_text(indices)),
+        Expr::ArrayPreDecrement(ident, indices) => format!("--{}{}", ident, indices_text(indices)),
+        Expr::ArrayPostIncrement(ident, indices) => format!("{}{}++", ident, indices_text(indices)),
+        Expr::ArrayPostDecrement(ident, indices) => format!("{}{}--", ident, indices_text(indices)),
+        Expr::Call(name, args) => format!("{}({})", name, exprs(args)),
+    }
+}
@@ -0,0 +1,94 @@
+// Host functions: Rust closures registered with `Interpreter::register_fn`
+//
+// They extend the builtin set without touching the engines. Arguments arrive
+// as BlurValues with their full history, like the parameters of a Blur
+// function. A host function can return a plain value or a BlurValue; the
+// latter keeps its history when the call is passed straight to a Blur function.
+
+use crate::ast::Type;
+use crate::interpreter::{Arg, BlurValue, RuntimeError, Value};
+use std::rc::Rc;
+
+/// What a host function returns
+pub enum HostValue {
+    Value(Value),
+    Blur(BlurValue),
+}
+
+impl From<Value> for HostValue {
+    fn from(value: Value) -> Self {
+        HostValue::Value(value)
+    }
+}
+
+impl From<BlurValue> for HostValue {
+    fn from(value: BlurValue) -> Self {
+        HostValue::Blur(value)
+    }
+}
+
+impl HostValue {
+    /// The value the call evaluates to in an expression
+    pub fn get(self) -> Value {
+        match self {
+            HostValue::Value(value) => value,
+            HostValue::Blur(blur_val) => blur_val.get(),
+        }
+    }
+
+    /// The call passed as an argument of a Blur function
+    pub(crate) fn into_arg(self) -> Arg {
+        match self {
+            HostValue::Blur(blur_val) => Arg::Scalar(blur_val),
+            HostValue::Value(Value::Array(arr)) => Arg::Array(arr),
+            HostValue::Value(value) => {
+                let mut blur_val = BlurValue::new(Type::Float);
+                blur_val.push(value.to_f64());
+                Arg::Scalar(blur_val)
+            }
+        }
+    }
+}
+
+type HostFn = dyn Fn(&[BlurValue]) -> Result<HostValue, RuntimeError>;
+
+#[derive(Clone)]
+pub struct HostFunction {
+    pub name: String,
+    pub arity: usize,
+    func: Rc<HostFn>,
+}
+
+impl HostFunction {
+    pub fn new<F, R>(name: &str, arity: usize, func: F) -> Self
+    where
+        F: Fn(&[BlurValue]) -> Result<R, RuntimeError> + 'static,
+        R: Into<HostValue>,
+    {
+        HostFunction {
+            name: name.to_string(),
+            arity,
+            func: Rc::new(move |args| func(args).map(Into::into)),
+        }
+    }
+
+    /// Check the arguments and call the closure
+    pub(crate) fn call(&self, args: Vec<Arg>) -> Result<HostValue, RuntimeError> {
+        if args.len() != self.arity {
+            return Err(RuntimeError::TypeMismatch(format!(
+                "{}() expects {} argument(s), got {}", self.name, self.arity, args.len()
+            )));
+        }
+        let args = args
+            .into_iter()
+            .enumerate()
+            .map(|(i, arg)| match arg {
+                Arg::Scalar(blur_val) => Ok(blur_val),
+                Arg::Array(_) => Err(RuntimeError::TypeMismatch(format!(
+                    "{}() expects a scalar as argument {}", self.name, i + 1
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        (self.func)(&args)
+    }
+}
@@ -0,0 +1,150 @@
+// Netpbm image input/output (PGM and PPM, plain and binary)
+//
+// P2/P5 are grayscale, P3/P6 are RGB. Plain formats (P2/P3) store pixels
+// as ASCII numbers, binary formats (P5/P6) as one byte per sample, or two
+// big-endian bytes when the maximum value is above 255.
+
+use std::fs;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ImageError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("not a Netpbm image: {0}")]
+    Format(String),
+}
+
+/// A decoded image with samples stored row by row, channel by channel
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// 1 for grayscale, 3 for RGB
+    pub channels: usize,
+    pub maxval: u32,
+    pub samples: Vec<u32>,
+}
+
+/// Reads header fields and ASCII samples, skipping whitespace and # comments
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn skip_space(&mut self) {
+        while let Some(&b) = self.data.get(self.pos) {
+            if b == b'#' {
+                while self.data.get(self.pos).is_some_and(|&b| b != b'\n') {
+                    self.pos += 1;
+                }
+            } else if b.is_ascii_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn token(&mut self) -> Result<&'a [u8], ImageError> {
+        self.skip_space();
+        let start = self.pos;
+        while self.data.get(self.pos).is_some_and(|b| !b.is_ascii_whitespace() && *b != b'#') {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(ImageError::Format("unexpected end of file".to_string()));
+        }
+        Ok(&self.data[start..self.pos])
+    }
+
+    fn number(&mut self) -> Result<u32, ImageError> {
+        let token = self.token()?;
+        std::str::from_utf8(token)
+            .ok()
+            .and_then(|t| t.parse().ok())
+            .ok_or_else(|| ImageError::Format(format!("invalid number '{}'", String::from_utf8_lossy(token))))
+    }
+}
+
+pub fn load(path: &str) -> Result<Image, ImageError> {
+    let data = fs::read(path)?;
+    let mut reader = Reader { data: &data, pos: 0 };
+
+    let magic = reader.token()?;
+    let (channels, binary) = match magic {
+        b"P2" => (1, false),
+        b"P3" => (3, false),
+        b"P5" => (1, true),
+        b"P6" => (3, true),
+        _ => return Err(ImageError::Format(format!(
+            "unsupported magic number '{}'", String::from_utf8_lossy(magic)
+        ))),
+    };
+    let width = reader.number()? as usize;
+    let height = reader.number()? as usize;
+    let maxval = reader.number()?;
+    if maxval == 0 || maxval > 65535 {
+        return Err(ImageError::Format(format!("invalid maximum value {}", maxval)));
+    }
+
+    let too_big = || ImageError::Format(format!("{}x{} image is too large", width, height));
+    let count = width.checked_mul(height).and_then(|n| n.checked_mul(channels)).ok_or_else(too_big)?;
+    // Check the header against the file before allocating anything for it
+    let samples = if binary {
+        // Exactly one whitespace byte separates the header from the pixels
+        let start = reader.pos + 1;
+        let bytes_per_sample = if maxval > 255 { 2 } else { 1 };
+        let size = count.checked_mul(bytes_per_sample).ok_or_else(too_big)?;
+        let available = data.len().saturating_sub(start);
+        if available < size {
+            return Err(ImageError::Format(format!(
+                "header gives {} bytes of pixel data, the file has only {}", size, available
+            )));
+        }
+        // Anything after the raster is ignored
+        let raw = &data[start..start + size];
+        if bytes_per_sample == 1 {
+            raw.iter().map(|&b| b as u32).collect()
+        } else {
+            raw.chunks(2).map(|b| u32::from(b[0]) << 8 | u32::from(b[1])).collect()
+        }
+    } else {
+        // Every ASCII sample takes at least a digit and a separator
+        if count > (data.len() - reader.pos) / 2 {
+            return Err(ImageError::Format("pixel data is truncated".to_string()));
+        }
+        (0..count).map(|_| reader.number()).collect::<Result<Vec<_>, _>>()?
+    };
+
+    Ok(Image { width, height, channels, maxval, samples })
+}
+
+/// Write an image; `plain` selects ASCII (P2/P3) instead of binary (P5/P6)
+pub fn save(path: &str, image: &Image, plain: bool) -> Result<(), ImageError> {
+    let magic = match (image.channels, plain) {
+        (1, true) => "P2",
+        (1, false) => "P5",
+        (_, true) => "P3",
+        (_, false) => "P6",
+    };
+    let mut out = format!("{}\n{} {}\n{}\n", magic, image.width, image.height, image.maxval).into_bytes();
+
+    if plain {
+        let row_len = image.width * image.channels;
+        for row in image.samples.chunks(row_len.max(1)) {
+            let values: Vec<String> = row.iter().map(|s| s.to_string()).collect();
+            out.extend_from_slice(values.join(" ").as_bytes());
+            out.push(b'\n');
+        }
+    } else if image.maxval > 255 {
+        for &sample in &image.samples {
+            out.extend_from_slice(&(sample as u16).to_be_bytes());
+        }
+    } else {
+        out.extend(image.samples.iter().map(|&s| s as u8));
+    }
+
+    fs::write(path, out)?;
+    Ok(())
+}
@@ -1,6 +1,14 @@
 use crate::ast::*;
+use crate::builtins::{self, Builtin, Param, Target};
+use crate::host::{HostFunction, HostValue};
+use crate::symbol::Symbol;
+use crate::compiler::{self, IncResult};
+use crate::trace::{LoopKind, Trace};
+use crate::vm;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
 use thiserror::Error;
 
 // Global blur factor for weighted averaging
@@ -8,7 +16,7 @@ use thiserror::Error;
 // blur = 0.9 means recent values count more (default)
 // blur = 0.0 means only most recent value counts (no blur)
 thread_local! {
-    static DECAY: RefCell<f64> = RefCell::new(0.9);
+    static DECAY: RefCell<f64> = const { RefCell::new(0.9) };
 }
 
 pub fn set_decay(decay: f64) {
@@ -19,25 +27,296 @@ pub fn get_decay() -> f64 {
     DECAY.with(|d| *d.borrow())
 }
 
-#[derive(Error, Debug)]
+// How many recent entries a history always keeps as raw values. Older entries
+// are folded into its Summary once their weight underflows to zero (so never
+// at blur 1.0); 0 keeps everything raw.
+thread_local! {
+    static RAW_HISTORY: RefCell<usize> = const { RefCell::new(64) };
+}
+
+pub fn set_raw_history(entries: usize) {
+    RAW_HISTORY.with(|r| *r.borrow_mut() = entries);
+}
+
+pub fn get_raw_history() -> usize {
+    RAW_HISTORY.with(|r| *r.borrow())
+}
+
+#[derive(Error, Debug, Clone)]
 pub enum RuntimeError {
     #[error("Undefined variable: {0}")]
     UndefinedVar(String),
     #[error("Undefined function: {0}")]
     UndefinedFunc(String),
+    #[error("Function {0}() is defined by both the program and the host")]
+    HostConflict(String),
+    #[error("Function {0}() is already a built-in")]
+    BuiltinConflict(String),
+    #[error("Host function {0}() is already registered")]
+    DuplicateHost(String),
     #[error("Division by zero")]
     DivisionByZero,
-    #[error("Array index out of bounds: {index} for array of size {size}")]
-    IndexOutOfBounds { index: i64, size: usize },
+    #[error("Array index out of bounds: {} for array of size {}", brackets(.indices), brackets(.dims))]
+    IndexOutOfBounds { indices: Vec<i64>, dims: Vec<usize> },
+    #[error("Invalid array size: {0}")]
+    InvalidArraySize(f64),
+    #[error("Array too large: {}", brackets(.0))]
+    ArrayTooLarge(Vec<usize>),
+    #[error("Cannot pop from empty array: {0}")]
+    EmptyArray(String),
+    #[error("Type mismatch: {0}")]
+    TypeMismatch(String),
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("I/O error: {0}")]
+    Io(String),
+    #[error("Assertion failed: {0}")]
+    AssertionFailed(String),
+    #[error("{0}")]
+    LimitExceeded(Limit),
+    #[error("Stopped by the debugger")]
+    Stopped,
+}
+
+/// An execution limit a program ran into, with the limit's value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    LoopIterations(usize),
+    /// Of a while or sharp for loop
+    Iterations(LoopKind, usize),
+    Fuel(u64),
+    CallDepth(usize),
+    History(usize),
+    ArrayElements(usize),
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::LoopIterations(n) => write!(f, "for loop hit {} iteration limit (use 'sharp for' for unlimited)", n),
+            Limit::Iterations(kind, n) => write!(f, "{} loop hit {} iteration limit (raise it with --max-iterations)", kind.as_str(), n),
+            Limit::Fuel(n) => write!(f, "out of fuel after {} steps", n),
+            Limit::CallDepth(n) => write!(f, "maximum call depth of {} exceeded", n),
+            Limit::History(n) => write!(f, "variable history exceeded {} entries", n),
+            Limit::ArrayElements(n) => write!(f, "array would have more than {} elements", n),
+        }
+    }
+}
+
+/// Limits that stop runaway programs; None means unlimited
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExecutionLimits {
+    /// Iterations of each regular for loop (blur semantics can make them run ~forever)
+    pub max_loop_iterations: Option<usize>,
+    /// Iterations of each while and sharp for loop
+    pub max_iterations: Option<usize>,
+    /// Statements executed in total
+    pub fuel: Option<u64>,
+    /// Nested function calls, including blur() itself
+    pub max_call_depth: Option<usize>,
+    /// History entries of any one variable or array element
+    pub max_history: Option<usize>,
+    /// Elements of any one declared array
+    pub max_array: Option<usize>,
+}
+
+impl Default for ExecutionLimits {
+    fn default() -> Self {
+        ExecutionLimits {
+            max_loop_iterations: Some(1000),
+            max_iterations: None,
+            fuel: None,
+            max_call_depth: Some(1000),
+            max_history: None,
+            max_array: None,
+        }
+    }
+}
+
+impl ExecutionLimits {
+    /// Names shared by CLI flags (--fuel 5000) and directives (#fuel 5000)
+    pub const OPTIONS: &'static [&'static str] = &["max-loop", "max-iterations", "fuel", "max-depth", "max-history", "max-array"];
+
+    /// Set a limit by option name; 0 means unlimited
+    pub fn set(&mut self, option: &str, value: &str) -> Result<(), String> {
+        let n: u64 = value
+            .parse()
+            .map_err(|_| format!("{} value must be a non-negative integer", option))?;
+        let limit = (n > 0).then_some(n);
+        match option {
+            "max-loop" => self.max_loop_iterations = limit.map(|n| n as usize),
+            "max-iterations" => self.max_iterations = limit.map(|n| n as usize),
+            "fuel" => self.fuel = limit,
+            "max-depth" => self.max_call_depth = limit.map(|n| n as usize),
+            "max-history" => self.max_history = limit.map(|n| n as usize),
+            "max-array" => self.max_array = limit.map(|n| n as usize),
+            _ => return Err(format!("unknown limit: {}", option)),
+        }
+        Ok(())
+    }
+
+    /// Fail if a loop of this kind has already run as often as it may
+    pub(crate) fn check_loop(&self, kind: LoopKind, iterations: usize) -> Result<(), RuntimeError> {
+        let max = if kind == LoopKind::For { self.max_loop_iterations } else { self.max_iterations };
+        match max {
+            Some(max) if iterations >= max => Err(RuntimeError::LimitExceeded(match kind {
+                LoopKind::For => Limit::LoopIterations(max),
+                _ => Limit::Iterations(kind, max),
+            })),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_fuel(&self, steps: u64) -> Result<(), RuntimeError> {
+        match self.fuel {
+            Some(fuel) if steps > fuel => Err(RuntimeError::LimitExceeded(Limit::Fuel(fuel))),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_call_depth(&self, depth: usize) -> Result<(), RuntimeError> {
+        match self.max_call_depth {
+            Some(max) if depth >= max => Err(RuntimeError::LimitExceeded(Limit::CallDepth(max))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail if pushing `count` more values would take a variable past the history
+    /// limit (sharp variables never grow)
+    pub(crate) fn check_history(&self, var: &BlurValue, count: usize) -> Result<(), RuntimeError> {
+        match self.max_history {
+            Some(max) if !var.sharp && var.history_len() + count > max => {
+                Err(RuntimeError::LimitExceeded(Limit::History(max)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail if an array declared with these dimensions would have more
+    /// elements than the limit, or than a usize can count
+    pub(crate) fn check_array(&self, dims: &[usize]) -> Result<(), RuntimeError> {
+        let count = element_count(dims).ok_or_else(|| RuntimeError::ArrayTooLarge(dims.to_vec()))?;
+        match self.max_array {
+            Some(max) if count > max => Err(RuntimeError::LimitExceeded(Limit::ArrayElements(max))),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn element_count(dims: &[usize]) -> Option<usize> {
+    dims.iter().try_fold(1usize, |count, &d| count.checked_mul(d))
+}
+
+/// The size a dimension expression gives, which must be a whole number that isn't negative
+pub(crate) fn array_size(value: &Value) -> Result<usize, RuntimeError> {
+    let size = value.to_f64();
+    if size >= 0.0 && size.fract() == 0.0 && size < i64::MAX as f64 {
+        Ok(size as usize)
+    } else {
+        Err(RuntimeError::InvalidArraySize(size))
+    }
+}
+
+/// Format indices or dimensions C-style: [3][70]
+fn brackets<T: std::fmt::Display>(values: &[T]) -> String {
+    values.iter().map(|v| format!("[{}]", v)).collect()
+}
+
+/// The oldest entries of a history, folded away.
+///
+/// Only entries whose weight (decay^age) has underflowed to exactly zero are
+/// folded, so the average comes out bit for bit the same as with every entry
+/// kept. Raising the blur afterwards (in the REPL) leaves them at zero weight.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    /// What the folded entries add to the weighted sum: zero, or NaN if one of
+    /// them wasn't finite
+    pub weighted: f64,
+    /// Plain sum of the folded values
+    pub sum: f64,
+    /// Number of entries folded in
+    pub count: usize,
+}
+
+impl Summary {
+    /// Plain average of the folded entries on their own
+    pub fn mean(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+
+    /// Weighted average of the summary followed by `values`: the most recent
+    /// value has weight 1, older ones decay^age
+    fn average(&self, values: impl ExactSizeIterator<Item = f64>) -> f64 {
+        let decay = get_decay();
+        let n = values.len();
+        // The folded entries are older than every raw one, so all they add is zero
+        let mut weighted_sum = self.weighted;
+        let mut weight_total = 0.0;
+        for (i, val) in values.enumerate() {
+            let age = (n - 1 - i) as f64; // 0 for most recent
+            let weight = decay.powf(age);
+            weighted_sum += val * weight;
+            weight_total += weight;
+        }
+        weighted_sum / weight_total
+    }
+
+    /// Add `values` (oldest first) after the entries already folded
+    fn fold(&mut self, values: impl Iterator<Item = f64>) {
+        for value in values {
+            self.weighted += value * 0.0;
+            self.sum += value;
+            self.count += 1;
+        }
+    }
+
+    /// Fold the entries of `history` whose weight is zero, once there are as
+    /// many of them as entries worth keeping
+    fn compact<T: Copy>(&mut self, history: &mut Vec<T>, to_f64: impl Fn(T) -> f64) {
+        let raw = get_raw_history();
+        if raw == 0 || history.len() < raw.saturating_mul(2) {
+            return;
+        }
+        let Some(zero) = zero_weight_age(get_decay()) else {
+            return;
+        };
+        let keep = raw.max(zero);
+        if history.len() >= keep.saturating_mul(2) {
+            let folded = history.len() - keep;
+            self.fold(history.drain(..folded).map(to_f64));
+        }
+    }
+}
+
+/// The smallest age whose weight underflows to zero (None if weights never do)
+fn zero_weight_age(decay: f64) -> Option<usize> {
+    if decay >= 1.0 {
+        return None;
+    }
+    if decay <= 0.0 {
+        return Some(1);
+    }
+    // The smallest subnormal is MIN_POSITIVE * EPSILON
+    let smallest = f64::MIN_POSITIVE * f64::EPSILON;
+    let mut age = (smallest.ln() / decay.ln()).floor().max(1.0) as usize;
+    while decay.powf(age as f64) > 0.0 {
+        age += 1;
+    }
+    while age > 1 && decay.powf((age - 1) as f64) == 0.0 {
+        age -= 1;
+    }
+    Some(age)
 }
 
 /// A Blur value - stores the history of all assigned values
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlurValue {
     pub var_type: Type,
-    pub history: Vec<f64>, // All values stored as f64 for averaging
+    pub history: Vec<f64>, // Recent values stored as f64 for averaging
     pub bool_history: Vec<bool>, // Separate history for booleans
     pub string_history: Vec<Vec<char>>, // Per-position character history for strings
+    pub summary: Summary, // Older entries of history or bool_history
+    pub string_summary: Vec<Summary>, // Older entries of each string position
     pub sharp: bool, // If true, don't average - just use last value (sharp mode)
 }
 
@@ -48,18 +327,14 @@ impl BlurValue {
             history: Vec::new(),
             bool_history: Vec::new(),
             string_history: Vec::new(),
+            summary: Summary::default(),
+            string_summary: Vec::new(),
             sharp: false,
         }
     }
 
     pub fn new_sharp(var_type: Type) -> Self {
-        BlurValue {
-            var_type,
-            history: Vec::new(),
-            bool_history: Vec::new(),
-            string_history: Vec::new(),
-            sharp: true,
-        }
+        BlurValue { sharp: true, ..Self::new(var_type) }
     }
 
     pub fn push(&mut self, value: f64) {
@@ -68,6 +343,7 @@ impl BlurValue {
             self.history.clear();
         }
         self.history.push(value);
+        self.summary.compact(&mut self.history, |v| v);
     }
 
     pub fn push_bool(&mut self, value: bool) {
@@ -75,6 +351,7 @@ impl BlurValue {
             self.bool_history.clear();
         }
         self.bool_history.push(value);
+        self.summary.compact(&mut self.bool_history, |b| if b { 1.0 } else { 0.0 });
     }
 
     /// Push a string value - adds each non-space character to its position's history
@@ -90,8 +367,23 @@ impl BlurValue {
             // Extend history if needed
             while self.string_history.len() <= i {
                 self.string_history.push(Vec::new());
+                self.string_summary.push(Summary::default());
             }
             self.string_history[i].push(c);
+            self.string_summary[i].compact(&mut self.string_history[i], char_code);
+        }
+    }
+
+    /// Push a runtime value, converting it according to the variable's type
+    pub fn push_value(&mut self, value: &Value) {
+        match &self.var_type {
+            Type::Bool => self.push_bool(value.to_bool()),
+            Type::String => {
+                if let Value::String(s) = value {
+                    self.push_string(s);
+                }
+            }
+            _ => self.push(value.to_f64()),
         }
     }
 
@@ -105,116 +397,211 @@ impl BlurValue {
     /// Compute weighted average with decay factor
     /// decay = 1.0: pure average (all weights equal)
     /// decay < 1.0: recent values weighted more (weight = decay^age)
-    fn weighted_avg(values: &[f64]) -> f64 {
-        if values.is_empty() {
+    fn weighted_avg(&self) -> f64 {
+        if self.history.is_empty() && self.summary.count == 0 {
             return 0.0;
         }
-        let decay = get_decay();
-        if decay >= 1.0 {
-            // Pure average (fast path)
-            return values.iter().sum::<f64>() / values.len() as f64;
-        }
-        // Weighted average: most recent (last) has weight 1, older has decay^age
-        let n = values.len();
-        let mut weighted_sum = 0.0;
-        let mut weight_total = 0.0;
-        for (i, &val) in values.iter().enumerate() {
-            let age = (n - 1 - i) as f64; // 0 for most recent
-            let weight = decay.powf(age);
-            weighted_sum += val * weight;
-            weight_total += weight;
-        }
-        weighted_sum / weight_total
+        self.summary.average(self.history.iter().copied())
     }
 
     /// Get the current averaged value
     pub fn get(&self) -> Value {
+        let empty = self.history_len() == 0;
         match &self.var_type {
             Type::Int => {
-                if self.history.is_empty() {
+                if empty {
                     Value::Int(0)
                 } else {
-                    let avg = Self::weighted_avg(&self.history);
-                    Value::Int(avg.ceil() as i64)
-                }
-            }
-            Type::Float => {
-                if self.history.is_empty() {
-                    Value::Float(0.0)
-                } else {
-                    Value::Float(Self::weighted_avg(&self.history))
+                    Value::Int(self.weighted_avg().ceil() as i64)
                 }
             }
+            Type::Float => Value::Float(self.weighted_avg()),
             Type::Bool => {
-                if self.bool_history.is_empty() {
+                if empty {
                     Value::Bool(false)
                 } else {
-                    let decay = get_decay();
-                    if decay >= 1.0 {
-                        // Pure average
-                        let true_count = self.bool_history.iter().filter(|&&b| b).count();
-                        let ratio = true_count as f64 / self.bool_history.len() as f64;
-                        Value::Bool(ratio >= 0.5)
-                    } else {
-                        // Weighted average for bools
-                        let n = self.bool_history.len();
-                        let mut weighted_true = 0.0;
-                        let mut weight_total = 0.0;
-                        for (i, &b) in self.bool_history.iter().enumerate() {
-                            let age = (n - 1 - i) as f64;
-                            let weight = decay.powf(age);
-                            if b {
-                                weighted_true += weight;
-                            }
-                            weight_total += weight;
-                        }
-                        Value::Bool(weighted_true / weight_total >= 0.5)
-                    }
+                    // Ratio of (weighted) true values
+                    let values = self.bool_history.iter().map(|&b| if b { 1.0 } else { 0.0 });
+                    Value::Bool(self.summary.average(values) >= 0.5)
                 }
             }
             Type::Char => {
-                if self.history.is_empty() {
+                if empty {
                     Value::Char('\0')
                 } else {
-                    let avg = Self::weighted_avg(&self.history);
-                    Value::Char(avg.ceil() as u8 as char)
+                    Value::Char(self.weighted_avg().ceil() as u8 as char)
                 }
             }
             Type::String => {
-                if self.string_history.is_empty() {
-                    Value::String(String::new())
-                } else {
-                    let decay = get_decay();
-                    let s: String = self.string_history.iter().map(|pos_history| {
-                        if pos_history.is_empty() {
-                            ' ' // No chars at this position yet
-                        } else if decay >= 1.0 {
-                            // Pure average
-                            let avg = pos_history.iter()
-                                .map(|c| *c as u32 as f64)
-                                .sum::<f64>() / pos_history.len() as f64;
-                            let code = avg.ceil() as u32;
-                            char::from_u32(code).unwrap_or(' ')
-                        } else {
-                            // Weighted average
-                            let values: Vec<f64> = pos_history.iter()
-                                .map(|c| *c as u32 as f64)
-                                .collect();
-                            let avg = Self::weighted_avg(&values);
-                            let code = avg.ceil() as u32;
-                            char::from_u32(code).unwrap_or(' ')
-                        }
-                    }).collect();
-                    Value::String(s)
-                }
+                let s: String = self.string_history.iter().zip(&self.string_summary).map(|(pos_history, summary)| {
+                    if pos_history.is_empty() && summary.count == 0 {
+                        ' ' // No chars at this position yet
+                    } else {
+                        let avg = summary.average(pos_history.iter().copied().map(char_code));
+                        let code = avg.ceil() as u32;
+                        char::from_u32(code).unwrap_or(' ')
+                    }
+                }).collect();
+                Value::String(s)
             }
-            Type::Void => Value::Void,
+            Type::Void | Type::Array(_) => Value::Void,
+        }
+    }
+
+    /// Number of entries in the history, folded ones included (for strings, at
+    /// the longest position)
+    pub fn history_len(&self) -> usize {
+        match self.var_type {
+            Type::Bool => self.summary.count + self.bool_history.len(),
+            Type::String => self
+                .string_history
+                .iter()
+                .zip(&self.string_summary)
+                .map(|(h, summary)| summary.count + h.len())
+                .max()
+                .unwrap_or(0),
+            _ => self.summary.count + self.history.len(),
         }
     }
 
     /// Get the raw averaged float value (for increment operations)
     pub fn get_raw(&self) -> f64 {
-        Self::weighted_avg(&self.history)
+        self.weighted_avg()
+    }
+}
+
+fn char_code(c: char) -> f64 {
+    c as u32 as f64
+}
+
+/// An array - every element keeps its own history.
+/// Multidimensional arrays store their elements flat, in row-major order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlurArray {
+    pub elem_type: Type,
+    pub dims: Vec<usize>,
+    pub elems: Vec<BlurValue>,
+}
+
+impl BlurArray {
+    pub fn new(elem_type: Type, dims: Vec<usize>) -> Self {
+        let size = dims.iter().product();
+        BlurArray {
+            elems: (0..size).map(|_| BlurValue::new(elem_type.clone())).collect(),
+            elem_type,
+            dims,
+        }
+    }
+
+    /// Like `new`, but fails instead of aborting when the elements don't fit in memory
+    pub fn try_new(elem_type: Type, dims: Vec<usize>) -> Result<Self, RuntimeError> {
+        let mut elems = Vec::new();
+        match element_count(&dims) {
+            Some(size) if elems.try_reserve_exact(size).is_ok() => {
+                elems.extend((0..size).map(|_| BlurValue::new(elem_type.clone())));
+                Ok(BlurArray { elem_type, dims, elems })
+            }
+            _ => Err(RuntimeError::ArrayTooLarge(dims)),
+        }
+    }
+
+    /// Number of elements along the first dimension
+    pub fn len(&self) -> usize {
+        self.dims.first().copied().unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Flat position of the element at `indices`
+    fn offset(&self, indices: &[i64]) -> Result<usize, RuntimeError> {
+        if indices.len() != self.dims.len() {
+            return Err(RuntimeError::TypeMismatch(format!(
+                "array has {} dimension(s), got {} index(es)",
+                self.dims.len(),
+                indices.len()
+            )));
+        }
+        let mut offset = 0;
+        for (&index, &dim) in indices.iter().zip(&self.dims) {
+            if index < 0 || index as usize >= dim {
+                return Err(RuntimeError::IndexOutOfBounds {
+                    indices: indices.to_vec(),
+                    dims: self.dims.clone(),
+                });
+            }
+            offset = offset * dim + index as usize;
+        }
+        Ok(offset)
+    }
+
+    pub fn elem(&self, indices: &[i64]) -> Result<&BlurValue, RuntimeError> {
+        let offset = self.offset(indices)?;
+        Ok(&self.elems[offset])
+    }
+
+    pub fn elem_mut(&mut self, indices: &[i64]) -> Result<&mut BlurValue, RuntimeError> {
+        let offset = self.offset(indices)?;
+        Ok(&mut self.elems[offset])
+    }
+
+    /// Grow or shrink a one-dimensional array; new elements start with empty history
+    pub fn resize(&mut self, size: usize) {
+        let elem_type = self.elem_type.clone();
+        self.elems.resize_with(size, || BlurValue::new(elem_type.clone()));
+        self.dims = vec![size];
+    }
+
+    /// Append an element to a one-dimensional array
+    pub fn push(&mut self, elem: BlurValue) -> Result<(), RuntimeError> {
+        if self.dims.len() != 1 {
+            return Err(RuntimeError::TypeMismatch(
+                "push() requires a one-dimensional array".to_string(),
+            ));
+        }
+        self.elems.push(elem);
+        self.dims[0] += 1;
+        Ok(())
+    }
+
+    /// Remove the last element of a one-dimensional array
+    pub fn pop(&mut self) -> Result<Option<BlurValue>, RuntimeError> {
+        if self.dims.len() != 1 {
+            return Err(RuntimeError::TypeMismatch(
+                "pop() requires a one-dimensional array".to_string(),
+            ));
+        }
+        let elem = self.elems.pop();
+        self.dims[0] = self.elems.len();
+        Ok(elem)
+    }
+
+    /// Write `elems` as nested lists, one level per dimension
+    fn fmt_dims(f: &mut std::fmt::Formatter<'_>, dims: &[usize], elems: &[BlurValue]) -> std::fmt::Result {
+        write!(f, "[")?;
+        match dims {
+            [] | [_] => {
+                let values: Vec<String> = elems.iter().map(|e| e.get().to_string()).collect();
+                write!(f, "{}", values.join(", "))?;
+            }
+            [rows, rest @ ..] => {
+                let stride = rest.iter().product::<usize>();
+                for row in 0..*rows {
+                    if row > 0 {
+                        write!(f, ", ")?;
+                    }
+                    Self::fmt_dims(f, rest, &elems[row * stride..(row + 1) * stride])?;
+                }
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+impl std::fmt::Display for BlurArray {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Self::fmt_dims(f, &self.dims, &self.elems)
     }
 }
 
@@ -226,6 +613,7 @@ pub enum Value {
     Bool(bool),
     Char(char),
     String(String),
+    Array(BlurArray),
     Void,
 }
 
@@ -234,7 +622,8 @@ impl Value {
         match self {
             Value::Int(n) => *n as f64,
             Value::Float(f) => *f,
-            Value::Bool(b) => if *b { 1.0 } else { 0.0 },
+            Value::Bool(true) => 1.0,
+            Value::Bool(false) => 0.0,
             Value::Char(c) => *c as u8 as f64,
             _ => 0.0,
         }
@@ -259,27 +648,230 @@ impl std::fmt::Display for Value {
             Value::Bool(b) => write!(f, "{}", b),
             Value::Char(c) => write!(f, "{}", c),
             Value::String(s) => write!(f, "{}", s),
+            Value::Array(arr) => write!(f, "{}", arr),
             Value::Void => write!(f, "void"),
         }
     }
 }
 
+/// Variables of one kind in a scope, indexed by the slot the resolver assigned
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Slots<T> {
+    /// Symbol::EMPTY for slots whose declaration hasn't run
+    pub names: Vec<Symbol>,
+    pub values: Vec<T>,
+}
+
+impl<T: Clone> Slots<T> {
+    fn new() -> Self {
+        Slots { names: Vec::new(), values: Vec::new() }
+    }
+
+    pub fn get(&self, slot: u32, name: Symbol) -> Option<&T> {
+        let slot = slot as usize;
+        (self.names.get(slot) == Some(&name)).then(|| &self.values[slot])
+    }
+
+    pub fn get_mut(&mut self, slot: u32, name: Symbol) -> Option<&mut T> {
+        let slot = slot as usize;
+        (self.names.get(slot) == Some(&name)).then(|| &mut self.values[slot])
+    }
+
+    /// The slot of a declared name
+    fn position(&self, name: Symbol) -> Option<u32> {
+        self.names.iter().position(|n| *n == name).map(|slot| slot as u32)
+    }
+
+    pub fn declare(&mut self, slot: u32, name: Symbol, value: T) {
+        let slot = slot as usize;
+        if slot < self.values.len() {
+            self.names[slot] = name;
+            self.values[slot] = value;
+            return;
+        }
+        // Earlier declarations may have been skipped (if without braces)
+        while self.values.len() < slot {
+            self.names.push(Symbol::EMPTY);
+            self.values.push(value.clone());
+        }
+        self.names.push(name);
+        self.values.push(value);
+    }
+
+    /// The declared entries, in slot order
+    pub fn iter(&self) -> impl Iterator<Item = (Symbol, &T)> {
+        self.names
+            .iter()
+            .zip(&self.values)
+            .filter(|(name, _)| **name != Symbol::EMPTY)
+            .map(|(name, value)| (*name, value))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
 /// A scope containing variables
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Scope {
-    pub vars: HashMap<String, BlurValue>,
-    pub arrays: HashMap<String, Vec<BlurValue>>,
+    pub vars: Slots<BlurValue>,
+    pub arrays: Slots<BlurArray>,
 }
 
 impl Scope {
     pub fn new() -> Self {
         Scope {
-            vars: HashMap::new(),
-            arrays: HashMap::new(),
+            vars: Slots::new(),
+            arrays: Slots::new(),
         }
     }
 }
 
+impl Default for Scope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether calls to `name` go to a builtin (blurstr() is handled by the engines)
+pub(crate) fn is_builtin(name: &str) -> bool {
+    name == "blurstr" || builtins::lookup(name).is_some()
+}
+
+/// Number of dimensions of an array type (int[][] -> 2)
+fn array_rank(ty: &Type) -> usize {
+    match ty {
+        Type::Array(inner) => 1 + array_rank(inner),
+        _ => 0,
+    }
+}
+
+/// A function call argument - scalars and arrays travel with their history
+pub(crate) enum Arg {
+    Scalar(BlurValue),
+    Array(BlurArray),
+}
+
+/// Check that an argument fits a parameter of `func`
+pub(crate) fn check_param(func: &str, param_type: &Type, param_name: &str, arg: &Arg) -> Result<(), RuntimeError> {
+    match (param_type, arg) {
+        (Type::Array(_), Arg::Array(arr)) => {
+            let rank = array_rank(param_type);
+            if arr.dims.len() != rank {
+                return Err(RuntimeError::TypeMismatch(format!(
+                    "parameter '{}' of {}() expects a {}-dimensional array, got {}",
+                    param_name, func, rank, arr.dims.len()
+                )));
+            }
+            Ok(())
+        }
+        (Type::Array(_), Arg::Scalar(_)) => Err(RuntimeError::TypeMismatch(format!(
+            "parameter '{}' of {}() expects an array", param_name, func
+        ))),
+        (_, Arg::Scalar(_)) => Ok(()),
+        (_, Arg::Array(_)) => Err(RuntimeError::TypeMismatch(format!(
+            "parameter '{}' of {}() does not take an array", param_name, func
+        ))),
+    }
+}
+
+/// Dimensions of an array declared with an initializer list;
+/// an omitted size is inferred from the number of values
+pub(crate) fn list_dims(dims: &[Option<usize>], count: usize) -> Vec<usize> {
+    let known: usize = dims.iter().flatten().product();
+    let missing = count.div_ceil(known.max(1));
+    dims.iter().map(|d| d.unwrap_or(missing)).collect()
+}
+
+/// Initialize array `name` from another array, keeping every element's history
+pub(crate) fn copy_array(name: &str, value: Value, dims: &[Option<usize>], limits: &ExecutionLimits) -> Result<BlurArray, RuntimeError> {
+    let mut arr = match value {
+        Value::Array(arr) => arr,
+        _ => return Err(RuntimeError::TypeMismatch(format!(
+            "array '{}' must be initialized with an array", name
+        ))),
+    };
+    if arr.dims.len() != dims.len() {
+        return Err(RuntimeError::TypeMismatch(format!(
+            "array '{}' has {} dimension(s), initializer has {}",
+            name, dims.len(), arr.dims.len()
+        )));
+    }
+    if let [Some(size)] = dims[..] {
+        limits.check_array(&[size])?;
+        if arr.elems.try_reserve_exact(size.saturating_sub(arr.elems.len())).is_err() {
+            return Err(RuntimeError::ArrayTooLarge(vec![size]));
+        }
+        arr.resize(size);
+    }
+    Ok(arr)
+}
+
+/// Add `delta` to a variable; returns the value an increment expression evaluates to
+pub(crate) fn inc(var: &mut BlurValue, delta: f64, result: IncResult) -> Option<Value> {
+    let old = (result == IncResult::Old).then(|| var.get());
+    let current = var.get_raw();
+    var.push(current + delta);
+    match result {
+        IncResult::None => None,
+        IncResult::New => Some(var.get()),
+        IncResult::Old => old,
+    }
+}
+
+/// The new raw value of a compound assignment (x += 2 etc.)
+pub(crate) fn compound(current: f64, op: CompoundOp, rhs: &Value) -> Result<f64, RuntimeError> {
+    let rhs = rhs.to_f64();
+    Ok(match op {
+        CompoundOp::Add => current + rhs,
+        CompoundOp::Sub => current - rhs,
+        CompoundOp::Mul => current * rhs,
+        CompoundOp::Div => {
+            if rhs == 0.0 {
+                return Err(RuntimeError::DivisionByZero);
+            }
+            current / rhs
+        }
+        CompoundOp::Mod => current % rhs,
+    })
+}
+
+pub(crate) fn eval_binop(left: Value, op: BinOp, right: Value) -> Result<Value, RuntimeError> {
+    let l = left.to_f64();
+    let r = right.to_f64();
+
+    match op {
+        BinOp::Add => Ok(Value::Float(l + r)),
+        BinOp::Sub => Ok(Value::Float(l - r)),
+        BinOp::Mul => Ok(Value::Float(l * r)),
+        BinOp::Div => {
+            if r == 0.0 {
+                return Err(RuntimeError::DivisionByZero);
+            }
+            Ok(Value::Float(l / r))
+        }
+        BinOp::Mod => Ok(Value::Float(l % r)),
+        BinOp::Eq => Ok(Value::Bool((l - r).abs() < f64::EPSILON)),
+        BinOp::Ne => Ok(Value::Bool((l - r).abs() >= f64::EPSILON)),
+        BinOp::Lt => Ok(Value::Bool(l < r)),
+        BinOp::Gt => Ok(Value::Bool(l > r)),
+        BinOp::Le => Ok(Value::Bool(l <= r)),
+        BinOp::Ge => Ok(Value::Bool(l >= r)),
+        BinOp::And => Ok(Value::Bool(left.to_bool() && right.to_bool())),
+        BinOp::Or => Ok(Value::Bool(left.to_bool() || right.to_bool())),
+    }
+}
+
+/// Which engine runs a program
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Engine {
+    /// Walk the AST directly
+    TreeWalk,
+    /// Compile to bytecode and run it on the VM
+    Bytecode,
+}
+
 /// Control flow signal
 pub enum ControlFlow {
     None,
@@ -287,33 +879,183 @@ pub enum ControlFlow {
 }
 
 /// The Blur interpreter
+/// A function call in progress on the tree-walker
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub name: String,
+    /// Index in `scopes` of the scope holding the parameters
+    pub scope: usize,
+    /// The statement being executed (kept up to date while a hook is set)
+    pub span: Span,
+}
+
+/// Called before every statement the tree-walker executes; an error stops the program
+pub type StmtHook = dyn FnMut(&mut Interpreter, &Stmt) -> Result<(), RuntimeError>;
+
 pub struct Interpreter {
     pub functions: HashMap<String, Function>,
     pub scopes: Vec<Scope>,
+    pub engine: Engine,
+    pub limits: ExecutionLimits,
+    /// Statements executed so far (counted against the fuel limit)
+    pub steps: u64,
+    call_depth: usize,
+    /// Where print() writes
+    pub output: Box<dyn Write>,
+    /// Where hosts report errors and warnings about the program
+    pub diagnostics: Box<dyn Write>,
+    /// Functions registered by the host program
+    pub hosts: Vec<HostFunction>,
+    /// Where pushes, calls and loop iterations are logged, if anywhere
+    pub trace: Option<Trace>,
+    /// Calls in progress (tree-walker only); left in place by a runtime error
+    pub frames: Vec<Frame>,
+    /// Runs before each statement (the debugger), tree-walker only
+    pub hook: Option<Box<StmtHook>>,
+    /// The program's command-line arguments (those after `--`), passed to
+    /// `blur(string args[])`
+    pub args: Vec<String>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        Interpreter {
+        let mut interpreter = Interpreter {
             functions: HashMap::new(),
             scopes: vec![Scope::new()],
+            engine: Engine::TreeWalk,
+            limits: ExecutionLimits::default(),
+            steps: 0,
+            call_depth: 0,
+            output: Box::new(io::stdout()),
+            diagnostics: Box::new(io::stderr()),
+            hosts: Vec::new(),
+            trace: None,
+            frames: Vec::new(),
+            hook: None,
+            args: Vec::new(),
+        };
+        // get_blur() - the current blur factor
+        let registered = interpreter.register_fn("get_blur", 0, |_| Ok(Value::Float(get_decay())));
+        debug_assert!(registered.is_ok());
+        interpreter
+    }
+
+    pub fn with_engine(engine: Engine) -> Self {
+        Interpreter { engine, ..Self::new() }
+    }
+
+    /// An interpreter that writes to the given sinks instead of stdout/stderr
+    /// (see `output::Capture` for reading the output back)
+    pub fn with_output(output: impl Write + 'static, diagnostics: impl Write + 'static) -> Self {
+        Interpreter {
+            output: Box::new(output),
+            diagnostics: Box::new(diagnostics),
+            ..Self::new()
         }
     }
 
+    /// Make a Rust closure callable from Blur as `name(...)` with exactly `arity`
+    /// arguments. Fails if a builtin or a function of the loaded program has
+    /// that name.
+    pub fn register_fn<F, R>(&mut self, name: &str, arity: usize, func: F) -> Result<(), RuntimeError>
+    where
+        F: Fn(&[BlurValue]) -> Result<R, RuntimeError> + 'static,
+        R: Into<HostValue>,
+    {
+        if is_builtin(name) {
+            return Err(RuntimeError::BuiltinConflict(name.to_string()));
+        }
+        if self.host(name).is_some() {
+            return Err(RuntimeError::DuplicateHost(name.to_string()));
+        }
+        if self.functions.contains_key(name) {
+            return Err(RuntimeError::HostConflict(name.to_string()));
+        }
+        self.hosts.push(HostFunction::new(name, arity, func));
+        Ok(())
+    }
+
+    pub(crate) fn host(&self, name: &str) -> Option<&HostFunction> {
+        self.hosts.iter().find(|h| h.name == name)
+    }
+
+    /// Add a function of the program; it may not shadow a builtin or a host function
+    pub fn define_function(&mut self, func: Function) -> Result<(), RuntimeError> {
+        if is_builtin(&func.name) {
+            return Err(RuntimeError::BuiltinConflict(func.name));
+        }
+        if self.host(&func.name).is_some() {
+            return Err(RuntimeError::HostConflict(func.name));
+        }
+        self.functions.insert(func.name.clone(), func);
+        Ok(())
+    }
+
+    /// print(...) - write values space-separated on one line
+    pub(crate) fn print(&mut self, values: &[Value]) -> Result<(), RuntimeError> {
+        let line: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        writeln!(self.output, "{}", line.join(" ")).map_err(|e| RuntimeError::Io(e.to_string()))
+    }
+
     pub fn run(&mut self, program: &Program) -> Result<Value, RuntimeError> {
         // Register all functions
         for func in &program.functions {
-            self.functions.insert(func.name.clone(), func.clone());
+            self.define_function(func.clone())?;
+        }
+
+        self.steps = 0;
+        if self.engine == Engine::Bytecode {
+            return vm::run(self, &compiler::compile(program, &self.hosts));
         }
 
         // Call blur() if it exists (the Blur entry point)
-        if self.functions.contains_key("blur") {
-            self.call_function("blur", vec![])
-        } else {
-            Ok(Value::Void)
+        match self.functions.get("blur") {
+            Some(blur) => {
+                let args = self.entry_args(&blur.params.clone())?;
+                self.call_function("blur", args)
+            }
+            None => Ok(Value::Void),
+        }
+    }
+
+    /// What blur() is called with: nothing, or the command-line arguments
+    /// when it is declared `blur(string args[])`
+    pub(crate) fn entry_args(&self, params: &[(Type, Ident)]) -> Result<Vec<Arg>, RuntimeError> {
+        match params {
+            [] => Ok(Vec::new()),
+            [(Type::Array(elem), _)] if **elem == Type::String => {
+                let mut arr = BlurArray::new(Type::String, vec![0]);
+                for arg in &self.args {
+                    let mut value = BlurValue::new(Type::String);
+                    value.push_string(arg);
+                    arr.push(value)?;
+                }
+                Ok(vec![Arg::Array(arr)])
+            }
+            _ => Err(RuntimeError::TypeMismatch(
+                "blur() takes no parameters, or the command-line arguments as string args[]".to_string(),
+            )),
         }
     }
 
+    /// Call a function of the loaded program that takes no arguments, on the
+    /// tree-walker (`blur test` runs each test_* function this way)
+    pub fn call(&mut self, name: &str) -> Result<Value, RuntimeError> {
+        self.call_function(name, vec![])
+    }
+
+    /// Drop the scopes and frames of the calls and blocks a runtime error left behind
+    pub fn unwind(&mut self) {
+        self.scopes.truncate(1);
+        self.frames.clear();
+    }
+
     fn push_scope(&mut self) {
         self.scopes.push(Scope::new());
     }
@@ -326,58 +1068,154 @@ impl Interpreter {
         self.scopes.last_mut().unwrap()
     }
 
-    fn get_var(&self, name: &str) -> Result<&BlurValue, RuntimeError> {
-        for scope in self.scopes.iter().rev() {
-            if let Some(var) = scope.vars.get(name) {
-                return Ok(var);
+    /// The scope `depth` levels out from the current one
+    fn scope_index(&self, depth: u32) -> Option<usize> {
+        self.scopes.len().checked_sub(depth as usize + 1)
+    }
+
+    /// The scope and slot of a scalar (array = false) or array: where the
+    /// resolver put it, or for a name the function doesn't declare, the
+    /// innermost scope that has it, the callers' included
+    fn place(&self, ident: &Ident, array: bool) -> Option<(usize, u32)> {
+        match (ident.addr, array) {
+            (Addr::Var { depth, slot }, false) | (Addr::Array { depth, slot }, true) => {
+                Some((self.scope_index(depth)?, slot))
             }
+            (Addr::Dynamic, _) => self.scopes.iter().enumerate().rev().find_map(|(i, scope)| {
+                let slot = if array { scope.arrays.position(ident.name) } else { scope.vars.position(ident.name) };
+                slot.map(|slot| (i, slot))
+            }),
+            _ => None,
         }
-        Err(RuntimeError::UndefinedVar(name.to_string()))
     }
 
-    fn get_var_mut(&mut self, name: &str) -> Result<&mut BlurValue, RuntimeError> {
-        for scope in self.scopes.iter_mut().rev() {
-            if scope.vars.contains_key(name) {
-                return Ok(scope.vars.get_mut(name).unwrap());
-            }
+    /// Whether a plain name refers to a whole array rather than a scalar
+    fn is_array(&self, ident: &Ident) -> bool {
+        match ident.addr {
+            Addr::Array { .. } => true,
+            Addr::Dynamic => self.place(ident, false).is_none() && self.place(ident, true).is_some(),
+            Addr::Var { .. } => false,
         }
-        Err(RuntimeError::UndefinedVar(name.to_string()))
     }
 
-    fn get_array(&self, name: &str) -> Result<&Vec<BlurValue>, RuntimeError> {
-        for scope in self.scopes.iter().rev() {
-            if let Some(arr) = scope.arrays.get(name) {
-                return Ok(arr);
+    fn get_var(&self, ident: &Ident) -> Result<&BlurValue, RuntimeError> {
+        self.place(ident, false)
+            .and_then(|(i, slot)| self.scopes[i].vars.get(slot, ident.name))
+            .ok_or_else(|| RuntimeError::UndefinedVar(ident.to_string()))
+    }
+
+    /// A variable to push a value into; fails if its history is full
+    fn get_var_mut(&mut self, ident: &Ident) -> Result<&mut BlurValue, RuntimeError> {
+        let (i, slot) = self.place(ident, false).ok_or_else(|| RuntimeError::UndefinedVar(ident.to_string()))?;
+        let var = self.scopes[i].vars.get_mut(slot, ident.name).ok_or_else(|| RuntimeError::UndefinedVar(ident.to_string()))?;
+        self.limits.check_history(var, 1)?;
+        Ok(var)
+    }
+
+    fn get_array(&self, ident: &Ident) -> Result<&BlurArray, RuntimeError> {
+        self.place(ident, true)
+            .and_then(|(i, slot)| self.scopes[i].arrays.get(slot, ident.name))
+            .ok_or_else(|| RuntimeError::UndefinedVar(ident.to_string()))
+    }
+
+    fn get_array_mut(&mut self, ident: &Ident) -> Result<&mut BlurArray, RuntimeError> {
+        let (i, slot) = self.place(ident, true).ok_or_else(|| RuntimeError::UndefinedVar(ident.to_string()))?;
+        self.scopes[i].arrays.get_mut(slot, ident.name).ok_or_else(|| RuntimeError::UndefinedVar(ident.to_string()))
+    }
+
+    /// An array element to push a value into; fails if its history is full
+    fn get_elem_mut(&mut self, ident: &Ident, indices: &[i64]) -> Result<&mut BlurValue, RuntimeError> {
+        let limits = self.limits;
+        let elem = self.get_array_mut(ident)?.elem_mut(indices)?;
+        limits.check_history(elem, 1)?;
+        Ok(elem)
+    }
+
+    /// Record a push into a variable (or the element at `indices`) in the trace
+    fn traced(&mut self, ident: &Ident, indices: &[i64], pushed: Option<&Value>, count: usize) -> Result<(), RuntimeError> {
+        let Some(mut trace) = self.trace.take() else {
+            return Ok(());
+        };
+        let var = if indices.is_empty() {
+            self.get_var(ident)
+        } else {
+            self.get_array(ident).and_then(|arr| arr.elem(indices))
+        };
+        let result = var.and_then(|var| trace.push(ident.name.as_str(), ident.addr.depth(), indices, var, pushed, count));
+        self.trace = Some(trace);
+        result
+    }
+
+    /// Add `delta` to a variable, or to an element if there are index expressions
+    fn increment(&mut self, ident: &Ident, index_exprs: &[Expr], delta: f64, result: IncResult) -> Result<Value, RuntimeError> {
+        let indices = self.eval_indices(index_exprs)?;
+        let var = if index_exprs.is_empty() {
+            self.get_var_mut(ident)?
+        } else {
+            self.get_elem_mut(ident, &indices)?
+        };
+        let value = inc(var, delta, result);
+        self.traced(ident, &indices, None, 1)?;
+        Ok(value.unwrap_or(Value::Void))
+    }
+
+    /// Store a declared variable in the slot the resolver gave it
+    fn declare_var(&mut self, ident: &Ident, var: BlurValue) -> Result<(), RuntimeError> {
+        match ident.addr {
+            Addr::Var { slot, .. } => {
+                self.current_scope().vars.declare(slot, ident.name, var);
+                Ok(())
             }
+            _ => Err(RuntimeError::UndefinedVar(ident.to_string())),
         }
-        Err(RuntimeError::UndefinedVar(name.to_string()))
     }
 
-    fn get_array_mut(&mut self, name: &str) -> Result<&mut Vec<BlurValue>, RuntimeError> {
-        for scope in self.scopes.iter_mut().rev() {
-            if scope.arrays.contains_key(name) {
-                return Ok(scope.arrays.get_mut(name).unwrap());
+    fn declare_array(&mut self, ident: &Ident, arr: BlurArray) -> Result<(), RuntimeError> {
+        match ident.addr {
+            Addr::Array { slot, .. } => {
+                self.current_scope().arrays.declare(slot, ident.name, arr);
+                Ok(())
             }
+            _ => Err(RuntimeError::UndefinedVar(ident.to_string())),
         }
-        Err(RuntimeError::UndefinedVar(name.to_string()))
     }
 
-    fn call_function(&mut self, name: &str, args: Vec<BlurValue>) -> Result<Value, RuntimeError> {
+    fn call_function(&mut self, name: &str, args: Vec<Arg>) -> Result<Value, RuntimeError> {
         let func = self.functions.get(name)
             .ok_or_else(|| RuntimeError::UndefinedFunc(name.to_string()))?
             .clone();
 
+        self.limits.check_call_depth(self.call_depth)?;
+        if let Some(trace) = &mut self.trace {
+            trace.call(name)?;
+        }
+        self.call_depth += 1;
+        let result = self.invoke(&func, args);
+        self.call_depth -= 1;
+        if let (Some(trace), Ok(value)) = (&mut self.trace, &result) {
+            trace.ret(name, value)?;
+        }
+        result
+    }
+
+    fn invoke(&mut self, func: &Function, args: Vec<Arg>) -> Result<Value, RuntimeError> {
+        let name = &func.name;
         self.push_scope();
 
         // Bind parameters - history travels with arguments!
-        for (i, (_param_type, param_name)) in func.params.iter().enumerate() {
-            if i < args.len() {
-                // Clone the full BlurValue including history
-                self.current_scope().vars.insert(param_name.clone(), args[i].clone());
+        for ((param_type, param_name), arg) in func.params.iter().zip(args) {
+            let bound = check_param(name, param_type, param_name.name.as_str(), &arg).and_then(|_| match arg {
+                Arg::Array(arr) => self.declare_array(param_name, arr),
+                Arg::Scalar(val) => self.declare_var(param_name, val),
+            });
+            if let Err(e) = bound {
+                self.pop_scope();
+                return Err(e);
             }
         }
 
         // Execute body
+        self.frames.push(Frame { name: name.clone(), scope: self.scopes.len() - 1, span: Span::default() });
         let mut result = Value::Void;
         for stmt in &func.body {
             match self.exec_stmt(stmt)? {
@@ -389,208 +1227,240 @@ impl Interpreter {
             }
         }
 
+        self.frames.pop();
         self.pop_scope();
         Ok(result)
     }
 
-    /// Evaluate an expression and return a BlurValue with history (if it's a variable)
-    fn eval_expr_as_blur(&mut self, expr: &Expr) -> Result<BlurValue, RuntimeError> {
+    /// Evaluate a call argument, keeping the history of variables, elements and arrays
+    fn eval_arg(&mut self, expr: &Expr) -> Result<Arg, RuntimeError> {
         match expr {
             // If it's a simple variable reference, clone its full history
-            Expr::Var(name) => {
-                let var = self.get_var(name)?;
-                Ok(var.clone())
+            // A whole array keeps every element's history
+            Expr::Var(name) if self.is_array(name) => {
+                Ok(Arg::Array(self.get_array(name)?.clone()))
             }
+            Expr::Var(name) => Ok(Arg::Scalar(self.get_var(name)?.clone())),
             // For array access, clone the element's history
-            Expr::ArrayAccess(name, index_expr) => {
-                let index = self.eval_expr(index_expr)?.to_f64() as i64;
+            Expr::ArrayAccess(name, index_exprs) => {
+                let indices = self.eval_indices(index_exprs)?;
                 let arr = self.get_array(name)?;
-                if index < 0 || index as usize >= arr.len() {
-                    return Err(RuntimeError::IndexOutOfBounds { index, size: arr.len() });
-                }
-                Ok(arr[index as usize].clone())
+                Ok(Arg::Scalar(arr.elem(&indices)?.clone()))
             }
+            // A host function may return a value with history
+            Expr::Call(name, args) if self.host(name).is_some() => Ok(self.call_host(name, args)?.into_arg()),
             // For any other expression, evaluate it and create a new BlurValue
             _ => {
-                let value = self.eval_expr(expr)?;
-                let mut blur_val = BlurValue::new(Type::Float); // Default to float for expressions
-                blur_val.push(value.to_f64());
-                Ok(blur_val)
+                match self.eval_expr(expr)? {
+                    Value::Array(arr) => Ok(Arg::Array(arr)),
+                    value => {
+                        let mut blur_val = BlurValue::new(Type::Float); // Default to float for expressions
+                        blur_val.push(value.to_f64());
+                        Ok(Arg::Scalar(blur_val))
+                    }
+                }
             }
         }
     }
 
+    /// Evaluate array index expressions
+    fn eval_indices(&mut self, index_exprs: &[Expr]) -> Result<Vec<i64>, RuntimeError> {
+        index_exprs
+            .iter()
+            .map(|e| self.eval_expr(e).map(|v| v.to_f64() as i64))
+            .collect()
+    }
+
+    /// Call a built-in: check the arguments, evaluate the by-value ones in order,
+    /// then resolve the variable the builtin works on
+    fn call_builtin(&mut self, builtin: &Builtin, args: &[Expr]) -> Result<Value, RuntimeError> {
+        let limits = self.limits;
+        builtin.check_args(args.len(), |i| matches!(args[i], Expr::Var(_)))?;
+        let mut values = Vec::new();
+        let mut target = None;
+        for (param, arg) in builtin.params.iter().zip(args) {
+            if *param == Param::Value {
+                values.push(self.eval_expr(arg)?);
+            } else if let Expr::Var(name) = arg {
+                target = Some((*param, name));
+            }
+        }
+        let resolved = match target {
+            Some((Param::Array, name)) => Target::Array(name.name.as_str(), self.get_array_mut(name)?),
+            Some((_, name)) => Target::Var(self.get_var_mut(name)?),
+            None => Target::None,
+        };
+        let value = builtins::call(builtin.name, values, resolved, &limits)?;
+        match target {
+            Some((Param::Array, name)) => {
+                if let Some(mut trace) = self.trace.take() {
+                    let result = self.get_array(name).and_then(|arr| trace.array_builtin(builtin.name, name.name.as_str(), name.addr.depth(), arr));
+                    self.trace = Some(trace);
+                    result?;
+                }
+            }
+            Some((_, name)) => self.traced(name, &[], None, 1)?,
+            None => {}
+        }
+        Ok(value)
+    }
+
+    /// Call a host function with its arguments' full history
+    fn call_host(&mut self, name: &str, args: &[Expr]) -> Result<HostValue, RuntimeError> {
+        let args = args.iter().map(|a| self.eval_arg(a)).collect::<Result<Vec<_>, _>>()?;
+        let host = self.host(name).ok_or_else(|| RuntimeError::UndefinedFunc(name.to_string()))?;
+        host.call(args)
+    }
+
     pub fn exec_stmt(&mut self, stmt: &Stmt) -> Result<ControlFlow, RuntimeError> {
-        match stmt {
-            Stmt::VarDecl(var_type, name, init) => {
+        self.steps += 1;
+        self.limits.check_fuel(self.steps)?;
+        if let Some(trace) = &mut self.trace {
+            trace.at(stmt.span);
+        }
+        if let Some(mut hook) = self.hook.take() {
+            if let Some(frame) = self.frames.last_mut() {
+                frame.span = stmt.span;
+            }
+            let result = hook(self, stmt);
+            self.hook = Some(hook);
+            result?;
+        }
+
+        match &stmt.kind {
+            StmtKind::VarDecl(var_type, name, init) => {
                 let mut blur_val = BlurValue::new(var_type.clone());
                 if let Some(expr) = init {
                     // Handle StringRepeat specially
                     if let Expr::StringRepeat(str_expr, count_expr) = expr {
                         if let Expr::StringLit(s) = str_expr.as_ref() {
                             let count = self.eval_expr(count_expr)?.to_f64() as usize;
+                            self.limits.check_history(&blur_val, count)?;
                             blur_val.push_string_times(s, count);
+                            if let Some(trace) = &mut self.trace {
+                                trace.push(name.name.as_str(), name.addr.depth(), &[], &blur_val, Some(&Value::String(s.clone())), count)?;
+                            }
                         }
                     } else {
                         let value = self.eval_expr(expr)?;
-                        match var_type {
-                            Type::Bool => blur_val.push_bool(value.to_bool()),
-                            Type::String => {
-                                if let Value::String(s) = value {
-                                    blur_val.push_string(&s);
-                                }
-                            }
-                            _ => blur_val.push(value.to_f64()),
+                        blur_val.push_value(&value);
+                        if let Some(trace) = &mut self.trace {
+                            trace.push(name.name.as_str(), name.addr.depth(), &[], &blur_val, Some(&value), 1)?;
                         }
                     }
                 }
-                self.current_scope().vars.insert(name.clone(), blur_val);
+                self.declare_var(name, blur_val)?;
                 Ok(ControlFlow::None)
             }
 
-            Stmt::ArrayDecl(elem_type, name, size, init) => {
-                let mut arr: Vec<BlurValue> = (0..*size)
-                    .map(|_| BlurValue::new(elem_type.clone()))
-                    .collect();
+            StmtKind::ArrayDecl(elem_type, name, dim_exprs, init) => {
+                let mut dims = Vec::new();
+                for dim_expr in dim_exprs {
+                    dims.push(match dim_expr {
+                        Some(expr) => Some(array_size(&self.eval_expr(expr)?)?),
+                        None => None,
+                    });
+                }
 
-                if let Some(values) = init {
-                    for (i, expr) in values.iter().enumerate() {
-                        if i < arr.len() {
-                            let value = self.eval_expr(expr)?;
-                            match elem_type {
-                                Type::Bool => arr[i].push_bool(value.to_bool()),
-                                _ => arr[i].push(value.to_f64()),
+                let arr = match init {
+                    Some(ArrayInit::List(values)) => {
+                        let dims = list_dims(&dims, values.len());
+                        self.limits.check_array(&dims)?;
+                        let mut arr = BlurArray::try_new(elem_type.clone(), dims)?;
+                        for (i, expr) in values.iter().enumerate() {
+                            if i < arr.elems.len() {
+                                let value = self.eval_expr(expr)?;
+                                arr.elems[i].push_value(&value);
+                                if let Some(trace) = &mut self.trace {
+                                    trace.init_elem(name.name.as_str(), &arr, i, &value)?;
+                                }
                             }
                         }
+                        arr
                     }
-                }
-                self.current_scope().arrays.insert(name.clone(), arr);
+                    Some(ArrayInit::Expr(expr)) => {
+                        let value = self.eval_expr(expr)?;
+                        copy_array(name.name.as_str(), value, &dims, &self.limits)?
+                    }
+                    None => {
+                        let dims: Vec<usize> = dims.iter().map(|d| d.unwrap_or(0)).collect();
+                        self.limits.check_array(&dims)?;
+                        BlurArray::try_new(elem_type.clone(), dims)?
+                    }
+                };
+                self.declare_array(name, arr)?;
                 Ok(ControlFlow::None)
             }
 
-            Stmt::Assign(name, expr) => {
+            StmtKind::Assign(name, expr) => {
                 // Handle StringRepeat specially
                 if let Expr::StringRepeat(str_expr, count_expr) = expr {
                     if let Expr::StringLit(s) = str_expr.as_ref() {
                         let count = self.eval_expr(count_expr)?.to_f64() as usize;
+                        let limits = self.limits;
                         let var = self.get_var_mut(name)?;
+                        limits.check_history(var, count)?;
                         var.push_string_times(s, count);
+                        self.traced(name, &[], Some(&Value::String(s.clone())), count)?;
                         return Ok(ControlFlow::None);
                     }
                 }
                 let value = self.eval_expr(expr)?;
                 let var = self.get_var_mut(name)?;
-                match &var.var_type {
-                    Type::Bool => var.push_bool(value.to_bool()),
-                    Type::String => {
-                        if let Value::String(s) = value {
-                            var.push_string(&s);
-                        }
-                    }
-                    _ => var.push(value.to_f64()),
-                }
+                var.push_value(&value);
+                self.traced(name, &[], Some(&value), 1)?;
                 Ok(ControlFlow::None)
             }
 
-            Stmt::ArrayAssign(name, index_expr, value_expr) => {
-                let index = self.eval_expr(index_expr)?.to_f64() as i64;
+            StmtKind::ArrayAssign(name, index_exprs, value_expr) => {
+                let indices = self.eval_indices(index_exprs)?;
                 let value = self.eval_expr(value_expr)?;
-                let arr = self.get_array_mut(name)?;
-                if index < 0 || index as usize >= arr.len() {
-                    return Err(RuntimeError::IndexOutOfBounds { index, size: arr.len() });
-                }
-                let elem = &mut arr[index as usize];
-                match &elem.var_type {
-                    Type::Bool => elem.push_bool(value.to_bool()),
-                    _ => elem.push(value.to_f64()),
-                }
+                let elem = self.get_elem_mut(name, &indices)?;
+                elem.push_value(&value);
+                self.traced(name, &indices, Some(&value), 1)?;
                 Ok(ControlFlow::None)
             }
 
-            Stmt::CompoundAssign(name, op, expr) => {
+            StmtKind::CompoundAssign(name, op, expr) => {
                 let rhs = self.eval_expr(expr)?;
                 let var = self.get_var_mut(name)?;
-                let current = var.get_raw();
-                let new_val = match op {
-                    CompoundOp::AddAssign => current + rhs.to_f64(),
-                    CompoundOp::SubAssign => current - rhs.to_f64(),
-                    CompoundOp::MulAssign => current * rhs.to_f64(),
-                    CompoundOp::DivAssign => {
-                        if rhs.to_f64() == 0.0 {
-                            return Err(RuntimeError::DivisionByZero);
-                        }
-                        current / rhs.to_f64()
-                    }
-                    CompoundOp::ModAssign => current % rhs.to_f64(),
-                };
+                let new_val = compound(var.get_raw(), *op, &rhs)?;
                 var.push(new_val);
+                self.traced(name, &[], None, 1)?;
                 Ok(ControlFlow::None)
             }
 
-            Stmt::ArrayCompoundAssign(name, index_expr, op, value_expr) => {
-                let index = self.eval_expr(index_expr)?.to_f64() as i64;
+            StmtKind::ArrayCompoundAssign(name, index_exprs, op, value_expr) => {
+                let indices = self.eval_indices(index_exprs)?;
                 let rhs = self.eval_expr(value_expr)?;
-                let arr = self.get_array_mut(name)?;
-                if index < 0 || index as usize >= arr.len() {
-                    return Err(RuntimeError::IndexOutOfBounds { index, size: arr.len() });
-                }
-                let elem = &mut arr[index as usize];
-                let current = elem.get_raw();
-                let new_val = match op {
-                    CompoundOp::AddAssign => current + rhs.to_f64(),
-                    CompoundOp::SubAssign => current - rhs.to_f64(),
-                    CompoundOp::MulAssign => current * rhs.to_f64(),
-                    CompoundOp::DivAssign => {
-                        if rhs.to_f64() == 0.0 {
-                            return Err(RuntimeError::DivisionByZero);
-                        }
-                        current / rhs.to_f64()
-                    }
-                    CompoundOp::ModAssign => current % rhs.to_f64(),
-                };
+                let elem = self.get_elem_mut(name, &indices)?;
+                let new_val = compound(elem.get_raw(), *op, &rhs)?;
                 elem.push(new_val);
+                self.traced(name, &indices, None, 1)?;
                 Ok(ControlFlow::None)
             }
 
-            Stmt::PreIncrement(name) | Stmt::PostIncrement(name) => {
-                let var = self.get_var_mut(name)?;
-                let current = var.get_raw();
-                var.push(current + 1.0);
+            StmtKind::PreIncrement(name) | StmtKind::PostIncrement(name) => {
+                self.increment(name, &[], 1.0, IncResult::None)?;
                 Ok(ControlFlow::None)
             }
 
-            Stmt::PreDecrement(name) | Stmt::PostDecrement(name) => {
-                let var = self.get_var_mut(name)?;
-                let current = var.get_raw();
-                var.push(current - 1.0);
+            StmtKind::PreDecrement(name) | StmtKind::PostDecrement(name) => {
+                self.increment(name, &[], -1.0, IncResult::None)?;
                 Ok(ControlFlow::None)
             }
 
-            Stmt::ArrayPreIncrement(name, index_expr) | Stmt::ArrayPostIncrement(name, index_expr) => {
-                let index = self.eval_expr(index_expr)?.to_f64() as i64;
-                let arr = self.get_array_mut(name)?;
-                if index < 0 || index as usize >= arr.len() {
-                    return Err(RuntimeError::IndexOutOfBounds { index, size: arr.len() });
-                }
-                let elem = &mut arr[index as usize];
-                let current = elem.get_raw();
-                elem.push(current + 1.0);
+            StmtKind::ArrayPreIncrement(name, index_exprs) | StmtKind::ArrayPostIncrement(name, index_exprs) => {
+                self.increment(name, index_exprs, 1.0, IncResult::None)?;
                 Ok(ControlFlow::None)
             }
 
-            Stmt::ArrayPreDecrement(name, index_expr) | Stmt::ArrayPostDecrement(name, index_expr) => {
-                let index = self.eval_expr(index_expr)?.to_f64() as i64;
-                let arr = self.get_array_mut(name)?;
-                if index < 0 || index as usize >= arr.len() {
-                    return Err(RuntimeError::IndexOutOfBounds { index, size: arr.len() });
-                }
-                let elem = &mut arr[index as usize];
-                let current = elem.get_raw();
-                elem.push(current - 1.0);
+            StmtKind::ArrayPreDecrement(name, index_exprs) | StmtKind::ArrayPostDecrement(name, index_exprs) => {
+                self.increment(name, index_exprs, -1.0, IncResult::None)?;
                 Ok(ControlFlow::None)
             }
 
-            Stmt::If(cond, then_branch, else_branch) => {
+            StmtKind::If(cond, then_branch, else_branch) => {
                 let cond_val = self.eval_expr(cond)?;
                 if cond_val.to_bool() {
                     self.exec_stmt(then_branch)
@@ -601,12 +1471,18 @@ impl Interpreter {
                 }
             }
 
-            Stmt::While(cond, body) => {
+            StmtKind::While(cond, body) => {
+                let mut iterations = 0;
                 loop {
+                    self.limits.check_loop(LoopKind::While, iterations)?;
+                    iterations += 1;
                     let cond_val = self.eval_expr(cond)?;
                     if !cond_val.to_bool() {
                         break;
                     }
+                    if let Some(trace) = &mut self.trace {
+                        trace.iteration(LoopKind::While, iterations, stmt.span)?;
+                    }
                     match self.exec_stmt(body)? {
                         ControlFlow::Return(v) => return Ok(ControlFlow::Return(v)),
                         ControlFlow::None => {}
@@ -615,23 +1491,16 @@ impl Interpreter {
                 Ok(ControlFlow::None)
             }
 
-            Stmt::For(init, cond, update, body) => {
+            StmtKind::For(init, cond, update, body) => {
                 self.push_scope();
 
                 if let Some(init_stmt) = init {
                     self.exec_stmt(init_stmt)?;
                 }
 
-                // Safety limit: regular for loops cap at 1000 iterations
-                // (blur semantics can cause loops to run ~forever)
                 let mut iterations = 0;
-                const MAX_ITERATIONS: usize = 1000;
-
                 loop {
-                    if iterations >= MAX_ITERATIONS {
-                        eprintln!("Warning: for loop hit {} iteration limit (use 'sharp for' for unlimited)", MAX_ITERATIONS);
-                        break;
-                    }
+                    self.limits.check_loop(LoopKind::For, iterations)?;
                     iterations += 1;
 
                     if let Some(cond_expr) = cond {
@@ -640,6 +1509,9 @@ impl Interpreter {
                             break;
                         }
                     }
+                    if let Some(trace) = &mut self.trace {
+                        trace.iteration(LoopKind::For, iterations, stmt.span)?;
+                    }
 
                     match self.exec_stmt(body)? {
                         ControlFlow::Return(v) => {
@@ -659,7 +1531,7 @@ impl Interpreter {
             }
 
             // SharpFor - variables declared in init are NOT averaged (escape hatch)
-            Stmt::SharpFor(init, cond, update, body) => {
+            StmtKind::SharpFor(init, cond, update, body) => {
                 self.push_scope();
 
                 // Execute init but mark declared variables as sharp (not averaged)
@@ -667,13 +1539,19 @@ impl Interpreter {
                     self.exec_sharp_stmt(init_stmt)?;
                 }
 
+                let mut iterations = 0;
                 loop {
+                    self.limits.check_loop(LoopKind::SharpFor, iterations)?;
+                    iterations += 1;
                     if let Some(cond_expr) = cond {
                         let cond_val = self.eval_expr(cond_expr)?;
                         if !cond_val.to_bool() {
                             break;
                         }
                     }
+                    if let Some(trace) = &mut self.trace {
+                        trace.iteration(LoopKind::SharpFor, iterations, stmt.span)?;
+                    }
 
                     match self.exec_stmt(body)? {
                         ControlFlow::Return(v) => {
@@ -692,7 +1570,7 @@ impl Interpreter {
                 Ok(ControlFlow::None)
             }
 
-            Stmt::Block(stmts) => {
+            StmtKind::Block(stmts) => {
                 self.push_scope();
                 for stmt in stmts {
                     match self.exec_stmt(stmt)? {
@@ -707,21 +1585,21 @@ impl Interpreter {
                 Ok(ControlFlow::None)
             }
 
-            Stmt::Expr(expr) => {
+            StmtKind::Expr(expr) => {
                 self.eval_expr(expr)?;
                 Ok(ControlFlow::None)
             }
 
-            Stmt::Print(exprs) => {
-                let values: Vec<String> = exprs
+            StmtKind::Print(exprs) => {
+                let values = exprs
                     .iter()
-                    .map(|e| self.eval_expr(e).map(|v| v.to_string()))
+                    .map(|e| self.eval_expr(e))
                     .collect::<Result<Vec<_>, _>>()?;
-                println!("{}", values.join(" "));
+                self.print(&values)?;
                 Ok(ControlFlow::None)
             }
 
-            Stmt::Return(expr) => {
+            StmtKind::Return(expr) => {
                 let value = if let Some(e) = expr {
                     self.eval_expr(e)?
                 } else {
@@ -734,22 +1612,17 @@ impl Interpreter {
 
     /// Execute a statement in "sharp" mode - variable declarations are not averaged
     fn exec_sharp_stmt(&mut self, stmt: &Stmt) -> Result<ControlFlow, RuntimeError> {
-        match stmt {
-            Stmt::VarDecl(var_type, name, init) => {
+        match &stmt.kind {
+            StmtKind::VarDecl(var_type, name, init) => {
                 let mut blur_val = BlurValue::new_sharp(var_type.clone());
                 if let Some(expr) = init {
                     let value = self.eval_expr(expr)?;
-                    match var_type {
-                        Type::Bool => blur_val.push_bool(value.to_bool()),
-                        Type::String => {
-                            if let Value::String(s) = value {
-                                blur_val.push_string(&s);
-                            }
-                        }
-                        _ => blur_val.push(value.to_f64()),
+                    blur_val.push_value(&value);
+                    if let Some(trace) = &mut self.trace {
+                        trace.push(name.name.as_str(), name.addr.depth(), &[], &blur_val, Some(&value), 1)?;
                     }
                 }
-                self.current_scope().vars.insert(name.clone(), blur_val);
+                self.declare_var(name, blur_val)?;
                 Ok(ControlFlow::None)
             }
             // For non-declaration statements, just execute normally
@@ -775,24 +1648,21 @@ impl Interpreter {
                 }
             }
 
-            Expr::Var(name) => {
-                let var = self.get_var(name)?;
-                Ok(var.get())
+            // Arrays evaluate to a copy with every element's history
+            Expr::Var(name) if self.is_array(name) => {
+                Ok(Value::Array(self.get_array(name)?.clone()))
             }
+            Expr::Var(name) => Ok(self.get_var(name)?.get()),
 
-            Expr::ArrayAccess(name, index_expr) => {
-                let index = self.eval_expr(index_expr)?.to_f64() as i64;
-                let arr = self.get_array(name)?;
-                if index < 0 || index as usize >= arr.len() {
-                    return Err(RuntimeError::IndexOutOfBounds { index, size: arr.len() });
-                }
-                Ok(arr[index as usize].get())
+            Expr::ArrayAccess(name, index_exprs) => {
+                let indices = self.eval_indices(index_exprs)?;
+                Ok(self.get_array(name)?.elem(&indices)?.get())
             }
 
             Expr::BinOp(left, op, right) => {
                 let l = self.eval_expr(left)?;
                 let r = self.eval_expr(right)?;
-                self.eval_binop(l, *op, r)
+                eval_binop(l, *op, r)
             }
 
             Expr::UnaryOp(op, expr) => {
@@ -803,145 +1673,49 @@ impl Interpreter {
                 }
             }
 
-            Expr::PreIncrement(name) => {
-                let var = self.get_var_mut(name)?;
-                let current = var.get_raw();
-                var.push(current + 1.0);
-                Ok(var.get())
-            }
-
-            Expr::PreDecrement(name) => {
-                let var = self.get_var_mut(name)?;
-                let current = var.get_raw();
-                var.push(current - 1.0);
-                Ok(var.get())
-            }
-
-            Expr::PostIncrement(name) => {
-                let var = self.get_var_mut(name)?;
-                let old_val = var.get();
-                let current = var.get_raw();
-                var.push(current + 1.0);
-                Ok(old_val)
-            }
-
-            Expr::PostDecrement(name) => {
-                let var = self.get_var_mut(name)?;
-                let old_val = var.get();
-                let current = var.get_raw();
-                var.push(current - 1.0);
-                Ok(old_val)
-            }
-
-            Expr::ArrayPreIncrement(name, index_expr) => {
-                let index = self.eval_expr(index_expr)?.to_f64() as i64;
-                let arr = self.get_array_mut(name)?;
-                if index < 0 || index as usize >= arr.len() {
-                    return Err(RuntimeError::IndexOutOfBounds { index, size: arr.len() });
-                }
-                let elem = &mut arr[index as usize];
-                let current = elem.get_raw();
-                elem.push(current + 1.0);
-                Ok(elem.get())
-            }
-
-            Expr::ArrayPreDecrement(name, index_expr) => {
-                let index = self.eval_expr(index_expr)?.to_f64() as i64;
-                let arr = self.get_array_mut(name)?;
-                if index < 0 || index as usize >= arr.len() {
-                    return Err(RuntimeError::IndexOutOfBounds { index, size: arr.len() });
-                }
-                let elem = &mut arr[index as usize];
-                let current = elem.get_raw();
-                elem.push(current - 1.0);
-                Ok(elem.get())
-            }
-
-            Expr::ArrayPostIncrement(name, index_expr) => {
-                let index = self.eval_expr(index_expr)?.to_f64() as i64;
-                let arr = self.get_array_mut(name)?;
-                if index < 0 || index as usize >= arr.len() {
-                    return Err(RuntimeError::IndexOutOfBounds { index, size: arr.len() });
-                }
-                let elem = &mut arr[index as usize];
-                let old_val = elem.get();
-                let current = elem.get_raw();
-                elem.push(current + 1.0);
-                Ok(old_val)
-            }
-
-            Expr::ArrayPostDecrement(name, index_expr) => {
-                let index = self.eval_expr(index_expr)?.to_f64() as i64;
-                let arr = self.get_array_mut(name)?;
-                if index < 0 || index as usize >= arr.len() {
-                    return Err(RuntimeError::IndexOutOfBounds { index, size: arr.len() });
-                }
-                let elem = &mut arr[index as usize];
-                let old_val = elem.get();
-                let current = elem.get_raw();
-                elem.push(current - 1.0);
-                Ok(old_val)
-            }
+            Expr::PreIncrement(name) => self.increment(name, &[], 1.0, IncResult::New),
+            Expr::PreDecrement(name) => self.increment(name, &[], -1.0, IncResult::New),
+            Expr::PostIncrement(name) => self.increment(name, &[], 1.0, IncResult::Old),
+            Expr::PostDecrement(name) => self.increment(name, &[], -1.0, IncResult::Old),
+            Expr::ArrayPreIncrement(name, index_exprs) => self.increment(name, index_exprs, 1.0, IncResult::New),
+            Expr::ArrayPreDecrement(name, index_exprs) => self.increment(name, index_exprs, -1.0, IncResult::New),
+            Expr::ArrayPostIncrement(name, index_exprs) => self.increment(name, index_exprs, 1.0, IncResult::Old),
+            Expr::ArrayPostDecrement(name, index_exprs) => self.increment(name, index_exprs, -1.0, IncResult::Old),
 
             Expr::Call(name, args) => {
-                // Built-in get_blur() function - returns current blur factor
-                if name == "get_blur" {
-                    return Ok(Value::Float(get_decay()));
-                }
-
                 // Built-in blurstr() function - blurs multiple strings together
                 if name == "blurstr" {
-                    let mut blur_val = BlurValue::new(Type::String);
+                    let mut parts = Vec::new();
                     for arg in args {
-                        // Handle StringRepeat specially
+                        // "str" * n pushes the string n times
                         if let Expr::StringRepeat(str_expr, count_expr) = arg {
                             if let Expr::StringLit(s) = str_expr.as_ref() {
                                 let count = self.eval_expr(count_expr)?.to_f64() as usize;
-                                blur_val.push_string_times(s, count);
+                                parts.push((Value::String(s.clone()), count));
                                 continue;
                             }
                         }
-                        let value = self.eval_expr(arg)?;
-                        if let Value::String(s) = value {
-                            blur_val.push_string(&s);
-                        }
+                        parts.push((self.eval_expr(arg)?, 1));
                     }
-                    return Ok(blur_val.get());
+                    return Ok(builtins::blurstr(parts));
+                }
+
+                // Other built-ins: len, push, pop, smooth, load_image, ...
+                if let Some(builtin) = builtins::lookup(name) {
+                    return self.call_builtin(builtin, args);
+                }
+
+                if self.host(name).is_some() {
+                    return Ok(self.call_host(name, args)?.get());
                 }
 
-                // Collect BlurValues with full history
-                let arg_values: Vec<BlurValue> = args
+                // Collect arguments with full history
+                let arg_values: Vec<Arg> = args
                     .iter()
-                    .map(|a| self.eval_expr_as_blur(a))
+                    .map(|a| self.eval_arg(a))
                     .collect::<Result<Vec<_>, _>>()?;
                 self.call_function(name, arg_values)
             }
         }
     }
-
-    fn eval_binop(&self, left: Value, op: BinOp, right: Value) -> Result<Value, RuntimeError> {
-        let l = left.to_f64();
-        let r = right.to_f64();
-
-        match op {
-            BinOp::Add => Ok(Value::Float(l + r)),
-            BinOp::Sub => Ok(Value::Float(l - r)),
-            BinOp::Mul => Ok(Value::Float(l * r)),
-            BinOp::Div => {
-                if r == 0.0 {
-                    return Err(RuntimeError::DivisionByZero);
-                }
-                Ok(Value::Float(l / r))
-            }
-            BinOp::Mod => Ok(Value::Float(l % r)),
-            BinOp::Eq => Ok(Value::Bool((l - r).abs() < f64::EPSILON)),
-            BinOp::Ne => Ok(Value::Bool((l - r).abs() >= f64::EPSILON)),
-            BinOp::Lt => Ok(Value::Bool(l < r)),
-            BinOp::Gt => Ok(Value::Bool(l > r)),
-            BinOp::Le => Ok(Value::Bool(l <= r)),
-            BinOp::Ge => Ok(Value::Bool(l >= r)),
-            BinOp::And => Ok(Value::Bool(left.to_bool() && right.to_bool())),
-            BinOp::Or => Ok(Value::Bool(left.to_bool() || right.to_bool())),
-        }
-    }
 }
@@ -0,0 +1,88 @@
+// The Blur language: lexer, parser, both engines and the tooling around them.
+//
+// The `blur` binary is a thin command line over this crate. Hosts that embed
+// Blur build an `interpreter::Interpreter`, register Rust functions with
+// `register_fn` and point its output at their own sinks with `with_output`.
+
+pub mod analyze;
+pub mod ast;
+pub mod builtins;
+pub mod check;
+pub mod cli;
+pub mod compiler;
+pub mod dap;
+pub mod debugger;
+pub mod doctest;
+pub mod fmt;
+pub mod host;
+pub mod image;
+pub mod interpreter;
+pub mod lexer;
+pub mod lint;
+pub mod lsp;
+pub mod output;
+pub mod parser;
+pub mod repl;
+pub mod resolver;
+pub mod rpc;
+pub mod snapshot;
+pub mod spatial;
+pub mod symbol;
+pub mod testing;
+pub mod trace;
+pub mod vm;
+pub mod wav;
+
+use interpreter::{set_decay, set_raw_history, ExecutionLimits};
+
+pub const VERSION: &str = "0.1.0";
+
+/// Whether a line is a directive: #blur, #raw-history or a limit like #fuel
+pub fn is_directive(line: &str) -> bool {
+    let trimmed = line.trim();
+    trimmed.starts_with("#blur")
+        || trimmed.starts_with("#raw-history")
+        || trimmed
+            .split_whitespace()
+            .next()
+            .and_then(|p| p.strip_prefix('#'))
+            .is_some_and(|name| ExecutionLimits::OPTIONS.contains(&name))
+}
+
+/// Process #blur, #raw-history and limit directives (#fuel 5000) and return remaining source
+pub fn process_directives(source: &str, limits: &mut ExecutionLimits) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in source.lines() {
+        if !is_directive(line) {
+            lines.push(line);
+            continue;
+        }
+        let trimmed = line.trim();
+        let parts: Vec<&str> = trimmed.split_whitespace().collect();
+        if trimmed.starts_with("#blur") {
+            // Parse: #blur 0.9
+            if parts.len() >= 2 {
+                if let Ok(d) = parts[1].parse::<f64>() {
+                    set_decay(d);
+                }
+            }
+        } else if trimmed.starts_with("#raw-history") {
+            if let Some(Ok(n)) = parts.get(1).map(|p| p.parse::<usize>()) {
+                set_raw_history(n);
+            }
+        } else if let Some(value) = parts.get(1) {
+            // Parse: #max-loop 5000 (invalid values are ignored, like #blur)
+            let _ = limits.set(&parts[0][1..], value);
+        }
+        // Keep a blank line so positions in the rest of the source don't move
+        lines.push("");
+    }
+    lines.join("\n")
+}
+
+/// Wrap statements in a blur() function taking the command-line arguments as
+/// `args`, on the same line so line numbers don't change (`-e`, stdin without
+/// blur() and doctests)
+pub fn wrap_statements(code: &str) -> String {
+    format!("void blur(string args[]) {{ {} }}", code)
+}
@@ -0,0 +1,419 @@
+// Lints (`blur lint`): programs that run, but not the way they read
+//
+// Each rule looks for one mistake that averaging makes easy. The checker
+// (check.rs) says which declaration every name refers to; this pass walks
+// the program again to see how each one is used. Every rule has a level:
+// allowed rules aren't reported, warnings are, and denied rules are
+// reported and make `blur lint` fail.
+
+use crate::ast::*;
+use crate::builtins::{self, Param};
+use crate::check::{self, DefKind};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Level::Allow => write!(f, "allow"),
+            Level::Warn => write!(f, "warning"),
+            Level::Deny => write!(f, "error"),
+        }
+    }
+}
+
+pub struct Rule {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub default: Level,
+}
+
+pub const RULES: &[Rule] = &[
+    Rule {
+        name: "stalling-for",
+        description: "a for loop counter is averaged, so its update stalls (use sharp for)",
+        default: Level::Warn,
+    },
+    Rule {
+        name: "compound-type",
+        description: "compound assignment on a bool or string pushes a number into its history",
+        default: Level::Warn,
+    },
+    Rule {
+        name: "print-unassigned",
+        description: "print of a variable that is never assigned",
+        default: Level::Warn,
+    },
+    Rule {
+        name: "unused",
+        description: "a variable, array or parameter that is never read (names starting with _ are skipped)",
+        default: Level::Warn,
+    },
+    Rule {
+        name: "string-repeat",
+        description: "repeating a string that isn't a literal gives an empty string",
+        default: Level::Warn,
+    },
+];
+
+/// The level of every rule
+#[derive(Debug, Clone)]
+pub struct Config {
+    levels: HashMap<&'static str, Level>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config { levels: RULES.iter().map(|r| (r.name, r.default)).collect() }
+    }
+}
+
+impl Config {
+    /// Set a rule's level, or every rule's with "all"
+    pub fn set(&mut self, rule: &str, level: Level) -> Result<(), String> {
+        if rule == "all" {
+            self.levels.values_mut().for_each(|l| *l = level);
+            return Ok(());
+        }
+        match self.levels.get_mut(rule) {
+            Some(l) => {
+                *l = level;
+                Ok(())
+            }
+            None => {
+                let names: Vec<&str> = RULES.iter().map(|r| r.name).collect();
+                Err(format!("unknown lint rule '{}' (expected all, {})", rule, names.join(", ")))
+            }
+        }
+    }
+
+    pub fn level(&self, rule: &str) -> Level {
+        self.levels.get(rule).copied().unwrap_or(Level::Allow)
+    }
+}
+
+/// A rule that matched
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub rule: &'static str,
+    pub level: Level,
+    pub span: Span,
+    pub message: String,
+}
+
+/// Lint a program, in source order
+pub fn lint(program: &Program, config: &Config) -> Vec<Finding> {
+    let analysis = check::check(program);
+    let mut by_span: HashMap<usize, usize> = analysis.references.iter().map(|r| (r.span.start, r.definition)).collect();
+    by_span.extend(analysis.definitions.iter().enumerate().map(|(i, d)| (d.span.start, i)));
+    let mut linter = Linter {
+        definitions: &analysis.definitions,
+        by_span,
+        read: vec![false; analysis.definitions.len()],
+        written: analysis.definitions.iter().map(|d| d.kind != DefKind::Variable).collect(),
+        printed: Vec::new(),
+        span: Span::default(),
+        findings: Vec::new(),
+    };
+    for func in &program.functions {
+        linter.stmts(&func.body);
+    }
+
+    for (ident, definition) in std::mem::take(&mut linter.printed) {
+        if !linter.written[definition] {
+            linter.report("print-unassigned", ident.span, format!("{} is printed but never assigned", ident));
+        }
+    }
+    for (i, definition) in analysis.definitions.iter().enumerate() {
+        if !linter.read[i] && !definition.name.starts_with('_') {
+            let kind = match definition.kind {
+                DefKind::Param => "parameter",
+                DefKind::Variable => "variable",
+                DefKind::Array => "array",
+            };
+            linter.report("unused", definition.span, format!("{} {} is never read", kind, definition.name));
+        }
+    }
+
+    let mut findings: Vec<Finding> = linter
+        .findings
+        .into_iter()
+        .map(|f| Finding { level: config.level(f.rule), ..f })
+        .filter(|f| f.level != Level::Allow)
+        .collect();
+    findings.sort_by_key(|f| f.span.start);
+    findings
+}
+
+struct Linter<'a> {
+    definitions: &'a [check::Definition],
+    /// Definition of the name at a byte offset
+    by_span: HashMap<usize, usize>,
+    /// Per definition: read somewhere, assigned somewhere (or a parameter or array)
+    read: Vec<bool>,
+    written: Vec<bool>,
+    /// Variables passed to print()
+    printed: Vec<(Ident, usize)>,
+    /// The statement being linted
+    span: Span,
+    findings: Vec<Finding>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, rule: &'static str, span: Span, message: String) {
+        self.findings.push(Finding { rule, level: Level::Warn, span, message });
+    }
+
+    fn definition(&self, ident: &Ident) -> Option<usize> {
+        self.by_span.get(&ident.span.start).copied()
+    }
+
+    fn read(&mut self, ident: &Ident) {
+        if let Some(d) = self.definition(ident) {
+            self.read[d] = true;
+        }
+    }
+
+    fn write(&mut self, ident: &Ident) {
+        if let Some(d) = self.definition(ident) {
+            self.written[d] = true;
+        }
+    }
+
+    /// Element type of what `ident` refers to
+    fn base_type(&self, ident: &Ident) -> Option<&Type> {
+        let mut ty = &self.definitions[self.definition(ident)?].ty;
+        while let Type::Array(inner) = ty {
+            ty = inner;
+        }
+        Some(ty)
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        self.span = stmt.span;
+        match &stmt.kind {
+            StmtKind::VarDecl(_, ident, init) => {
+                if let Some(init) = init {
+                    self.expr(init);
+                    self.write(ident);
+                }
+            }
+            StmtKind::ArrayDecl(_, _, dims, init) => {
+                self.exprs(dims.iter().flatten());
+                match init {
+                    Some(ArrayInit::List(values)) => self.exprs(values),
+                    Some(ArrayInit::Expr(expr)) => self.expr(expr),
+                    None => {}
+                }
+            }
+
+            StmtKind::Assign(ident, value) => {
+                self.expr(value);
+                self.write(ident);
+            }
+            StmtKind::ArrayAssign(_, indices, value) => {
+                self.exprs(indices);
+                self.expr(value);
+            }
+            // Compound assignments and increments read the current value
+            StmtKind::CompoundAssign(ident, _, value) => {
+                self.expr(value);
+                self.read(ident);
+                self.write(ident);
+                self.compound(ident);
+            }
+            StmtKind::ArrayCompoundAssign(ident, indices, _, value) => {
+                self.exprs(indices);
+                self.expr(value);
+                self.read(ident);
+                self.compound(ident);
+            }
+
+            StmtKind::PreIncrement(ident)
+            | StmtKind::PreDecrement(ident)
+            | StmtKind::PostIncrement(ident)
+            | StmtKind::PostDecrement(ident) => {
+                self.read(ident);
+                self.write(ident);
+            }
+            StmtKind::ArrayPreIncrement(ident, indices)
+            | StmtKind::ArrayPreDecrement(ident, indices)
+            | StmtKind::ArrayPostIncrement(ident, indices)
+            | StmtKind::ArrayPostDecrement(ident, indices) => {
+                self.exprs(indices);
+                self.read(ident);
+            }
+
+            StmtKind::If(cond, then_branch, else_branch) => {
+                self.expr(cond);
+                self.stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            StmtKind::While(cond, body) => {
+                self.expr(cond);
+                self.stmt(body);
+            }
+            StmtKind::For(init, cond, update, body) | StmtKind::SharpFor(init, cond, update, body) => {
+                if let Some(init) = init {
+                    self.stmt(init);
+                }
+                self.span = stmt.span;
+                if let (StmtKind::For(..), Some(cond), Some(update)) = (&stmt.kind, cond, update) {
+                    self.stalling(stmt.span, cond, update);
+                }
+                if let Some(cond) = cond {
+                    self.expr(cond);
+                }
+                self.stmt(body);
+                if let Some(update) = update {
+                    self.stmt(update);
+                }
+            }
+            StmtKind::Block(stmts) => self.stmts(stmts),
+
+            StmtKind::Expr(expr) => self.expr(expr),
+            StmtKind::Print(exprs) => {
+                for expr in exprs {
+                    if let Expr::Var(ident) = expr {
+                        if let Some(d) = self.definition(ident) {
+                            self.printed.push((ident.clone(), d));
+                        }
+                    }
+                }
+                self.exprs(exprs);
+            }
+            StmtKind::Return(expr) => {
+                if let Some(expr) = expr {
+                    self.expr(expr);
+                }
+            }
+        }
+    }
+
+    /// stalling-for: the update of a plain for changes a variable its condition reads
+    fn stalling(&mut self, span: Span, cond: &Expr, update: &Stmt) {
+        let counter = match &update.kind {
+            StmtKind::Assign(ident, _)
+            | StmtKind::CompoundAssign(ident, _, _)
+            | StmtKind::PreIncrement(ident)
+            | StmtKind::PreDecrement(ident)
+            | StmtKind::PostIncrement(ident)
+            | StmtKind::PostDecrement(ident) => ident,
+            _ => return,
+        };
+        let Some(definition) = self.definition(counter) else {
+            return;
+        };
+        if self.definitions[definition].sharp || !reads(cond, &|ident| self.definition(ident) == Some(definition)) {
+            return;
+        }
+        let message = format!("counter {} is averaged with its history, so its update stalls; use sharp for", counter);
+        self.report("stalling-for", span, message);
+    }
+
+    /// compound-type: += and friends on a bool or string
+    fn compound(&mut self, ident: &Ident) {
+        if let Some(ty @ (Type::Bool | Type::String)) = self.base_type(ident) {
+            let message = format!("compound assignment on {} {} pushes a number, not a {}", ty, ident, ty);
+            self.report("compound-type", ident.span, message);
+        }
+    }
+
+    fn exprs<'e>(&mut self, exprs: impl IntoIterator<Item = &'e Expr>) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::IntLit(_) | Expr::FloatLit(_) | Expr::BoolLit(_) | Expr::CharLit(_) | Expr::StringLit(_) => {}
+            Expr::Var(ident) => self.read(ident),
+            Expr::ArrayAccess(ident, indices) => {
+                self.exprs(indices);
+                self.read(ident);
+            }
+            Expr::BinOp(left, op, right) => {
+                if *op == BinOp::Mul && self.is_string(left) {
+                    let message = "only string literals can be repeated (\"ab\" * 3); this gives an empty string";
+                    self.report("string-repeat", self.span, message.to_string());
+                }
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::StringRepeat(text, count) => {
+                if !matches!(text.as_ref(), Expr::StringLit(_)) {
+                    let message = "only string literals can be repeated (\"ab\" * 3); this gives an empty string";
+                    self.report("string-repeat", self.span, message.to_string());
+                }
+                self.expr(text);
+                self.expr(count);
+            }
+            Expr::UnaryOp(_, operand) => self.expr(operand),
+            Expr::PreIncrement(ident)
+            | Expr::PreDecrement(ident)
+            | Expr::PostIncrement(ident)
+            | Expr::PostDecrement(ident) => {
+                self.read(ident);
+                self.write(ident);
+            }
+            Expr::ArrayPreIncrement(ident, indices)
+            | Expr::ArrayPreDecrement(ident, indices)
+            | Expr::ArrayPostIncrement(ident, indices)
+            | Expr::ArrayPostDecrement(ident, indices) => {
+                self.exprs(indices);
+                self.read(ident);
+            }
+            Expr::Call(name, args) => {
+                let params = builtins::lookup(name).map_or(&[][..], |b| b.params);
+                for (i, arg) in args.iter().enumerate() {
+                    match (params.get(i), arg) {
+                        // Receives a result: written, not read
+                        (Some(Param::Var), Expr::Var(ident)) => self.write(ident),
+                        _ => self.expr(arg),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether an expression is known to be a string (other than a literal)
+    fn is_string(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Var(ident) | Expr::ArrayAccess(ident, _) => self.base_type(ident) == Some(&Type::String),
+            Expr::StringRepeat(..) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Whether `expr` reads a variable matching `is_counter`
+fn reads(expr: &Expr, is_counter: &dyn Fn(&Ident) -> bool) -> bool {
+    match expr {
+        Expr::Var(ident) => is_counter(ident),
+        Expr::ArrayAccess(_, indices) | Expr::Call(_, indices) => indices.iter().any(|e| reads(e, is_counter)),
+        Expr::BinOp(left, _, right) | Expr::StringRepeat(left, right) => {
+            reads(left, is_counter) || reads(right, is_counter)
+        }
+        Expr::UnaryOp(_, operand) => reads(operand, is_counter),
+        Expr::PreIncrement(ident)
+        | Expr::PreDecrement(ident)
+        | Expr::PostIncrement(ident)
+        | Expr::PostDecrement(ident) => is_counter(ident),
+        _ => false,
+    }
+}
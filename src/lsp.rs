@@ -0,0 +1,295 @@
+// Language server (`blur lsp`)
+//
+// Speaks the Language Server Protocol over stdin/stdout. Every open document
+// is parsed and checked (check.rs) on each change; diagnostics are pushed to
+// the client, and definitions, hovers and symbols are answered from the last
+// analysis. Documents are synced in full. Directive lines are blanked the
+// same way the CLI does it, so positions in the rest of the file match.
+
+use crate::ast::{Function, LineIndex, Program, Span, Type};
+use crate::builtins::{self, BUILTINS};
+use crate::check::{self, Analysis, DefKind};
+use crate::interpreter::ExecutionLimits;
+use crate::lexer::Token;
+use crate::parser::Parser;
+use crate::process_directives;
+use crate::rpc::{read_message, write_message};
+use logos::Logos;
+use serde_json::{json, Value as Json};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+const KEYWORDS: &[&str] = &[
+    "int", "float", "bool", "char", "string", "void", "if", "else", "while", "for", "sharp", "return", "true", "false",
+];
+
+/// Functions that aren't in the builtins table, with what they do
+const OTHER_FUNCTIONS: &[(&str, &str)] = &[
+    ("print", "print(values...): write the values, space-separated, on one line"),
+    ("blurstr", "blurstr(strings...): blur strings together position by position; \"s\" * n counts n times"),
+    ("get_blur", "get_blur(): the current blur factor"),
+];
+
+// LSP constants
+const SEVERITY_ERROR: u32 = 1;
+const SYMBOL_FUNCTION: u32 = 12;
+const COMPLETION_FUNCTION: u32 = 3;
+const COMPLETION_KEYWORD: u32 = 14;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// An open document and what is known about it
+struct Document {
+    /// The text with directive lines blanked
+    source: String,
+    lines: LineIndex,
+    /// None if the text doesn't parse
+    program: Option<Program>,
+    analysis: Analysis,
+    /// The parse error, if any
+    error: Option<(Span, String)>,
+}
+
+impl Document {
+    fn new(text: &str) -> Self {
+        let source = process_directives(text, &mut ExecutionLimits::default());
+        let lines = LineIndex::new(&source);
+        let mut parser = Parser::new(&source);
+        let (program, analysis, error) = match parser.parse_program() {
+            Ok(program) => {
+                let analysis = check::check(&program);
+                (Some(program), analysis, None)
+            }
+            Err(e) => (None, Analysis::default(), Some((parser.error_span(), format!("Parse error: {}", e)))),
+        };
+        Document { source, lines, program, analysis, error }
+    }
+
+    /// LSP position (0-based line, UTF-16 character) of a byte offset
+    fn position(&self, offset: usize) -> Json {
+        let offset = offset.min(self.source.len());
+        let span = self.lines.span(offset, offset);
+        let line = span.line as usize - 1;
+        let character: usize = self.source[offset + 1 - span.col as usize..offset].chars().map(char::len_utf16).sum();
+        json!({ "line": line, "character": character })
+    }
+
+    fn range(&self, span: Span) -> Json {
+        json!({ "start": self.position(span.start), "end": self.position(span.end) })
+    }
+
+    /// Byte offset of an LSP position
+    fn offset(&self, position: &Json) -> usize {
+        let line = position["line"].as_u64().unwrap_or(0) as usize;
+        let Some(start) = self.lines.start(line) else {
+            return self.source.len();
+        };
+        let mut units = position["character"].as_u64().unwrap_or(0) as usize;
+        let mut offset = start;
+        for c in self.source[start..].chars().take_while(|&c| c != '\n') {
+            if units == 0 {
+                break;
+            }
+            units = units.saturating_sub(c.len_utf16());
+            offset += c.len_utf8();
+        }
+        offset
+    }
+
+    fn diagnostics(&self) -> Vec<Json> {
+        let error = self.error.iter().map(|(span, message)| (*span, message.clone()));
+        let checks = self.analysis.diagnostics.iter().map(|d| (d.span, d.message.clone()));
+        error
+            .chain(checks)
+            .map(|(span, message)| {
+                json!({ "range": self.range(span), "severity": SEVERITY_ERROR, "source": "blur", "message": message })
+            })
+            .collect()
+    }
+
+    /// The name at byte `offset`, if the cursor is on one
+    fn word_at(&self, offset: usize) -> Option<(String, Span)> {
+        Token::lexer(&self.source).spanned().find_map(|(token, range)| match token {
+            Ok(Token::Identifier(name)) if range.start <= offset && offset <= range.end => {
+                Some((name, Span { start: range.start, end: range.end, ..Span::default() }))
+            }
+            Ok(Token::Print) if range.start <= offset && offset <= range.end => {
+                Some(("print".to_string(), Span { start: range.start, end: range.end, ..Span::default() }))
+            }
+            _ => None,
+        })
+    }
+
+    fn function(&self, name: &str) -> Option<&Function> {
+        self.program.as_ref()?.functions.iter().find(|f| f.name == name)
+    }
+
+    fn definition(&self, uri: &Json, offset: usize) -> Json {
+        if let Some(definition) = self.analysis.definition_at(offset) {
+            return json!({ "uri": uri, "range": self.range(definition.span) });
+        }
+        match self.word_at(offset).and_then(|(name, _)| self.function(&name)) {
+            Some(func) => json!({ "uri": uri, "range": self.range(func.name_span) }),
+            None => Json::Null,
+        }
+    }
+
+    fn hover(&self, offset: usize) -> Json {
+        let (text, span) = if let Some(definition) = self.analysis.definition_at(offset) {
+            let mut text = format!("```blur\n{}\n```", declaration(&definition.ty, &definition.name));
+            let role = match definition.kind {
+                DefKind::Param => "parameter",
+                DefKind::Variable => "variable",
+                DefKind::Array => "array",
+            };
+            text += &format!("\n\n{} of {}()", role, definition.function);
+            if definition.sharp {
+                text += "; **sharp**: assignments replace its value instead of averaging";
+            }
+            let mut spans = self.analysis.references.iter().map(|r| r.span).chain([definition.span]);
+            let span = spans.find(|s| s.start <= offset && offset <= s.end).unwrap_or(definition.span);
+            (text, span)
+        } else if let Some((name, span)) = self.word_at(offset) {
+            let text = if let Some(func) = self.function(&name) {
+                format!("```blur\n{}\n```", signature(func))
+            } else if let Some(builtin) = builtins::lookup(&name) {
+                format!("{}(): built-in, takes {}", builtin.name, builtin.usage)
+            } else if let Some((_, doc)) = OTHER_FUNCTIONS.iter().find(|(n, _)| *n == name) {
+                doc.to_string()
+            } else {
+                return Json::Null;
+            };
+            (text, span)
+        } else {
+            return Json::Null;
+        };
+        json!({ "contents": { "kind": "markdown", "value": text }, "range": self.range(span) })
+    }
+
+    fn symbols(&self) -> Vec<Json> {
+        let functions = self.program.as_ref().map_or(&[][..], |p| &p.functions[..]);
+        functions
+            .iter()
+            .map(|func| {
+                json!({
+                    "name": func.name,
+                    "detail": signature(func),
+                    "kind": SYMBOL_FUNCTION,
+                    "range": self.range(func.span),
+                    "selectionRange": self.range(func.name_span),
+                })
+            })
+            .collect()
+    }
+}
+
+/// "int grid[][]"
+fn declaration(ty: &Type, name: &str) -> String {
+    let mut base = ty;
+    let mut brackets = String::new();
+    while let Type::Array(inner) = base {
+        base = inner;
+        brackets += "[]";
+    }
+    format!("{} {}{}", base, name, brackets)
+}
+
+/// "int twice(int n)"
+fn signature(func: &Function) -> String {
+    let params: Vec<String> = func.params.iter().map(|(ty, name)| declaration(ty, name.name.as_str())).collect();
+    format!("{} {}({})", func.ret, func.name, params.join(", "))
+}
+
+fn completions() -> Vec<Json> {
+    let keywords = KEYWORDS.iter().map(|k| json!({ "label": k, "kind": COMPLETION_KEYWORD }));
+    let others = OTHER_FUNCTIONS.iter().map(|(name, doc)| json!({ "label": name, "kind": COMPLETION_FUNCTION, "detail": doc }));
+    let builtins = BUILTINS.iter().map(|b| {
+        json!({ "label": b.name, "kind": COMPLETION_FUNCTION, "detail": format!("{}(): takes {}", b.name, b.usage) })
+    });
+    keywords.chain(others).chain(builtins).collect()
+}
+
+struct Server {
+    out: Box<dyn Write>,
+    documents: HashMap<String, Document>,
+}
+
+impl Server {
+    fn send(&mut self, message: Json) -> io::Result<()> {
+        write_message(&mut self.out, &message)
+    }
+
+    fn open(&mut self, uri: &Json, text: &str) -> io::Result<()> {
+        let document = Document::new(text);
+        let diagnostics = document.diagnostics();
+        self.documents.insert(uri.as_str().unwrap_or("").to_string(), document);
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+
+    /// Answer a request; None if the method isn't supported
+    fn answer(&mut self, method: &str, params: &Json) -> Option<Json> {
+        let uri = &params["textDocument"]["uri"];
+        let document = self.documents.get(uri.as_str().unwrap_or(""));
+        let offset = |document: &Document| document.offset(&params["position"]);
+        Some(match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": {},
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "blur", "version": env!("CARGO_PKG_VERSION") },
+            }),
+            "shutdown" => Json::Null,
+            "textDocument/definition" => document.map_or(Json::Null, |d| d.definition(uri, offset(d))),
+            "textDocument/hover" => document.map_or(Json::Null, |d| d.hover(offset(d))),
+            "textDocument/completion" => completions().into(),
+            "textDocument/documentSymbol" => document.map_or(Vec::new(), Document::symbols).into(),
+            _ => return None,
+        })
+    }
+}
+
+/// Serve until the client sends `exit` or closes the input
+pub fn serve(input: impl BufRead, output: impl Write + 'static) -> io::Result<()> {
+    let mut input = input;
+    let mut server = Server { out: Box::new(output), documents: HashMap::new() };
+    while let Some(message) = read_message(&mut input)? {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        match method {
+            "exit" => return Ok(()),
+            "textDocument/didOpen" => server.open(&params["textDocument"]["uri"], params["textDocument"]["text"].as_str().unwrap_or(""))?,
+            "textDocument/didChange" => {
+                // Full sync: the last change holds the whole text
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|c| c.last()).and_then(|c| c["text"].as_str()) {
+                    server.open(&params["textDocument"]["uri"], text)?;
+                }
+            }
+            "textDocument/didClose" => {
+                server.documents.remove(params["textDocument"]["uri"].as_str().unwrap_or(""));
+            }
+            _ => {}
+        }
+        // Notifications (no id) get no response
+        let Some(id) = message.get("id").cloned() else {
+            continue;
+        };
+        let response = match server.answer(method, params) {
+            Some(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            None => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": METHOD_NOT_FOUND, "message": format!("Unsupported method '{}'", method) },
+            }),
+        };
+        server.send(response)?;
+    }
+    Ok(())
+}
@@ -1,240 +1,738 @@
-mod ast;
-mod interpreter;
-mod lexer;
-mod parser;
-mod repl;
-
-use interpreter::{Interpreter, set_decay};
-use parser::Parser;
-use std::env;
+use blur::ast::{LineIndex, Program, Type};
+use blur::cli::{AstFormat, Cli, Command, RunFlags};
+use blur::debugger::Debugger;
+use blur::interpreter::{BlurValue, Engine, ExecutionLimits, Interpreter, Value, get_decay};
+use blur::lexer::Token;
+use blur::parser::Parser;
+use blur::resolver::Undefined;
+use blur::trace::Trace;
+use blur::{analyze, check, dap, doctest, fmt, interpreter, lint, lsp, process_directives, repl, resolver, testing, wrap_statements};
+use clap::Parser as _;
+use logos::Logos;
 use std::fs;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::cell::RefCell;
 use std::process;
+use std::rc::Rc;
 
-pub const VERSION: &str = "0.1.0";
+/// Stack size for the interpreter thread, so the tree-walker reaches the call
+/// depth limit instead of overflowing the native stack
+const STACK_SIZE: usize = 256 * 1024 * 1024;
 
-fn print_help() {
-    println!(
-        r#"blur - The Blur Programming Language Interpreter v{}
-
-Blur is an esoteric language where every variable stores the average
-of all values it has ever been assigned. Welcome to regression to the mean!
-
-USAGE:
-    blur                    Start the REPL (interactive mode)
-    blur <file.blur>        Run a Blur program
-    blur -e "code"          Execute code directly
-    blur -                  Read and execute code from stdin
-    blur [OPTIONS]
-
-OPTIONS:
-    -h, --help          Print this help message
-    -v, --version       Print version information
-    -i, --repl          Start the REPL (interactive mode)
-    -e <code>           Execute code directly (statements, no blur() needed)
-    --blur <0.0-1.0>    Set blur factor for weighted averaging (default: 0.9)
-                        1.0 = maximum blur (pure average)
-                        0.9 = slight recency bias (default)
-                        0.5 = strong recency bias
-                        0.0 = no blur (only most recent value)
-
-EXAMPLE:
-    blur -e "int x = 5; x++; x = 10; print(x);"
-    echo "int x = 5; print(x);" | blur -
-
-    int money = 5;    // money = 5 (history: [5])
-    money++;          // money = 6 (history: [5, 6], avg = 5.5 -> ceil = 6)
-    money = 10;       // money = 7 (history: [5, 6, 10], avg = 7)
-
-FEATURES:
-    - Entry point: blur() function (not main!)
-    - Types: int (ceiling), float (exact), bool (ceiling of true ratio), char
-    - All C-style operators: +, -, *, /, %, ++, --, +=, -=, etc.
-    - Control flow: if/else, while, for, sharp for
-    - Functions with parameters (history travels!)
-    - Arrays with per-element history
-    - Built-in print() function
-
-ESCAPE HATCH:
-    sharp for (int i = 0; i < 10; i++) {{ ... }}
-    The loop counter 'i' behaves normally (not averaged).
-
-WEBSITE:
-    https://esolangs.org/wiki/Blur
-"#,
-        VERSION
-    );
+// Exit codes for programs that don't run to the end (from sysexits.h), kept
+// apart from the small codes programs return from blur()
+const EXIT_PARSE_ERROR: i32 = 65;
+const EXIT_NO_INPUT: i32 = 66;
+const EXIT_RUNTIME_ERROR: i32 = 70;
+const EXIT_CANT_CREATE: i32 = 73;
+
+/// How to run a program: which engine, under which limits, traced where
+#[derive(Clone)]
+struct RunOptions {
+    engine: Engine,
+    limits: ExecutionLimits,
+    trace: Option<String>,
+    /// Print the tokens or syntax tree instead of running
+    dump: Option<Dump>,
+    /// Arguments after `--`, for the program
+    args: Vec<String>,
 }
 
-fn run_file(filename: &str) {
+impl RunOptions {
+    /// Run on the VM under these limits, without tracing or dumps
+    fn new(limits: ExecutionLimits) -> Self {
+        RunOptions { engine: Engine::Bytecode, limits, trace: None, dump: None, args: Vec::new() }
+    }
+
+    /// The options the run flags give, after setting the blur factor and
+    /// history length they give
+    fn from_flags(flags: RunFlags, args: Vec<String>) -> Self {
+        flags.blur.apply();
+        let engine = if flags.tree_walk { Engine::TreeWalk } else { Engine::Bytecode };
+        let dump = match (flags.dump_tokens, flags.dump_ast) {
+            (true, _) => Some(Dump::Tokens),
+            (false, Some(AstFormat::Debug)) => Some(Dump::Ast),
+            (false, Some(AstFormat::Json)) => Some(Dump::AstJson),
+            (false, None) => None,
+        };
+        RunOptions { engine, limits: flags.limits.limits(), trace: flags.trace, dump, args }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Dump {
+    Tokens,
+    Ast,
+    AstJson,
+}
+
+fn run_file(filename: &str, options: RunOptions) {
     let source = match fs::read_to_string(filename) {
         Ok(content) => content,
         Err(e) => {
             eprintln!("Error reading file '{}': {}", filename, e);
-            process::exit(1);
+            process::exit(EXIT_NO_INPUT);
         }
     };
 
-    run_program(&source);
+    run_program(&source, options);
 }
 
-/// Process #blur directive and return remaining source
-fn process_directives(source: &str) -> String {
-    let mut lines: Vec<&str> = Vec::new();
-    for line in source.lines() {
-        let trimmed = line.trim();
-        if trimmed.starts_with("#blur") {
-            // Parse: #blur 0.9
-            let parts: Vec<&str> = trimmed.split_whitespace().collect();
-            if parts.len() >= 2 {
-                if let Ok(d) = parts[1].parse::<f64>() {
-                    set_decay(d);
-                }
-            }
-        } else {
-            lines.push(line);
-        }
+fn run_program(source: &str, mut options: RunOptions) {
+    let source = process_directives(source, &mut options.limits);
+    if options.dump == Some(Dump::Tokens) {
+        return dump_tokens(&source);
     }
-    lines.join("\n")
-}
-
-fn run_program(source: &str) {
-    let source = process_directives(source);
     let mut parser = Parser::new(&source);
-    let program = match parser.parse_program() {
+    let mut program = match parser.parse_program() {
         Ok(prog) => prog,
         Err(e) => {
             eprintln!("Parse error: {}", e);
-            process::exit(1);
+            process::exit(EXIT_PARSE_ERROR);
         }
     };
+    if let Some(dump) = options.dump {
+        return dump_ast(&program, dump);
+    }
+    reject_undefined(&resolver::resolve(&mut program), true);
 
-    let mut interpreter = Interpreter::new();
-    match interpreter.run(&program) {
-        Ok(_) => {}
+    execute(&program, options);
+}
+
+/// Print each token with the line and column it starts at; characters the
+/// lexer doesn't know are printed as errors (the parser skips them)
+fn dump_tokens(source: &str) {
+    let lines = LineIndex::new(source);
+    for (token, range) in Token::lexer(source).spanned() {
+        let span = lines.span(range.start, range.end);
+        match token {
+            Ok(token) => println!("{} {:?}", span, token),
+            Err(()) => println!("{} Error({:?})", span, &source[range]),
+        }
+    }
+}
+
+/// Print a parsed (not yet resolved) program
+fn dump_ast(program: &Program, dump: Dump) {
+    if dump == Dump::AstJson {
+        match serde_json::to_string_pretty(program) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+    } else {
+        println!("{:#?}", program);
+    }
+}
+
+/// Run a parsed program, exiting on runtime errors and with the int blur()
+/// returns
+fn execute(program: &Program, options: RunOptions) {
+    let mut interpreter = Interpreter::with_engine(options.engine);
+    interpreter.limits = options.limits;
+    interpreter.args = options.args;
+    if let Some(path) = &options.trace {
+        match Trace::create(path) {
+            Ok(trace) => interpreter.trace = Some(trace),
+            Err(e) => {
+                eprintln!("Error creating trace file '{}': {}", path, e);
+                process::exit(EXIT_CANT_CREATE);
+            }
+        }
+    }
+    let result = interpreter.run(program);
+    // The trace matters most when the program failed, so flush it either way
+    if let Some(Err(e)) = interpreter.trace.as_mut().map(|trace| trace.flush()) {
+        eprintln!("Error writing trace: {}", e);
+    }
+    match result.map_err(|e| e.to_string()).and_then(|value| exit_code(program, &value)) {
+        Ok(0) => {}
+        Ok(code) => {
+            let _ = io::stdout().flush();
+            process::exit(code);
+        }
         Err(e) => {
             eprintln!("Runtime error: {}", e);
-            process::exit(1);
+            process::exit(EXIT_RUNTIME_ERROR);
+        }
+    }
+}
+
+/// Stop before running a program that uses variables no function declares,
+/// with where they are used if `located`
+fn reject_undefined(undefined: &[Undefined], located: bool) {
+    if undefined.is_empty() {
+        return;
+    }
+    for name in undefined {
+        if located {
+            eprintln!("Error: {}: {}", name.span, name);
+        } else {
+            eprintln!("Error: {}", name);
         }
     }
+    process::exit(EXIT_PARSE_ERROR);
+}
+
+/// The exit code for what blur() returned: the value as the int blur() is
+/// declared to return (rounded up, like any int), which must be 0-255; 0 if
+/// blur() isn't declared int
+fn exit_code(program: &Program, value: &Value) -> Result<i32, String> {
+    if !program.functions.iter().any(|f| f.name == "blur" && f.ret == Type::Int) {
+        return Ok(0);
+    }
+    let mut int = BlurValue::new(Type::Int);
+    int.push_value(value);
+    match int.get() {
+        Value::Int(code @ 0..=255) => Ok(code as i32),
+        value => Err(format!("blur() returned {}, but exit codes go from 0 to 255", value)),
+    }
 }
 
-fn run_statements(code: &str) {
+fn run_statements(code: &str, mut options: RunOptions) {
     // Process directives first
-    let code = process_directives(code);
-    // Wrap statements in a blur() function and run
-    let wrapped = format!("void blur() {{ {} }}", code);
+    let code = process_directives(code, &mut options.limits);
+    if options.dump == Some(Dump::Tokens) {
+        return dump_tokens(&code);
+    }
+    let wrapped = wrap_statements(&code);
 
     let mut parser = Parser::new(&wrapped);
-    let program = match parser.parse_program() {
+    let mut program = match parser.parse_program() {
         Ok(prog) => prog,
         Err(e) => {
             eprintln!("Parse error: {}", e);
-            process::exit(1);
+            process::exit(EXIT_PARSE_ERROR);
         }
     };
+    if let Some(dump) = options.dump {
+        return dump_ast(&program, dump);
+    }
+    // The statements are wrapped onto one line, so columns would be off
+    reject_undefined(&resolver::resolve(&mut program), false);
 
-    let mut interpreter = Interpreter::new();
-    match interpreter.run(&program) {
-        Ok(_) => {}
-        Err(e) => {
-            eprintln!("Runtime error: {}", e);
+    execute(&program, options);
+}
+
+/// `blur fmt [--check] [files...]`
+fn run_fmt(check: bool, files: &[String]) {
+    if files.is_empty() {
+        let mut source = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut source) {
+            eprintln!("Error reading stdin: {}", e);
             process::exit(1);
         }
+        match fmt::format(&source) {
+            Ok(formatted) if check => {
+                if formatted != source {
+                    println!("<stdin>");
+                    process::exit(1);
+                }
+            }
+            Ok(formatted) => print!("{}", formatted),
+            Err(e) => {
+                eprintln!("<stdin>: {}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut failed = false;
+    for filename in files {
+        let source = match fs::read_to_string(filename) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("Error reading file '{}': {}", filename, e);
+                failed = true;
+                continue;
+            }
+        };
+        let formatted = match fmt::format(&source) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("{}: {}", filename, e);
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{}", filename);
+            failed = true;
+        } else if let Err(e) = fs::write(filename, formatted) {
+            eprintln!("Error writing file '{}': {}", filename, e);
+            failed = true;
+        }
+    }
+    if failed {
+        process::exit(1);
     }
 }
 
-fn run_stdin() {
-    let mut source = String::new();
-    match io::stdin().read_to_string(&mut source) {
-        Ok(_) => {}
+/// `blur test [--golden [--update]] [paths...]`
+fn run_tests(golden: bool, update: bool, mut paths: Vec<String>, options: RunOptions) {
+    if paths.is_empty() {
+        paths.push(".".to_string());
+    }
+    let files = match testing::files(&paths) {
+        Ok(files) => files,
         Err(e) => {
-            eprintln!("Error reading stdin: {}", e);
+            eprintln!("Error: {}", e);
             process::exit(1);
         }
+    };
+    let settings = testing::Settings::current(options.limits);
+    if golden {
+        return run_golden(&files, &paths, settings, options.engine, update);
+    }
+    let (mut passed, mut failures, mut errors) = (0, Vec::new(), 0);
+    for path in &files {
+        let outcomes = match testing::run_file(path, settings) {
+            Ok(outcomes) => outcomes,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                errors += 1;
+                continue;
+            }
+        };
+        for outcome in outcomes {
+            let status = if outcome.failure.is_some() { "FAILED" } else { "ok" };
+            println!("test {}::{} ... {}", path.display(), outcome.name, status);
+            match outcome.failure {
+                Some(failure) => failures.push((path, outcome.name, failure)),
+                None => passed += 1,
+            }
+        }
     }
 
-    // Process directives first
-    let source = process_directives(&source);
+    if !failures.is_empty() {
+        println!("\nfailures:");
+    }
+    for (path, name, failure) in &failures {
+        println!("\n---- {}::{} ----", path.display(), name);
+        println!("{}:{}: in {}(): {}", path.display(), failure.span, failure.function, failure.message);
+        if !failure.output.is_empty() {
+            println!("output:");
+            for line in failure.output.lines() {
+                println!("    {}", line);
+            }
+        }
+    }
+    let result = if failures.is_empty() && errors == 0 { "ok" } else { "FAILED" };
+    println!("\ntest result: {}. {} passed; {} failed", result, passed, failures.len());
+    if result != "ok" {
+        process::exit(1);
+    }
+}
 
-    // Try to detect if it's statements or a full program
-    // If it contains a function definition, treat as program
-    if source.contains("blur()") || source.contains("blur ()") {
-        run_program(&source);
-    } else {
-        run_statements(&source);
+/// `blur test --golden [--update]`: compare each file's output with its
+/// expectations. `--update` only adds expectations to files named in `paths`.
+fn run_golden(files: &[PathBuf], paths: &[String], settings: testing::Settings, engine: Engine, update: bool) {
+    let (mut passed, mut failures, mut errors) = (0, Vec::new(), 0);
+    for path in files {
+        let named = paths.iter().any(|p| Path::new(p) == path);
+        let outcome = match testing::run_golden(path, named, settings, engine, update) {
+            Ok(Some(outcome)) => outcome,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                errors += 1;
+                continue;
+            }
+        };
+        let status = match (outcome.updated, outcome.passed()) {
+            (true, _) => "updated",
+            (false, true) => "ok",
+            (false, false) => "FAILED",
+        };
+        println!("golden {} ... {}", path.display(), status);
+        if outcome.updated || outcome.passed() {
+            passed += 1;
+        } else {
+            failures.push((path, outcome));
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+    }
+    for (path, outcome) in &failures {
+        println!("\n---- {} ----", path.display());
+        if let Some(error) = &outcome.error {
+            println!("{}: Runtime error: {}", path.display(), error);
+        }
+        let expected: Vec<&str> = outcome.expected.iter().map(|e| e.text.as_str()).collect();
+        let actual: Vec<&str> = outcome.actual.lines().collect();
+        let changes = testing::diff(&expected, &actual);
+        // Point at the first expectation that isn't met (or the last one, for extra output)
+        let line = changes.iter().find(|(sign, _, _)| *sign == '-').map(|(_, i, _)| *i);
+        if let Some(expected) = line.or(expected.len().checked_sub(1)).and_then(|i| outcome.expected.get(i)) {
+            println!("{}:{}: output differs from the expected output", path.display(), expected.span);
+        } else if !changes.is_empty() {
+            println!("{}: output differs from the expected output", path.display());
+        }
+        for (sign, _, text) in changes {
+            println!("{}{}", sign, text);
+        }
+    }
+    let result = if failures.is_empty() && errors == 0 { "ok" } else { "FAILED" };
+    println!("\ngolden result: {}. {} passed; {} failed", result, passed, failures.len());
+    if result != "ok" {
+        process::exit(1);
     }
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-
-    // Parse --blur flag first (can appear anywhere)
-    let mut i = 1;
-    while i < args.len() {
-        if args[i] == "--blur" {
-            if i + 1 >= args.len() {
-                eprintln!("Error: --blur requires a value (0.0-1.0)");
-                process::exit(1);
+/// `blur doctest <files...>`
+fn run_doctest(files: &[String], options: RunOptions) {
+    let settings = testing::Settings::current(options.limits);
+    let (mut passed, mut failures, mut skipped, mut checks, mut errors) = (0, Vec::new(), 0, 0, 0);
+    for filename in files {
+        let text = match fs::read_to_string(filename) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("Error reading file '{}': {}", filename, e);
+                errors += 1;
+                continue;
             }
-            match args[i + 1].parse::<f64>() {
-                Ok(d) => set_decay(d),
-                Err(_) => {
-                    eprintln!("Error: --blur value must be a number (0.0-1.0)");
-                    process::exit(1);
+        };
+        for snippet in doctest::snippets(&text, doctest::Markup::of(filename)) {
+            let status = match doctest::run(&snippet, settings) {
+                doctest::Outcome::Passed(n) => {
+                    passed += 1;
+                    checks += n;
+                    "ok"
+                }
+                doctest::Outcome::Failed(n, failed) => {
+                    checks += n;
+                    failures.push((filename, snippet.line, failed));
+                    "FAILED"
                 }
+                doctest::Outcome::Skipped => {
+                    skipped += 1;
+                    "skipped"
+                }
+            };
+            println!("doctest {}:{} ... {}", filename, snippet.line, status);
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+    }
+    for (filename, start, failed) in &failures {
+        println!("\n---- {}:{} ----", filename, start);
+        for (line, message) in failed {
+            println!("{}:{}: {}", filename, start + line - 1, message);
+        }
+    }
+    let result = if failures.is_empty() && errors == 0 { "ok" } else { "FAILED" };
+    println!(
+        "\ndoctest result: {}. {} passed; {} failed; {} skipped; {} annotations checked",
+        result, passed, failures.len(), skipped, checks
+    );
+    if result != "ok" {
+        process::exit(1);
+    }
+}
+
+/// `blur check <files...>`: the diagnostics the language server gives, without running
+fn run_check(files: &[String]) {
+    let mut failed = false;
+    for filename in files {
+        let source = match fs::read_to_string(filename) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("Error reading file '{}': {}", filename, e);
+                failed = true;
+                continue;
             }
-            i += 2;
-        } else {
-            i += 1;
+        };
+        let source = process_directives(&source, &mut ExecutionLimits::default());
+        let mut parser = Parser::new(&source);
+        let program = match parser.parse_program() {
+            Ok(prog) => prog,
+            Err(e) => {
+                eprintln!("{}:{}: Parse error: {}", filename, parser.error_span(), e);
+                failed = true;
+                continue;
+            }
+        };
+        for diagnostic in check::check(&program).diagnostics {
+            eprintln!("{}:{}: error: {}", filename, diagnostic.span, diagnostic.message);
+            failed = true;
         }
     }
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// Stop after a write to stdout failed; quietly if the reader is gone (`| head`)
+fn write_failed(e: io::Error) -> ! {
+    if e.kind() == io::ErrorKind::BrokenPipe {
+        process::exit(0);
+    }
+    eprintln!("Error writing output: {}", e);
+    process::exit(1);
+}
 
-    // Filter out --blur and its value for remaining processing
-    let args: Vec<String> = args.iter()
-        .enumerate()
-        .filter(|(i, arg)| {
-            if arg.as_str() == "--blur" {
-                return false;
+/// `blur analyze <files...>`
+fn run_analyze(files: &[String], options: RunOptions) {
+    let mut out = io::stdout().lock();
+    let mut failed = false;
+    for filename in files {
+        let source = match fs::read_to_string(filename) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("Error reading file '{}': {}", filename, e);
+                failed = true;
+                continue;
+            }
+        };
+        // Directives can change the blur factor and the limit
+        let mut limits = options.limits;
+        let source = process_directives(&source, &mut limits);
+        let mut parser = Parser::new(&source);
+        let program = match parser.parse_program() {
+            Ok(prog) => prog,
+            Err(e) => {
+                eprintln!("{}:{}: Parse error: {}", filename, parser.error_span(), e);
+                failed = true;
+                continue;
+            }
+        };
+        for report in analyze::analyze(&program) {
+            let count = match report.iterations {
+                Some(n) => n.to_string(),
+                None => format!("more than {}", analyze::SIMULATION_LIMIT),
+            };
+            let mut line = format!("{}:{}: {} runs {} times at blur {:?}", filename, report.span, report.header, count, get_decay());
+            if report.may_return {
+                line += " (unless it returns first)";
+            }
+            let over = match (limits.max_loop_iterations, report.iterations) {
+                (Some(max), Some(n)) => n >= max as u64,
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+            if over {
+                line += &format!(", over the max-loop limit of {}", limits.max_loop_iterations.unwrap_or(0));
+                failed |= !report.may_return;
             }
-            // Also filter the value after --blur
-            if *i > 0 && args.get(i - 1).map(|s| s.as_str()) == Some("--blur") {
-                return false;
+            writeln!(out, "{}", line).unwrap_or_else(|e| write_failed(e));
+            // How long the counter stays at each value (floats rarely repeat one)
+            if report.runs.iter().all(|&(_, n)| n == 1) {
+                continue;
             }
-            true
-        })
-        .map(|(_, s)| s.clone())
-        .collect();
+            let runs: Vec<String> = report.runs.iter().take(10).map(|(value, n)| format!("{} for {}", value, n)).collect();
+            let more = if report.runs.len() > 10 { ", ..." } else { "" };
+            writeln!(out, "    {} = {}{}", report.counter, runs.join(", "), more).unwrap_or_else(|e| write_failed(e));
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
 
-    // No arguments - start REPL
-    if args.len() < 2 {
-        repl::run_repl();
+/// `blur lint [--allow|--warn|--deny <rule>]... <files...>`
+fn run_lint(allow: Vec<String>, warn: Vec<String>, deny: Vec<String>, rules: bool, files: &[String]) {
+    if rules {
+        for rule in lint::RULES {
+            println!("{:<18}{:<9}{}", rule.name, rule.default.to_string(), rule.description);
+        }
         return;
     }
+    // "all" first, so a rule named on its own overrides it
+    let mut config = lint::Config::default();
+    let levels = [(lint::Level::Allow, allow), (lint::Level::Warn, warn), (lint::Level::Deny, deny)];
+    for all in [true, false] {
+        for (level, rules) in &levels {
+            for rule in rules.iter().filter(|r| (*r == "all") == all) {
+                if let Err(e) = config.set(rule, *level) {
+                    eprintln!("Error: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+    }
 
-    match args[1].as_str() {
-        "-h" | "--help" => {
-            print_help();
+    let mut failed = false;
+    for filename in files {
+        let source = match fs::read_to_string(filename) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("Error reading file '{}': {}", filename, e);
+                failed = true;
+                continue;
+            }
+        };
+        let source = process_directives(&source, &mut ExecutionLimits::default());
+        let mut parser = Parser::new(&source);
+        let program = match parser.parse_program() {
+            Ok(prog) => prog,
+            Err(e) => {
+                eprintln!("{}:{}: Parse error: {}", filename, parser.error_span(), e);
+                failed = true;
+                continue;
+            }
+        };
+        for finding in lint::lint(&program, &config) {
+            println!("{}:{}: {}: {} [{}]", filename, finding.span, finding.level, finding.message, finding.rule);
+            failed |= finding.level == lint::Level::Deny;
         }
-        "-v" | "--version" => {
-            println!("blur {}", VERSION);
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// Run a program under the debugger, on the tree-walker
+fn run_debug(filename: &str, mut options: RunOptions) {
+    let source = match fs::read_to_string(filename) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Error reading file '{}': {}", filename, e);
+            process::exit(EXIT_NO_INPUT);
         }
-        "-i" | "--repl" => {
+    };
+    let source = process_directives(&source, &mut options.limits);
+    let mut program = match Parser::new(&source).parse_program() {
+        Ok(prog) => prog,
+        Err(e) => {
+            eprintln!("Parse error: {}", e);
+            process::exit(EXIT_PARSE_ERROR);
+        }
+    };
+    reject_undefined(&resolver::resolve(&mut program), true);
+
+    let mut interpreter = Interpreter::with_engine(Engine::TreeWalk);
+    interpreter.limits = options.limits;
+    let debugger = Rc::new(RefCell::new(Debugger::stdio(&source)));
+    Debugger::attach(&debugger, &mut interpreter);
+    let result = interpreter.run(&program);
+    interpreter.hook = None;
+    if let Err(e) = debugger.borrow_mut().finished(&interpreter, &result) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+    if matches!(result, Err(ref e) if !matches!(e, interpreter::RuntimeError::Stopped)) {
+        process::exit(EXIT_RUNTIME_ERROR);
+    }
+}
+
+fn run_stdin(mut options: RunOptions) {
+    let mut source = String::new();
+    match io::stdin().read_to_string(&mut source) {
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Error reading stdin: {}", e);
+            process::exit(EXIT_NO_INPUT);
+        }
+    }
+
+    // Process directives first
+    let source = process_directives(&source, &mut options.limits);
+
+    // Try to detect if it's statements or a full program
+    if declares_blur(&source) {
+        run_program(&source, options);
+    } else {
+        run_statements(&source, options);
+    }
+}
+
+/// Whether source declares blur() (a type, then `blur(`)
+fn declares_blur(source: &str) -> bool {
+    let tokens: Vec<Token> = Token::lexer(source).flatten().collect();
+    tokens.windows(3).any(|window| match window {
+        [Token::Int | Token::Float | Token::Bool | Token::Char | Token::StringType | Token::Void, Token::Identifier(name), Token::LParen] => name == "blur",
+        _ => false,
+    })
+}
+
+fn main() {
+    // Run on a thread with a big stack (the tree-walker recurses per call)
+    let handle = match std::thread::Builder::new().stack_size(STACK_SIZE).spawn(run_cli) {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("Error starting interpreter thread: {}", e);
+            process::exit(1);
+        }
+    };
+    // The panic message has already been printed; exit like a panicking main would
+    if handle.join().is_err() {
+        process::exit(101);
+    }
+}
+
+fn run_cli() {
+    let cli = Cli::parse();
+    let Some(command) = cli.command else {
+        let options = RunOptions::from_flags(cli.run, cli.args);
+        match (cli.code, cli.file) {
+            (Some(code), _) => run_statements(&code, options),
+            (None, Some(file)) if file == "-" => run_stdin(options),
+            (None, Some(file)) => run_file(&file, options),
+            (None, None) => repl::run_repl(),
+        }
+        return;
+    };
+
+    match command {
+        Command::Run { file, run, args } => {
+            let options = RunOptions::from_flags(run, args);
+            if file == "-" {
+                run_stdin(options);
+            } else {
+                run_file(&file, options);
+            }
+        }
+        Command::Eval { code, run, args } => run_statements(&code, RunOptions::from_flags(run, args)),
+        Command::Repl { blur } => {
+            blur.apply();
             repl::run_repl();
         }
-        "-e" => {
-            if args.len() < 3 {
-                eprintln!("Error: -e requires code argument");
-                eprintln!("Usage: blur -e \"int x = 5; print(x);\"");
-                process::exit(1);
+        Command::Check { files } => run_check(&files),
+        Command::Test { golden, update, paths, blur, limits, tree_walk } => {
+            blur.apply();
+            let mut options = RunOptions::new(limits.limits());
+            if tree_walk {
+                options.engine = Engine::TreeWalk;
             }
-            run_statements(&args[2]);
+            run_tests(golden, update, paths, options);
+        }
+        Command::Fmt { check, files } => run_fmt(check, &files),
+        Command::Lint { allow, warn, deny, rules, files } => run_lint(allow, warn, deny, rules, &files),
+        Command::Analyze { files, blur, limits } => {
+            blur.apply();
+            run_analyze(&files, RunOptions::new(limits.limits()));
         }
-        "-" => {
-            run_stdin();
+        Command::Doctest { files, blur, limits } => {
+            blur.apply();
+            run_doctest(&files, RunOptions::new(limits.limits()));
         }
-        filename => {
-            run_file(filename);
+        Command::Debug { file, blur, limits } => {
+            blur.apply();
+            run_debug(&file, RunOptions::new(limits.limits()));
+        }
+        Command::Lsp => {
+            if let Err(e) = lsp::serve(io::stdin().lock(), io::stdout()) {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+        Command::Dap { blur, limits } => {
+            blur.apply();
+            if let Err(e) = dap::serve(io::stdin().lock(), io::stdout(), limits.limits()) {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
         }
     }
 }
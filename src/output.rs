@@ -0,0 +1,42 @@
+// Output sinks
+//
+// The interpreter writes program output and diagnostics to boxed writers
+// (stdout and stderr in the CLI). A Capture collects what is written so the
+// host can read it back after the interpreter has taken ownership of the sink;
+// `blur test` keeps each test's output this way.
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// An in-memory sink whose clones share one buffer
+#[derive(Clone, Default)]
+pub struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything written so far
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+
+    /// Take everything written so far, leaving the buffer empty
+    pub fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.0.borrow_mut());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
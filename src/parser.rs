@@ -1,6 +1,7 @@
 use crate::ast::*;
 use crate::lexer::Token;
 use logos::Logos;
+use std::ops::Range;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -17,15 +18,19 @@ pub enum ParseError {
 
 pub struct Parser {
     tokens: Vec<Token>,
+    /// Byte range of each token
+    ranges: Vec<Range<usize>>,
+    lines: LineIndex,
     pos: usize,
 }
 
 impl Parser {
     pub fn new(source: &str) -> Self {
-        let tokens: Vec<Token> = Token::lexer(source)
-            .filter_map(|t| t.ok())
-            .collect();
-        Parser { tokens, pos: 0 }
+        let (tokens, ranges) = Token::lexer(source)
+            .spanned()
+            .filter_map(|(t, range)| t.ok().map(|t| (t, range)))
+            .unzip();
+        Parser { tokens, ranges, lines: LineIndex::new(source), pos: 0 }
     }
 
     fn peek(&self) -> Option<&Token> {
@@ -38,6 +43,17 @@ impl Parser {
         token
     }
 
+    /// The span from token `first` to the last token consumed
+    fn span_from(&self, first: usize) -> Span {
+        let start = self.ranges.get(first).map_or(0, |r| r.start);
+        let end = self.ranges.get(self.pos.saturating_sub(1)).map_or(start, |r| r.end);
+        self.lines.span(start, end.max(start))
+    }
+
+    fn stmt(&self, kind: StmtKind, first: usize) -> Stmt {
+        Stmt { kind, span: self.span_from(first) }
+    }
+
     fn expect(&mut self, expected: Token) -> Result<Token, ParseError> {
         match self.peek() {
             Some(t) if std::mem::discriminant(t) == std::mem::discriminant(&expected) => {
@@ -63,18 +79,54 @@ impl Parser {
     }
 
     fn parse_type(&mut self) -> Result<Type, ParseError> {
-        match self.advance() {
-            Some(Token::Int) => Ok(Type::Int),
-            Some(Token::Float) => Ok(Type::Float),
-            Some(Token::Bool) => Ok(Type::Bool),
-            Some(Token::Char) => Ok(Type::Char),
-            Some(Token::StringType) => Ok(Type::String),
-            Some(Token::Void) => Ok(Type::Void),
-            Some(t) => Err(ParseError::UnexpectedToken(t.to_string())),
+        let ty = match self.peek() {
+            Some(Token::Int) => Type::Int,
+            Some(Token::Float) => Type::Float,
+            Some(Token::Bool) => Type::Bool,
+            Some(Token::Char) => Type::Char,
+            Some(Token::StringType) => Type::String,
+            Some(Token::Void) => Type::Void,
+            Some(t) => return Err(ParseError::UnexpectedToken(t.to_string())),
+            None => return Err(ParseError::UnexpectedEof),
+        };
+        self.advance();
+        Ok(ty)
+    }
+
+    /// Consume a name, remembering where it is
+    fn identifier(&mut self) -> Result<Ident, ParseError> {
+        match self.peek() {
+            Some(Token::Identifier(name)) => {
+                let mut ident = Ident::new(name);
+                self.advance();
+                ident.span = self.span_from(self.pos - 1);
+                Ok(ident)
+            }
+            Some(t) => Err(ParseError::Expected {
+                expected: "identifier".to_string(),
+                found: t.to_string(),
+            }),
             None => Err(ParseError::UnexpectedEof),
         }
     }
 
+    /// Where the last error happened: errors are about the token parsing
+    /// stopped at (the end of the input if it ran out)
+    pub fn error_span(&self) -> Span {
+        match self.ranges.get(self.pos) {
+            Some(_) => {
+                let mut span = self.span_from(self.pos);
+                span.end = self.ranges[self.pos].end;
+                span
+            }
+            None => {
+                let mut span = self.span_from(self.ranges.len().saturating_sub(1));
+                span.start = span.end;
+                span
+            }
+        }
+    }
+
     pub fn parse_program(&mut self) -> Result<Program, ParseError> {
         let mut functions = Vec::new();
         while self.peek().is_some() {
@@ -84,30 +136,23 @@ impl Parser {
     }
 
     fn parse_function(&mut self) -> Result<Function, ParseError> {
-        // Parse return type (required for syntax) but not stored
-        self.parse_type()?;
-        let name = match self.advance() {
-            Some(Token::Identifier(s)) => s,
-            Some(t) => return Err(ParseError::Expected {
-                expected: "identifier".to_string(),
-                found: t.to_string(),
-            }),
-            None => return Err(ParseError::UnexpectedEof),
-        };
+        let first = self.pos;
+        let ret = self.parse_type()?;
+        let name = self.identifier()?;
 
         self.expect(Token::LParen)?;
         let mut params = Vec::new();
         if !self.check(&Token::RParen) {
             loop {
                 let param_type = self.parse_type()?;
-                let param_name = match self.advance() {
-                    Some(Token::Identifier(s)) => s,
-                    Some(t) => return Err(ParseError::Expected {
-                        expected: "identifier".to_string(),
-                        found: t.to_string(),
-                    }),
-                    None => return Err(ParseError::UnexpectedEof),
-                };
+                let param_name = self.identifier()?;
+                // Array parameter: int arr[], one [] per dimension
+                let mut param_type = param_type;
+                while self.check(&Token::LBracket) {
+                    self.advance();
+                    self.expect(Token::RBracket)?;
+                    param_type = Type::Array(Box::new(param_type));
+                }
                 params.push((param_type, param_name));
                 if !self.check(&Token::Comma) {
                     break;
@@ -125,58 +170,65 @@ impl Parser {
         self.expect(Token::RBrace)?;
 
         Ok(Function {
-            name,
+            name: name.to_string(),
             params,
             body,
+            ret,
+            span: self.span_from(first),
+            name_span: name.span,
         })
     }
 
     fn parse_stmt(&mut self) -> Result<Stmt, ParseError> {
+        let first = self.pos;
+        let kind = self.parse_stmt_kind()?;
+        Ok(self.stmt(kind, first))
+    }
+
+    fn parse_stmt_kind(&mut self) -> Result<StmtKind, ParseError> {
         // Variable declaration
         if self.is_type() {
             let var_type = self.parse_type()?;
-            let name = match self.advance() {
-                Some(Token::Identifier(s)) => s,
-                Some(t) => return Err(ParseError::Expected {
-                    expected: "identifier".to_string(),
-                    found: t.to_string(),
-                }),
-                None => return Err(ParseError::UnexpectedEof),
-            };
+            let name = self.identifier()?;
 
             // Check for array declaration
             if self.check(&Token::LBracket) {
-                self.advance();
-                let size = match self.advance() {
-                    Some(Token::IntLiteral(n)) => n as usize,
-                    Some(t) => return Err(ParseError::Expected {
-                        expected: "array size".to_string(),
-                        found: t.to_string(),
-                    }),
-                    None => return Err(ParseError::UnexpectedEof),
-                };
-                self.expect(Token::RBracket)?;
+                // Sizes are any expression; they may be omitted when an initializer follows
+                let mut dims = Vec::new();
+                while self.check(&Token::LBracket) {
+                    self.advance();
+                    if self.check(&Token::RBracket) {
+                        dims.push(None);
+                    } else {
+                        dims.push(Some(self.parse_expr()?));
+                    }
+                    self.expect(Token::RBracket)?;
+                }
 
                 let init = if self.check(&Token::Assign) {
                     self.advance();
-                    self.expect(Token::LBrace)?;
-                    let mut values = Vec::new();
-                    if !self.check(&Token::RBrace) {
-                        loop {
-                            values.push(self.parse_expr()?);
-                            if !self.check(&Token::Comma) {
-                                break;
-                            }
-                            self.advance();
-                        }
+                    if self.check(&Token::LBrace) {
+                        let mut values = Vec::new();
+                        self.parse_array_list(&mut values)?;
+                        Some(ArrayInit::List(values))
+                    } else {
+                        Some(ArrayInit::Expr(self.parse_expr()?))
                     }
-                    self.expect(Token::RBrace)?;
-                    Some(values)
                 } else {
                     None
                 };
+
+                if dims.iter().any(|d| d.is_none()) && init.is_none() {
+                    return Err(match self.peek() {
+                        Some(t) => ParseError::Expected {
+                            expected: "array size or initializer".to_string(),
+                            found: t.to_string(),
+                        },
+                        None => ParseError::UnexpectedEof,
+                    });
+                }
                 self.expect(Token::Semicolon)?;
-                return Ok(Stmt::ArrayDecl(var_type, name, size, init));
+                return Ok(StmtKind::ArrayDecl(var_type, name, dims, init));
             }
 
             let init = if self.check(&Token::Assign) {
@@ -186,7 +238,7 @@ impl Parser {
                 None
             };
             self.expect(Token::Semicolon)?;
-            return Ok(Stmt::VarDecl(var_type, name, init));
+            return Ok(StmtKind::VarDecl(var_type, name, init));
         }
 
         // If statement
@@ -202,7 +254,7 @@ impl Parser {
             } else {
                 None
             };
-            return Ok(Stmt::If(cond, then_branch, else_branch));
+            return Ok(StmtKind::If(cond, then_branch, else_branch));
         }
 
         // While loop
@@ -212,7 +264,7 @@ impl Parser {
             let cond = self.parse_expr()?;
             self.expect(Token::RParen)?;
             let body = Box::new(self.parse_stmt()?);
-            return Ok(Stmt::While(cond, body));
+            return Ok(StmtKind::While(cond, body));
         }
 
         // Sharp for loop (escape hatch - no averaging on loop variable)
@@ -250,7 +302,7 @@ impl Parser {
 
             self.expect(Token::RParen)?;
             let body = Box::new(self.parse_stmt()?);
-            return Ok(Stmt::SharpFor(init, cond, update, body));
+            return Ok(StmtKind::SharpFor(init, cond, update, body));
         }
 
         // For loop
@@ -287,7 +339,7 @@ impl Parser {
 
             self.expect(Token::RParen)?;
             let body = Box::new(self.parse_stmt()?);
-            return Ok(Stmt::For(init, cond, update, body));
+            return Ok(StmtKind::For(init, cond, update, body));
         }
 
         // Block
@@ -298,7 +350,7 @@ impl Parser {
                 stmts.push(self.parse_stmt()?);
             }
             self.expect(Token::RBrace)?;
-            return Ok(Stmt::Block(stmts));
+            return Ok(StmtKind::Block(stmts));
         }
 
         // Print
@@ -317,7 +369,7 @@ impl Parser {
             }
             self.expect(Token::RParen)?;
             self.expect(Token::Semicolon)?;
-            return Ok(Stmt::Print(args));
+            return Ok(StmtKind::Print(args));
         }
 
         // Return
@@ -329,7 +381,7 @@ impl Parser {
                 Some(self.parse_expr()?)
             };
             self.expect(Token::Semicolon)?;
-            return Ok(Stmt::Return(value));
+            return Ok(StmtKind::Return(value));
         }
 
         // Expression statement (assignment, increment, function call, etc.)
@@ -339,16 +391,18 @@ impl Parser {
     }
 
     fn parse_for_init(&mut self) -> Result<Stmt, ParseError> {
+        let first = self.pos;
+        let kind = self.parse_for_init_kind()?;
+        // The span leaves out the semicolon
+        let mut stmt = self.stmt(kind, first);
+        stmt.span.end = self.ranges.get(self.pos.saturating_sub(2)).map_or(stmt.span.end, |r| r.end.max(stmt.span.start));
+        Ok(stmt)
+    }
+
+    fn parse_for_init_kind(&mut self) -> Result<StmtKind, ParseError> {
         if self.is_type() {
             let var_type = self.parse_type()?;
-            let name = match self.advance() {
-                Some(Token::Identifier(s)) => s,
-                Some(t) => return Err(ParseError::Expected {
-                    expected: "identifier".to_string(),
-                    found: t.to_string(),
-                }),
-                None => return Err(ParseError::UnexpectedEof),
-            };
+            let name = self.identifier()?;
             let init = if self.check(&Token::Assign) {
                 self.advance();
                 Some(self.parse_expr()?)
@@ -356,7 +410,7 @@ impl Parser {
                 None
             };
             self.expect(Token::Semicolon)?;
-            Ok(Stmt::VarDecl(var_type, name, init))
+            Ok(StmtKind::VarDecl(var_type, name, init))
         } else {
             let stmt = self.parse_expr_stmt()?;
             self.expect(Token::Semicolon)?;
@@ -364,105 +418,118 @@ impl Parser {
         }
     }
 
+    /// Parse a brace-enclosed initializer list, flattening nested lists row by row
+    fn parse_array_list(&mut self, values: &mut Vec<Expr>) -> Result<(), ParseError> {
+        self.expect(Token::LBrace)?;
+        if !self.check(&Token::RBrace) {
+            loop {
+                if self.check(&Token::LBrace) {
+                    self.parse_array_list(values)?;
+                } else {
+                    values.push(self.parse_expr()?);
+                }
+                if !self.check(&Token::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        self.expect(Token::RBrace)?;
+        Ok(())
+    }
+
+    /// Parse one or more index expressions: [i] or [i][j]...
+    fn parse_indices(&mut self) -> Result<Vec<Expr>, ParseError> {
+        let mut indices = Vec::new();
+        while self.check(&Token::LBracket) {
+            self.advance();
+            indices.push(self.parse_expr()?);
+            self.expect(Token::RBracket)?;
+        }
+        Ok(indices)
+    }
+
     fn parse_for_update(&mut self) -> Result<Stmt, ParseError> {
-        self.parse_expr_stmt()
+        let first = self.pos;
+        let kind = self.parse_expr_stmt()?;
+        Ok(self.stmt(kind, first))
     }
 
-    fn parse_expr_stmt(&mut self) -> Result<Stmt, ParseError> {
+    fn parse_expr_stmt(&mut self) -> Result<StmtKind, ParseError> {
         // Check for pre-increment/decrement
         if self.check(&Token::PlusPlus) {
             self.advance();
-            let name = match self.advance() {
-                Some(Token::Identifier(s)) => s,
-                Some(t) => return Err(ParseError::Expected {
-                    expected: "identifier".to_string(),
-                    found: t.to_string(),
-                }),
-                None => return Err(ParseError::UnexpectedEof),
-            };
+            let name = self.identifier()?;
             if self.check(&Token::LBracket) {
-                self.advance();
-                let index = self.parse_expr()?;
-                self.expect(Token::RBracket)?;
-                return Ok(Stmt::ArrayPreIncrement(name, index));
+                let indices = self.parse_indices()?;
+                return Ok(StmtKind::ArrayPreIncrement(name, indices));
             }
-            return Ok(Stmt::PreIncrement(name));
+            return Ok(StmtKind::PreIncrement(name));
         }
 
         if self.check(&Token::MinusMinus) {
             self.advance();
-            let name = match self.advance() {
-                Some(Token::Identifier(s)) => s,
-                Some(t) => return Err(ParseError::Expected {
-                    expected: "identifier".to_string(),
-                    found: t.to_string(),
-                }),
-                None => return Err(ParseError::UnexpectedEof),
-            };
+            let name = self.identifier()?;
             if self.check(&Token::LBracket) {
-                self.advance();
-                let index = self.parse_expr()?;
-                self.expect(Token::RBracket)?;
-                return Ok(Stmt::ArrayPreDecrement(name, index));
+                let indices = self.parse_indices()?;
+                return Ok(StmtKind::ArrayPreDecrement(name, indices));
             }
-            return Ok(Stmt::PreDecrement(name));
+            return Ok(StmtKind::PreDecrement(name));
         }
 
         // Must be identifier-based statement or function call
         if let Some(Token::Identifier(name)) = self.peek().cloned() {
-            self.advance();
+            let ident = self.identifier()?;
 
             // Array access
             if self.check(&Token::LBracket) {
-                self.advance();
-                let index = self.parse_expr()?;
-                self.expect(Token::RBracket)?;
+                let indices = self.parse_indices()?;
 
                 // Array post-increment/decrement
                 if self.check(&Token::PlusPlus) {
                     self.advance();
-                    return Ok(Stmt::ArrayPostIncrement(name, index));
+                    return Ok(StmtKind::ArrayPostIncrement(ident, indices));
                 }
                 if self.check(&Token::MinusMinus) {
                     self.advance();
-                    return Ok(Stmt::ArrayPostDecrement(name, index));
+                    return Ok(StmtKind::ArrayPostDecrement(ident, indices));
                 }
 
                 // Array assignment
                 if self.check(&Token::Assign) {
                     self.advance();
                     let value = self.parse_expr()?;
-                    return Ok(Stmt::ArrayAssign(name, index, value));
+                    return Ok(StmtKind::ArrayAssign(ident, indices, value));
                 }
 
                 // Array compound assignment
                 if let Some(op) = self.try_parse_compound_op() {
                     let value = self.parse_expr()?;
-                    return Ok(Stmt::ArrayCompoundAssign(name, index, op, value));
+                    return Ok(StmtKind::ArrayCompoundAssign(ident, indices, op, value));
                 }
             }
 
             // Post-increment/decrement
             if self.check(&Token::PlusPlus) {
                 self.advance();
-                return Ok(Stmt::PostIncrement(name));
+                return Ok(StmtKind::PostIncrement(ident));
             }
             if self.check(&Token::MinusMinus) {
                 self.advance();
-                return Ok(Stmt::PostDecrement(name));
+                return Ok(StmtKind::PostDecrement(ident));
             }
 
             // Simple assignment
             if self.check(&Token::Assign) {
                 self.advance();
                 let value = self.parse_expr()?;
-                return Ok(Stmt::Assign(name, value));
+                return Ok(StmtKind::Assign(ident, value));
             }
 
             // Compound assignment
             if let Some(op) = self.try_parse_compound_op() {
                 let value = self.parse_expr()?;
-                return Ok(Stmt::CompoundAssign(name, op, value));
+                return Ok(StmtKind::CompoundAssign(ident, op, value));
             }
 
             // Function call as statement
@@ -479,25 +546,25 @@ impl Parser {
                     }
                 }
                 self.expect(Token::RParen)?;
-                return Ok(Stmt::Expr(Expr::Call(name, args)));
+                return Ok(StmtKind::Expr(Expr::Call(name, args)));
             }
 
             // Just a variable expression (shouldn't happen often)
-            return Ok(Stmt::Expr(Expr::Var(name)));
+            return Ok(StmtKind::Expr(Expr::Var(ident)));
         }
 
         // Generic expression
         let expr = self.parse_expr()?;
-        Ok(Stmt::Expr(expr))
+        Ok(StmtKind::Expr(expr))
     }
 
     fn try_parse_compound_op(&mut self) -> Option<CompoundOp> {
         let op = match self.peek() {
-            Some(Token::PlusAssign) => Some(CompoundOp::AddAssign),
-            Some(Token::MinusAssign) => Some(CompoundOp::SubAssign),
-            Some(Token::StarAssign) => Some(CompoundOp::MulAssign),
-            Some(Token::SlashAssign) => Some(CompoundOp::DivAssign),
-            Some(Token::PercentAssign) => Some(CompoundOp::ModAssign),
+            Some(Token::PlusAssign) => Some(CompoundOp::Add),
+            Some(Token::MinusAssign) => Some(CompoundOp::Sub),
+            Some(Token::StarAssign) => Some(CompoundOp::Mul),
+            Some(Token::SlashAssign) => Some(CompoundOp::Div),
+            Some(Token::PercentAssign) => Some(CompoundOp::Mod),
             _ => None,
         };
         if op.is_some() {
@@ -620,29 +687,19 @@ impl Parser {
         }
         if self.check(&Token::PlusPlus) {
             self.advance();
-            let name = match self.advance() {
-                Some(Token::Identifier(s)) => s,
-                _ => return Err(ParseError::InvalidExpr),
-            };
+            let name = self.identifier().map_err(|_| ParseError::InvalidExpr)?;
             if self.check(&Token::LBracket) {
-                self.advance();
-                let index = self.parse_expr()?;
-                self.expect(Token::RBracket)?;
-                return Ok(Expr::ArrayPreIncrement(name, Box::new(index)));
+                let indices = self.parse_indices()?;
+                return Ok(Expr::ArrayPreIncrement(name, indices));
             }
             return Ok(Expr::PreIncrement(name));
         }
         if self.check(&Token::MinusMinus) {
             self.advance();
-            let name = match self.advance() {
-                Some(Token::Identifier(s)) => s,
-                _ => return Err(ParseError::InvalidExpr),
-            };
+            let name = self.identifier().map_err(|_| ParseError::InvalidExpr)?;
             if self.check(&Token::LBracket) {
-                self.advance();
-                let index = self.parse_expr()?;
-                self.expect(Token::RBracket)?;
-                return Ok(Expr::ArrayPreDecrement(name, Box::new(index)));
+                let indices = self.parse_indices()?;
+                return Ok(Expr::ArrayPreDecrement(name, indices));
             }
             return Ok(Expr::PreDecrement(name));
         }
@@ -655,20 +712,18 @@ impl Parser {
         loop {
             if self.check(&Token::LBracket) {
                 if let Expr::Var(name) = expr {
-                    self.advance();
-                    let index = self.parse_expr()?;
-                    self.expect(Token::RBracket)?;
+                    let indices = self.parse_indices()?;
 
                     // Check for post-increment/decrement
                     if self.check(&Token::PlusPlus) {
                         self.advance();
-                        return Ok(Expr::ArrayPostIncrement(name, Box::new(index)));
+                        return Ok(Expr::ArrayPostIncrement(name, indices));
                     }
                     if self.check(&Token::MinusMinus) {
                         self.advance();
-                        return Ok(Expr::ArrayPostDecrement(name, Box::new(index)));
+                        return Ok(Expr::ArrayPostDecrement(name, indices));
                     }
-                    expr = Expr::ArrayAccess(name, Box::new(index));
+                    expr = Expr::ArrayAccess(name, indices);
                 } else {
                     break;
                 }
@@ -719,7 +774,7 @@ impl Parser {
                 Ok(Expr::StringLit(s))
             }
             Some(Token::Identifier(name)) => {
-                self.advance();
+                let ident = self.identifier()?;
                 // Function call
                 if self.check(&Token::LParen) {
                     self.advance();
@@ -736,7 +791,7 @@ impl Parser {
                     self.expect(Token::RParen)?;
                     Ok(Expr::Call(name, args))
                 } else {
-                    Ok(Expr::Var(name))
+                    Ok(Expr::Var(ident))
                 }
             }
             Some(Token::LParen) => {
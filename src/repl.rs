@@ -1,11 +1,17 @@
-use crate::ast::Stmt;
+use crate::ast::{Stmt, StmtKind};
+use crate::debugger::Debugger;
 use crate::interpreter::{get_decay, set_decay, ControlFlow, Interpreter, Value};
 use crate::lexer::Token;
 use crate::parser::Parser;
+use crate::resolver;
+use crate::snapshot;
 use logos::Logos;
 use rustyline::error::ReadlineError;
 use rustyline::{DefaultEditor, Result as RlResult};
+use std::cell::RefCell;
 use std::fs;
+use std::io::Write;
+use std::rc::Rc;
 
 const BANNER: &str = r#"
   ____  _
@@ -138,7 +144,10 @@ fn handle_command(cmd: &str, interpreter: &mut Interpreter) -> bool {
             print_repl_help();
         }
         ".clear" => {
-            *interpreter = Interpreter::new();
+            // Keep the output sinks
+            let fresh = Interpreter::new();
+            interpreter.functions = fresh.functions;
+            interpreter.scopes = fresh.scopes;
             println!("State cleared.");
         }
         ".vars" => {
@@ -167,6 +176,24 @@ fn handle_command(cmd: &str, interpreter: &mut Interpreter) -> bool {
                 eprintln!("Usage: .load <filename>");
             }
         }
+        ".save" => match arg {
+            Some(path) => match snapshot::save(interpreter, path) {
+                Ok(()) => println!("State saved to {}", path),
+                Err(e) => eprintln!("Error saving state: {}", e),
+            },
+            None => eprintln!("Usage: .save <file.json>"),
+        },
+        ".restore" => match arg {
+            Some(path) => match snapshot::restore(interpreter, path) {
+                Ok(()) => println!("State restored from {}", path),
+                Err(e) => eprintln!("Error restoring state: {}", e),
+            },
+            None => eprintln!("Usage: .restore <file.json>"),
+        },
+        ".debug" => match arg {
+            Some(code) => debug_input(interpreter, code),
+            None => eprintln!("Usage: .debug <statements>   (e.g. .debug fib(5);)"),
+        },
         ".run" => {
             if let Some(func_name) = arg {
                 run_function(interpreter, func_name);
@@ -193,10 +220,15 @@ fn load_file(interpreter: &mut Interpreter, filename: &str) {
             println!("LOADING");
             let mut parser = Parser::new(&source);
             match parser.parse_program() {
-                Ok(program) => {
+                Ok(mut program) => {
+                    resolver::resolve(&mut program);
+
                     // Register all functions
                     for func in &program.functions {
-                        interpreter.functions.insert(func.name.clone(), func.clone());
+                        if let Err(e) = interpreter.define_function(func.clone()) {
+                            eprintln!("?{} ERROR", e.to_string().to_uppercase());
+                            return;
+                        }
                     }
 
                     // List what was loaded
@@ -209,11 +241,14 @@ fn load_file(interpreter: &mut Interpreter, filename: &str) {
                     // Auto-run blur() if it exists
                     if interpreter.functions.contains_key("blur") {
                         println!("RUN");
-                        println!("");
+                        println!();
                         // Call blur() directly instead of going through execute_input
                         match interpreter.run(&program) {
                             Ok(_) => {}
-                            Err(e) => eprintln!("?{} ERROR", e.to_string().to_uppercase()),
+                            Err(e) => {
+                                interpreter.unwind();
+                                let _ = writeln!(interpreter.diagnostics, "?{} ERROR", e.to_string().to_uppercase());
+                            }
                         }
                     }
                 }
@@ -239,15 +274,27 @@ fn run_function(interpreter: &mut Interpreter, name: &str) {
     execute_input(interpreter, &call_code);
 }
 
+/// Run input with the debugger attached; it stops at the first statement
+fn debug_input(interpreter: &mut Interpreter, input: &str) {
+    let debugger = Rc::new(RefCell::new(Debugger::stdio(input)));
+    Debugger::attach(&debugger, interpreter);
+    execute_input(interpreter, input);
+    interpreter.hook = None;
+}
+
 fn execute_input(interpreter: &mut Interpreter, input: &str) {
     // First, try to parse as a function definition
     if looks_like_function(input) {
         let mut parser = Parser::new(input);
         match parser.parse_program() {
-            Ok(program) => {
+            Ok(mut program) => {
+                resolver::resolve(&mut program);
                 for func in program.functions {
-                    println!("Defined function: {}", func.name);
-                    interpreter.functions.insert(func.name.clone(), func);
+                    let name = func.name.clone();
+                    match interpreter.define_function(func) {
+                        Ok(()) => println!("Defined function: {}", name),
+                        Err(e) => eprintln!("Error: {}", e),
+                    }
                 }
                 return;
             }
@@ -263,17 +310,21 @@ fn execute_input(interpreter: &mut Interpreter, input: &str) {
     let mut parser = Parser::new(&wrapped);
 
     match parser.parse_program() {
-        Ok(program) => {
-            if let Some(func) = program.functions.first() {
+        Ok(mut program) => {
+            if let Some(func) = program.functions.first_mut() {
+                // Input runs directly in the global scope
+                resolver::resolve_stmts(&interpreter.scopes[0], &mut func.body);
+                interpreter.steps = 0;
                 for stmt in &func.body {
                     match execute_stmt(interpreter, stmt) {
                         Ok(Some(value)) => {
                             // Print non-void results
-                            println!("=> {}", value);
+                            let _ = writeln!(interpreter.output, "=> {}", value);
                         }
                         Ok(None) => {}
                         Err(e) => {
-                            eprintln!("Runtime error: {}", e);
+                            interpreter.unwind();
+                            let _ = writeln!(interpreter.diagnostics, "Runtime error: {}", e);
                         }
                     }
                 }
@@ -294,7 +345,7 @@ fn looks_like_function(input: &str) -> bool {
 
     if tokens.len() >= 3 {
         let is_type = matches!(
-            tokens.get(0),
+            tokens.first(),
             Some(Token::Int | Token::Float | Token::Bool | Token::Char | Token::StringType | Token::Void)
         );
         let is_ident = matches!(tokens.get(1), Some(Token::Identifier(_)));
@@ -309,18 +360,14 @@ fn execute_stmt(
     interpreter: &mut Interpreter,
     stmt: &Stmt,
 ) -> Result<Option<Value>, crate::interpreter::RuntimeError> {
+    // Evaluate expression statements once, to show the result
+    if let StmtKind::Expr(expr) = &stmt.kind {
+        let val = interpreter.eval_expr(expr)?;
+        return Ok((!matches!(val, Value::Void)).then_some(val));
+    }
     match interpreter.exec_stmt(stmt)? {
         ControlFlow::Return(v) => Ok(Some(v)),
-        ControlFlow::None => {
-            // For expression statements, try to show the result
-            if let Stmt::Expr(expr) = stmt {
-                let val = interpreter.eval_expr(expr)?;
-                if !matches!(val, Value::Void) {
-                    return Ok(Some(val));
-                }
-            }
-            Ok(None)
-        }
+        ControlFlow::None => Ok(None),
     }
 }
 
@@ -335,6 +382,9 @@ REPL Commands:
     .blur [value]      Show or set blur factor (0.0-1.0)
     .load <file>       Load and run a .blur file (C64 style!)
     .run [func]        Run a function (default: blur)
+    .debug <code>      Run code in the debugger (step, break, print, ...)
+    .save <file>       Save variables, functions and blur factor as JSON
+    .restore <file>    Replace the session with a saved one
 
 Navigation:
     Up/Down arrows     Navigate command history
@@ -374,18 +424,13 @@ fn print_variables(interpreter: &Interpreter) {
 
     if has_vars {
         println!("Variables:");
-        for (name, blur_val) in &interpreter.scopes[0].vars {
+        for (name, blur_val) in interpreter.scopes[0].vars.iter() {
             let val = blur_val.get();
-            let history_len = if blur_val.var_type == crate::ast::Type::Bool {
-                blur_val.bool_history.len()
-            } else {
-                blur_val.history.len()
-            };
             println!(
                 "  {} = {} (history: {} values{})",
                 name,
                 val,
-                history_len,
+                blur_val.history_len(),
                 if blur_val.sharp { ", sharp" } else { "" }
             );
         }
@@ -0,0 +1,289 @@
+// Resolver: gives every variable and array reference a (depth, slot) address
+//
+// Runs after parsing and before either engine. Scopes mirror the ones the
+// tree-walker pushes at run time: one per function call (parameters and
+// body), one per block and one per for loop. Scalars and arrays have separate
+// slots, and redeclaring a name in the same scope reuses its slot. Variables
+// are scoped dynamically: a name the function doesn't declare is left
+// `Addr::Dynamic` and looked up at run time in the scopes of its callers. A
+// dynamic name that no function of the program declares can't come from a
+// caller either; `resolve` returns those so they can be reported before the
+// program runs.
+
+use crate::ast::*;
+use crate::builtins::{self, Param};
+use crate::interpreter::Scope;
+use crate::symbol::Symbol;
+use std::collections::{HashMap, HashSet};
+
+/// A variable or array used where no function of the program declares it
+#[derive(Debug, Clone)]
+pub struct Undefined {
+    pub name: String,
+    pub span: Span,
+}
+
+impl std::fmt::Display for Undefined {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Undefined variable: {}", self.name)
+    }
+}
+
+/// Resolve every function of a program, returning the names it uses that no
+/// function declares (in the REPL they may still be globals)
+pub fn resolve(program: &mut Program) -> Vec<Undefined> {
+    let mut resolver = Resolver::default();
+    for func in &mut program.functions {
+        resolver.function(func);
+    }
+    resolver.undefined()
+}
+
+pub fn resolve_function(func: &mut Function) {
+    Resolver::default().function(func);
+}
+
+/// Resolve statements that run directly in `globals` (REPL input)
+pub fn resolve_stmts(globals: &Scope, stmts: &mut [Stmt]) {
+    let mut resolver = Resolver { scopes: vec![Names::from_scope(globals)], ..Resolver::default() };
+    resolver.stmts(stmts);
+}
+
+/// The names declared in one scope and their slots
+#[derive(Default)]
+struct Names {
+    vars: HashMap<Symbol, u32>,
+    arrays: HashMap<Symbol, u32>,
+    next_var: u32,
+    next_array: u32,
+}
+
+impl Names {
+    fn from_scope(scope: &Scope) -> Self {
+        let slots = |names: &[Symbol]| {
+            names
+                .iter()
+                .enumerate()
+                .filter(|(_, name)| **name != Symbol::EMPTY)
+                .map(|(slot, name)| (*name, slot as u32))
+                .collect()
+        };
+        Names {
+            vars: slots(&scope.vars.names),
+            arrays: slots(&scope.arrays.names),
+            next_var: scope.vars.names.len() as u32,
+            next_array: scope.arrays.names.len() as u32,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Resolver {
+    scopes: Vec<Names>,
+    /// Every name declared so far, and whether as an array
+    declared: HashSet<(Symbol, bool)>,
+    /// Names left dynamic, with whether an array, a scalar or (`None`) either was wanted
+    dynamic: Vec<(Ident, Option<bool>)>,
+}
+
+impl Resolver {
+    fn function(&mut self, func: &mut Function) {
+        self.scopes = vec![Names::default()];
+        for (ty, param) in &mut func.params {
+            self.declare(param, matches!(ty, Type::Array(_)));
+        }
+        self.stmts(&mut func.body);
+    }
+
+    /// The dynamic names no function declares, in the order they are used
+    fn undefined(self) -> Vec<Undefined> {
+        let declared = |name: Symbol, array: bool| self.declared.contains(&(name, array));
+        self.dynamic
+            .iter()
+            .filter(|(ident, array)| match array {
+                Some(array) => !declared(ident.name, *array),
+                None => !declared(ident.name, false) && !declared(ident.name, true),
+            })
+            .map(|(ident, _)| Undefined { name: ident.to_string(), span: ident.span })
+            .collect()
+    }
+
+    fn declare(&mut self, ident: &mut Ident, array: bool) {
+        self.declared.insert((ident.name, array));
+        let scope = self.scopes.last_mut().unwrap();
+        let (slots, next) = if array {
+            (&mut scope.arrays, &mut scope.next_array)
+        } else {
+            (&mut scope.vars, &mut scope.next_var)
+        };
+        let slot = *slots.entry(ident.name).or_insert_with(|| {
+            *next += 1;
+            *next - 1
+        });
+        ident.addr = if array { Addr::Array { depth: 0, slot } } else { Addr::Var { depth: 0, slot } };
+    }
+
+    fn lookup(&self, name: Symbol, array: bool) -> Option<Addr> {
+        self.scopes.iter().rev().enumerate().find_map(|(depth, scope)| {
+            let depth = depth as u32;
+            if array {
+                scope.arrays.get(&name).map(|&slot| Addr::Array { depth, slot })
+            } else {
+                scope.vars.get(&name).map(|&slot| Addr::Var { depth, slot })
+            }
+        })
+    }
+
+    fn var(&mut self, ident: &mut Ident) {
+        ident.addr = self.lookup(ident.name, false).unwrap_or(Addr::Dynamic);
+        self.note_dynamic(ident, Some(false));
+    }
+
+    fn array(&mut self, ident: &mut Ident) {
+        ident.addr = self.lookup(ident.name, true).unwrap_or(Addr::Dynamic);
+        self.note_dynamic(ident, Some(true));
+    }
+
+    /// A plain name is a scalar if there is one, otherwise a whole array
+    fn var_or_array(&mut self, ident: &mut Ident) {
+        ident.addr = self.lookup(ident.name, false).or_else(|| self.lookup(ident.name, true)).unwrap_or(Addr::Dynamic);
+        self.note_dynamic(ident, None);
+    }
+
+    fn note_dynamic(&mut self, ident: &Ident, array: Option<bool>) {
+        if ident.addr == Addr::Dynamic {
+            self.dynamic.push((ident.clone(), array));
+        }
+    }
+
+    fn stmts(&mut self, stmts: &mut [Stmt]) {
+        stmts.iter_mut().for_each(|stmt| self.stmt(stmt));
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt) {
+        match &mut stmt.kind {
+            // The initializer can't see the variable it declares
+            StmtKind::VarDecl(_, ident, init) => {
+                if let Some(init) = init {
+                    self.expr(init);
+                }
+                self.declare(ident, false);
+            }
+            StmtKind::ArrayDecl(_, ident, dims, init) => {
+                for dim in dims.iter_mut().flatten() {
+                    self.expr(dim);
+                }
+                match init {
+                    Some(ArrayInit::List(values)) => self.exprs(values),
+                    Some(ArrayInit::Expr(expr)) => self.expr(expr),
+                    None => {}
+                }
+                self.declare(ident, true);
+            }
+
+            StmtKind::Assign(ident, value) | StmtKind::CompoundAssign(ident, _, value) => {
+                self.expr(value);
+                self.var(ident);
+            }
+            StmtKind::ArrayAssign(ident, indices, value) | StmtKind::ArrayCompoundAssign(ident, indices, _, value) => {
+                self.exprs(indices);
+                self.expr(value);
+                self.array(ident);
+            }
+
+            StmtKind::PreIncrement(ident)
+            | StmtKind::PreDecrement(ident)
+            | StmtKind::PostIncrement(ident)
+            | StmtKind::PostDecrement(ident) => self.var(ident),
+            StmtKind::ArrayPreIncrement(ident, indices)
+            | StmtKind::ArrayPreDecrement(ident, indices)
+            | StmtKind::ArrayPostIncrement(ident, indices)
+            | StmtKind::ArrayPostDecrement(ident, indices) => {
+                self.exprs(indices);
+                self.array(ident);
+            }
+
+            // Branches and loop bodies without braces declare into the current scope
+            StmtKind::If(cond, then_branch, else_branch) => {
+                self.expr(cond);
+                self.stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            StmtKind::While(cond, body) => {
+                self.expr(cond);
+                self.stmt(body);
+            }
+            StmtKind::For(init, cond, update, body) | StmtKind::SharpFor(init, cond, update, body) => {
+                self.scopes.push(Names::default());
+                if let Some(init) = init {
+                    self.stmt(init);
+                }
+                if let Some(cond) = cond {
+                    self.expr(cond);
+                }
+                self.stmt(body);
+                if let Some(update) = update {
+                    self.stmt(update);
+                }
+                self.scopes.pop();
+            }
+            StmtKind::Block(stmts) => {
+                self.scopes.push(Names::default());
+                self.stmts(stmts);
+                self.scopes.pop();
+            }
+
+            StmtKind::Expr(expr) => self.expr(expr),
+            StmtKind::Print(exprs) => self.exprs(exprs),
+            StmtKind::Return(expr) => {
+                if let Some(expr) = expr {
+                    self.expr(expr);
+                }
+            }
+        }
+    }
+
+    fn exprs(&mut self, exprs: &mut [Expr]) {
+        exprs.iter_mut().for_each(|expr| self.expr(expr));
+    }
+
+    fn expr(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::IntLit(_) | Expr::FloatLit(_) | Expr::BoolLit(_) | Expr::CharLit(_) | Expr::StringLit(_) => {}
+            Expr::Var(ident) => self.var_or_array(ident),
+            Expr::ArrayAccess(ident, indices) => {
+                self.exprs(indices);
+                self.array(ident)
+            }
+            Expr::BinOp(left, _, right) | Expr::StringRepeat(left, right) => {
+                self.expr(left);
+                self.expr(right)
+            }
+            Expr::UnaryOp(_, operand) => self.expr(operand),
+            Expr::PreIncrement(ident)
+            | Expr::PreDecrement(ident)
+            | Expr::PostIncrement(ident)
+            | Expr::PostDecrement(ident) => self.var(ident),
+            Expr::ArrayPreIncrement(ident, indices)
+            | Expr::ArrayPreDecrement(ident, indices)
+            | Expr::ArrayPostIncrement(ident, indices)
+            | Expr::ArrayPostDecrement(ident, indices) => {
+                self.exprs(indices);
+                self.array(ident)
+            }
+            Expr::Call(name, args) => {
+                // Built-ins take some arguments by reference: push(arr, x), load_wav(path, rate)
+                let params = builtins::lookup(name).map_or(&[][..], |b| b.params);
+                for (i, arg) in args.iter_mut().enumerate() {
+                    match (params.get(i), arg) {
+                        (Some(Param::Array), Expr::Var(ident)) => self.array(ident),
+                        (Some(Param::Var), Expr::Var(ident)) => self.var(ident),
+                        (_, arg) => self.expr(arg),
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,34 @@
+// The base protocol of the DAP and LSP servers: JSON messages, each preceded
+// by a `Content-Length` header and a blank line
+
+use serde_json::Value as Json;
+use std::io::{self, BufRead, Write};
+
+/// The next message, or None at the end of the input
+pub fn read_message(input: &mut dyn BufRead) -> io::Result<Option<Json>> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        match line.split_once(':') {
+            Some((name, value)) if name.eq_ignore_ascii_case("Content-Length") => {
+                length = Some(value.trim().parse::<usize>().map_err(|e| invalid(e.to_string()))?);
+            }
+            _ if line.is_empty() && length.is_some() => break,
+            _ => {}
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| invalid(e.to_string()))
+}
+
+pub fn write_message(out: &mut dyn Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
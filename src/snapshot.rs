@@ -0,0 +1,106 @@
+// Snapshots: an interpreter's state as JSON
+//
+// A snapshot holds every scope (each BlurValue with its full history, sharp
+// flag and compaction summary, and every array), the defined functions and
+// the blur settings. Functions are stored as their syntax tree without
+// resolver addresses and are resolved again on restore.
+
+use crate::ast::Function;
+use crate::interpreter::{get_decay, get_raw_history, is_builtin, set_decay, set_raw_history, Interpreter, RuntimeError, Scope};
+use crate::resolver;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use thiserror::Error;
+
+/// Bumped whenever the format changes incompatibly
+pub const VERSION: u32 = 2;
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid snapshot: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Unsupported snapshot version {0} (expected {VERSION})")]
+    Version(u32),
+    #[error("Invalid snapshot: scope {scope} has {names} {kind} names for {values} values")]
+    Slots { scope: usize, kind: &'static str, names: usize, values: usize },
+    #[error("{0}")]
+    Runtime(#[from] RuntimeError),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub blur: f64,
+    pub raw_history: usize,
+    /// Outermost (global) scope first
+    pub scopes: Vec<Scope>,
+    /// Sorted by name
+    pub functions: Vec<Function>,
+}
+
+impl Snapshot {
+    pub fn capture(interpreter: &Interpreter) -> Self {
+        let mut functions: Vec<Function> = interpreter.functions.values().cloned().collect();
+        functions.sort_by(|a, b| a.name.cmp(&b.name));
+        Snapshot {
+            version: VERSION,
+            blur: get_decay(),
+            raw_history: get_raw_history(),
+            scopes: interpreter.scopes.clone(),
+            functions,
+        }
+    }
+
+    /// Replace the interpreter's scopes, functions and blur settings with the snapshot's
+    pub fn restore(self, interpreter: &mut Interpreter) -> Result<(), SnapshotError> {
+        if self.version != VERSION {
+            return Err(SnapshotError::Version(self.version));
+        }
+        let mut functions = self.functions;
+        for func in &mut functions {
+            resolver::resolve_function(func);
+        }
+        // Check the scopes and for conflicts before changing anything
+        for (scope, s) in self.scopes.iter().enumerate() {
+            let slots = [("variable", s.vars.names.len(), s.vars.values.len()), ("array", s.arrays.names.len(), s.arrays.values.len())];
+            if let Some(&(kind, names, values)) = slots.iter().find(|(_, names, values)| names != values) {
+                return Err(SnapshotError::Slots { scope, kind, names, values });
+            }
+        }
+        if let Some(func) = functions.iter().find(|f| is_builtin(&f.name)) {
+            return Err(RuntimeError::BuiltinConflict(func.name.clone()).into());
+        }
+        if let Some(func) = functions.iter().find(|f| interpreter.host(&f.name).is_some()) {
+            return Err(RuntimeError::HostConflict(func.name.clone()).into());
+        }
+        interpreter.functions = functions.into_iter().map(|f| (f.name.clone(), f)).collect();
+        interpreter.scopes = self.scopes;
+        if interpreter.scopes.is_empty() {
+            interpreter.scopes.push(Scope::new());
+        }
+        set_decay(self.blur);
+        set_raw_history(self.raw_history);
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Result<String, SnapshotError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, SnapshotError> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// Write the interpreter's state to `path`
+pub fn save(interpreter: &Interpreter, path: &str) -> Result<(), SnapshotError> {
+    fs::write(path, Snapshot::capture(interpreter).to_json()? + "\n")?;
+    Ok(())
+}
+
+/// Load the state saved at `path` into the interpreter
+pub fn restore(interpreter: &mut Interpreter, path: &str) -> Result<(), SnapshotError> {
+    Snapshot::from_json(&fs::read_to_string(path)?)?.restore(interpreter)
+}
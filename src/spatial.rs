@@ -0,0 +1,90 @@
+// Spatial blur: averaging across neighbours instead of through time.
+//
+// All filters work on 1-D and 2-D grids stored flat in row-major order
+// (a 1-D array is treated as a single row). Samples outside the grid
+// repeat the nearest edge value, so borders don't fade to zero.
+
+/// (rows, cols) of a 1-D or 2-D grid
+fn shape(dims: &[usize]) -> Option<(usize, usize)> {
+    match dims {
+        [cols] => Some((1, *cols)),
+        [rows, cols] => Some((*rows, *cols)),
+        _ => None,
+    }
+}
+
+/// Value at (row, col), clamped to the nearest edge
+fn at(values: &[f64], rows: usize, cols: usize, row: isize, col: isize) -> f64 {
+    let r = row.clamp(0, rows as isize - 1) as usize;
+    let c = col.clamp(0, cols as isize - 1) as usize;
+    values[r * cols + c]
+}
+
+/// Box blur: each value becomes the mean of the in-bounds neighbours within `radius`
+pub fn smooth(values: &[f64], dims: &[usize], radius: usize) -> Option<Vec<f64>> {
+    let (rows, cols) = shape(dims)?;
+    // A single row has no vertical neighbours
+    let row_radius = if rows == 1 { 0 } else { radius as isize };
+    let radius = radius as isize;
+    let mut out = Vec::with_capacity(values.len());
+    for row in 0..rows as isize {
+        for col in 0..cols as isize {
+            let mut sum = 0.0;
+            let mut count = 0;
+            for r in (row - row_radius).max(0)..=(row + row_radius).min(rows as isize - 1) {
+                for c in (col - radius).max(0)..=(col + radius).min(cols as isize - 1) {
+                    sum += values[r as usize * cols + c as usize];
+                    count += 1;
+                }
+            }
+            out.push(sum / count as f64);
+        }
+    }
+    Some(out)
+}
+
+/// Apply a kernel centred on every value. The kernel must have the same
+/// number of dimensions as the grid and is applied as written (not flipped).
+pub fn convolve(values: &[f64], dims: &[usize], kernel: &[f64], kernel_dims: &[usize]) -> Option<Vec<f64>> {
+    if dims.len() != kernel_dims.len() {
+        return None;
+    }
+    let (rows, cols) = shape(dims)?;
+    let (k_rows, k_cols) = shape(kernel_dims)?;
+    let (center_r, center_c) = ((k_rows / 2) as isize, (k_cols / 2) as isize);
+    let mut out = Vec::with_capacity(values.len());
+    for row in 0..rows as isize {
+        for col in 0..cols as isize {
+            let mut sum = 0.0;
+            for kr in 0..k_rows {
+                for kc in 0..k_cols {
+                    let r = row + kr as isize - center_r;
+                    let c = col + kc as isize - center_c;
+                    sum += kernel[kr * k_cols + kc] * at(values, rows, cols, r, c);
+                }
+            }
+            out.push(sum);
+        }
+    }
+    Some(out)
+}
+
+/// Gaussian blur with standard deviation `sigma` (in elements)
+pub fn gaussian(values: &[f64], dims: &[usize], sigma: f64) -> Option<Vec<f64>> {
+    shape(dims)?;
+    // Normalized 1-D kernel covering +/- 3 sigma
+    let radius = (3.0 * sigma).ceil().max(1.0) as isize;
+    let mut kernel: Vec<f64> = (-radius..=radius)
+        .map(|x| (-((x * x) as f64) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f64 = kernel.iter().sum();
+    kernel.iter_mut().for_each(|k| *k /= total);
+    let size = kernel.len();
+
+    if dims.len() == 1 {
+        return convolve(values, dims, &kernel, &[size]);
+    }
+    // The 2-D gaussian is separable: blur the rows, then the columns
+    let blurred = convolve(values, dims, &kernel, &[1, size])?;
+    convolve(&blurred, dims, &kernel, &[size, 1])
+}
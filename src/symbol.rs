@@ -0,0 +1,67 @@
+// Interned names
+//
+// Variable names are interned once by the parser, so the resolver and both
+// engines compare and hash a u32 instead of a string. Interned strings live
+// for the rest of the process.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+struct Interner {
+    ids: HashMap<&'static str, Symbol>,
+    names: Vec<&'static str>,
+}
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(|| {
+        // Symbol 0 is the empty name, used for slots nothing was declared in
+        Mutex::new(Interner {
+            ids: HashMap::from([("", Symbol(0))]),
+            names: vec![""],
+        })
+    })
+}
+
+impl Symbol {
+    /// The empty name; never the name of a variable
+    pub const EMPTY: Symbol = Symbol(0);
+
+    pub fn intern(name: &str) -> Symbol {
+        let mut interner = interner().lock().unwrap();
+        if let Some(&sym) = interner.ids.get(name) {
+            return sym;
+        }
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let sym = Symbol(interner.names.len() as u32);
+        interner.names.push(name);
+        interner.ids.insert(name, sym);
+        sym
+    }
+
+    pub fn as_str(self) -> &'static str {
+        interner().lock().unwrap().names[self.0 as usize]
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Symbol::intern(&String::deserialize(deserializer)?))
+    }
+}
@@ -0,0 +1,361 @@
+// Test runner (`blur test`)
+//
+// Finds the test_* functions of .blur files and runs each one in a fresh
+// tree-walking Interpreter, with the blur factor, history length and limits
+// set by the command line and the file's directives, so no test sees
+// another's variables or settings. A test fails when it stops with a runtime
+// error, such as a failed assert(); the statement it stopped at (tracked the
+// same way the debugger tracks it) is where the failure is reported.
+//
+// Golden files (`blur test --golden`) instead declare what blur() prints, as
+// a `// expect: <line>` comment per line or one block:
+//
+//     /* expected-output:
+//     Hello, Blur!
+//     */
+//
+// Both kinds are read in source order. `--update` rewrites them to match
+// the actual output: each `// expect:` comment in place if there are as many
+// as output lines, otherwise as one block (at the end of the file if it had
+// none).
+
+use crate::ast::{LineIndex, Program, Span};
+use crate::fmt;
+use crate::interpreter::{
+    get_decay, get_raw_history, set_decay, set_raw_history, Engine, ExecutionLimits, Interpreter,
+};
+use crate::lexer::Token;
+use crate::output::Capture;
+use crate::parser::{ParseError, Parser};
+use crate::process_directives;
+use crate::resolver;
+use logos::Logos;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+const EXPECT: &str = "// expect:";
+const BLOCK: &str = "/* expected-output:";
+
+#[derive(Error, Debug)]
+pub enum TestError {
+    #[error("{0}: {1}")]
+    Io(String, io::Error),
+    #[error("{0}:{1}: Parse error: {2}")]
+    Parse(String, Span, ParseError),
+    #[error("{0}: the output contains \"*/\", which can't be kept in an expected-output block")]
+    Unrepresentable(String),
+}
+
+/// Settings every test starts from (the file's directives apply on top)
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    pub decay: f64,
+    pub raw_history: usize,
+    pub limits: ExecutionLimits,
+}
+
+impl Settings {
+    /// The settings currently in effect
+    pub fn current(limits: ExecutionLimits) -> Self {
+        Settings { decay: get_decay(), raw_history: get_raw_history(), limits }
+    }
+
+    pub fn apply(&self) {
+        set_decay(self.decay);
+        set_raw_history(self.raw_history);
+    }
+}
+
+/// How one test went
+pub struct Outcome {
+    pub name: String,
+    /// None if it passed
+    pub failure: Option<Failure>,
+}
+
+pub struct Failure {
+    pub message: String,
+    /// The statement that failed, and the function it is in
+    pub span: Span,
+    pub function: String,
+    /// What the test printed
+    pub output: String,
+}
+
+/// The .blur files named by `paths`, searching directories recursively
+/// (skipping hidden ones and target/), in sorted order
+pub fn files(paths: &[String]) -> Result<Vec<PathBuf>, TestError> {
+    let mut files = Vec::new();
+    for path in paths {
+        collect(Path::new(path), true, &mut files)?;
+    }
+    Ok(files)
+}
+
+fn collect(path: &Path, named: bool, files: &mut Vec<PathBuf>) -> Result<(), TestError> {
+    let error = |e| TestError::Io(path.display().to_string(), e);
+    if !path.is_dir() {
+        if named || path.extension().is_some_and(|e| e == "blur") {
+            files.push(path.to_path_buf());
+        }
+        return Ok(());
+    }
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    if !named && (name.starts_with('.') || name == "target") {
+        return Ok(());
+    }
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(path).map_err(error)? {
+        entries.push(entry.map_err(error)?.path());
+    }
+    entries.sort();
+    for entry in entries {
+        collect(&entry, false, files)?;
+    }
+    Ok(())
+}
+
+/// Read, parse and resolve a file, applying its directives on top of `settings`
+fn load(path: &Path, settings: Settings) -> Result<(String, Program, Settings), TestError> {
+    let filename = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|e| TestError::Io(filename.clone(), e))?;
+
+    settings.apply();
+    let mut limits = settings.limits;
+    let code = process_directives(&source, &mut limits);
+    let settings = Settings::current(limits);
+
+    let mut parser = Parser::new(&code);
+    let mut program = parser.parse_program().map_err(|e| TestError::Parse(filename.clone(), parser.error_span(), e))?;
+    resolver::resolve(&mut program);
+    Ok((source, program, settings))
+}
+
+/// Run the test_* functions of one file, in the order they are defined
+pub fn run_file(path: &Path, settings: Settings) -> Result<Vec<Outcome>, TestError> {
+    // Directives apply to every test in the file
+    let (_, program, settings) = load(path, settings)?;
+
+    let tests = program.functions.iter().filter(|f| f.name.starts_with("test_") && f.params.is_empty());
+    let mut outcomes = Vec::new();
+    for test in tests {
+        settings.apply();
+        let output = Capture::new();
+        let mut interpreter = Interpreter::with_output(output.clone(), output.clone());
+        interpreter.limits = settings.limits;
+        // With a hook set, each frame keeps the span of its current statement
+        interpreter.hook = Some(Box::new(|_, _| Ok(())));
+        let result = program.functions.iter().try_for_each(|f| interpreter.define_function(f.clone()));
+        let result = result.and_then(|_| interpreter.call(&test.name));
+        let failure = result.err().map(|e| {
+            let frame = interpreter.frames.last();
+            Failure {
+                message: e.to_string(),
+                span: frame.map_or(test.span, |f| f.span),
+                function: frame.map_or(test.name.clone(), |f| f.name.clone()),
+                output: output.contents(),
+            }
+        });
+        outcomes.push(Outcome { name: test.name.clone(), failure });
+    }
+    Ok(outcomes)
+}
+
+/// One line of expected output
+pub struct Expected {
+    pub text: String,
+    /// Where it is declared
+    pub span: Span,
+}
+
+/// The expected output a file declares
+#[derive(Default)]
+struct Expectations {
+    lines: Vec<Expected>,
+    /// The `// expect:` comments
+    comments: Vec<Range<usize>>,
+    /// The `/* expected-output: */` blocks
+    blocks: Vec<Range<usize>>,
+}
+
+impl Expectations {
+    fn find(source: &str) -> Self {
+        let mut found = Expectations::default();
+        let lines = LineIndex::new(source);
+        let mut previous = 0;
+        let tokens = Token::lexer(source).spanned().map(|(_, range)| range);
+        for range in tokens.chain(std::iter::once(source.len()..source.len())) {
+            for comment in fmt::comments(source, previous, range.start) {
+                let text = &source[comment.clone()];
+                if let Some(line) = text.strip_prefix(EXPECT) {
+                    let line = line.strip_prefix(' ').unwrap_or(line).trim_end_matches('\r');
+                    found.lines.push(Expected { text: line.to_string(), span: lines.span(comment.start, comment.start) });
+                    found.comments.push(comment);
+                } else if let Some(body) = text.strip_prefix(BLOCK) {
+                    let body = body.strip_suffix("*/").unwrap_or(body);
+                    let mut offset = comment.start + BLOCK.len();
+                    for (i, line) in body.split_inclusive('\n').enumerate() {
+                        // Text on the first and last lines, next to the delimiters, is optional
+                        let text = line.trim_end_matches(['\n', '\r']);
+                        let (text, start) = match i {
+                            0 => (text.trim_start(), offset + line.len() - line.trim_start().len()),
+                            _ => (text, offset),
+                        };
+                        let edge = i == 0 || !line.ends_with('\n');
+                        if !(edge && text.trim().is_empty()) {
+                            found.lines.push(Expected { text: text.to_string(), span: lines.span(start, start) });
+                        }
+                        offset += line.len();
+                    }
+                    found.blocks.push(comment);
+                }
+            }
+            previous = range.end;
+        }
+        found
+    }
+
+    fn is_empty(&self) -> bool {
+        self.comments.is_empty() && self.blocks.is_empty()
+    }
+
+    /// `source` with the expectations replaced by `actual`
+    fn update(&self, source: &str, actual: &[&str]) -> Option<String> {
+        // (range, replacement), applied from the end so the ranges stay valid
+        let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+        if self.blocks.is_empty() && !self.comments.is_empty() && self.comments.len() == actual.len() {
+            for (comment, line) in self.comments.iter().zip(actual) {
+                let text = if line.is_empty() { EXPECT.to_string() } else { format!("{} {}", EXPECT, line) };
+                edits.push((comment.clone(), text));
+            }
+        } else {
+            if actual.iter().any(|line| line.contains("*/")) {
+                return None;
+            }
+            let block: String = actual.iter().map(|line| format!("{}\n", line)).collect();
+            let block = format!("{}\n{}*/", BLOCK, block);
+            for comment in &self.comments {
+                edits.push((removal(source, comment.clone()), String::new()));
+            }
+            match self.blocks.split_first() {
+                Some((first, rest)) => {
+                    edits.push((first.clone(), block));
+                    for other in rest {
+                        edits.push((removal(source, other.clone()), String::new()));
+                    }
+                }
+                None => {
+                    let separator = match source {
+                        "" => "",
+                        _ if source.ends_with('\n') => "\n",
+                        _ => "\n\n",
+                    };
+                    edits.push((source.len()..source.len(), format!("{}{}\n", separator, block)));
+                }
+            }
+        }
+        edits.sort_by_key(|(range, _)| range.start);
+        let mut updated = source.to_string();
+        for (range, text) in edits.into_iter().rev() {
+            updated.replace_range(range, &text);
+        }
+        Some(updated)
+    }
+}
+
+/// What to delete to remove a comment: its line if nothing else is on it,
+/// otherwise the comment and the spaces before it
+fn removal(source: &str, comment: Range<usize>) -> Range<usize> {
+    let line_start = source[..comment.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[comment.end..].find('\n').map_or(source.len(), |i| comment.end + i + 1);
+    let before = &source[line_start..comment.start];
+    let after = &source[comment.end..line_end];
+    if before.trim().is_empty() && after.trim().is_empty() {
+        line_start..line_end
+    } else {
+        line_start + before.trim_end().len()..comment.end
+    }
+}
+
+/// How a golden file went
+pub struct GoldenOutcome {
+    pub expected: Vec<Expected>,
+    pub actual: String,
+    /// The runtime error blur() stopped with
+    pub error: Option<String>,
+    /// Whether --update rewrote the file
+    pub updated: bool,
+}
+
+impl GoldenOutcome {
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.expected.iter().map(|e| e.text.as_str()).eq(self.actual.lines())
+    }
+}
+
+/// Run blur() of a file that declares its expected output (None if it
+/// declares none, unless it was `named` on the command line and `update` is
+/// on); with `update`, rewrite the expectations if the output differs
+pub fn run_golden(path: &Path, named: bool, settings: Settings, engine: Engine, update: bool) -> Result<Option<GoldenOutcome>, TestError> {
+    let filename = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|e| TestError::Io(filename.clone(), e))?;
+    let mut expectations = Expectations::find(&source);
+    if expectations.is_empty() && !(update && named) {
+        return Ok(None);
+    }
+    let (_, program, settings) = load(path, settings)?;
+
+    settings.apply();
+    let output = Capture::new();
+    let mut interpreter = Interpreter::with_output(output.clone(), io::stderr());
+    interpreter.engine = engine;
+    interpreter.limits = settings.limits;
+    let error = interpreter.run(&program).err().map(|e| e.to_string());
+    drop(interpreter);
+
+    let expected = std::mem::take(&mut expectations.lines);
+    let mut outcome = GoldenOutcome { expected, actual: output.contents(), error, updated: false };
+    if update && outcome.error.is_none() && (expectations.is_empty() || !outcome.passed()) {
+        let actual: Vec<&str> = outcome.actual.lines().collect();
+        let updated = expectations.update(&source, &actual).ok_or_else(|| TestError::Unrepresentable(filename.clone()))?;
+        fs::write(path, updated).map_err(|e| TestError::Io(filename, e))?;
+        outcome.updated = true;
+    }
+    Ok(Some(outcome))
+}
+
+/// The lines that differ between the expected and actual output, in order:
+/// `-` for expected lines that are missing, `+` for actual lines that
+/// weren't expected (a longest-common-subsequence diff)
+pub fn diff<'a>(expected: &[&'a str], actual: &[&'a str]) -> Vec<(char, usize, &'a str)> {
+    let (n, m) = (expected.len(), actual.len());
+    // common[i][j]: the longest common subsequence of expected[i..] and actual[j..]
+    let mut common = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && expected[i] == actual[j] {
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && common[i + 1][j] >= common[i][j + 1]) {
+            changes.push(('-', i, expected[i]));
+            i += 1;
+        } else {
+            changes.push(('+', j, actual[j]));
+            j += 1;
+        }
+    }
+    changes
+}
@@ -0,0 +1,219 @@
+// Execution traces: one JSON object per line for every history push, call,
+// return and loop iteration (`blur --trace out.jsonl`)
+//
+// Both engines report the same events in the same order, so their traces can
+// be compared line by line. Locations are those of the statement being
+// executed; `depth` is the number of calls in progress (1 inside blur()). A
+// push also has the variable's `scope`: how many scopes out from the current
+// one it was declared (0 = the current block), or null for a name the function
+// doesn't declare, found by looking through the callers' scopes.
+
+use crate::ast::{Span, Type};
+use crate::interpreter::{get_decay, BlurArray, BlurValue, RuntimeError, Value};
+use serde::Serialize;
+use serde_json::Value as Json;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopKind {
+    For,
+    SharpFor,
+    While,
+}
+
+impl LoopKind {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            LoopKind::For => "for",
+            LoopKind::SharpFor => "sharp for",
+            LoopKind::While => "while",
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum Event<'a> {
+    /// `count` values were pushed (more than one for "str" * n)
+    Push {
+        name: String,
+        depth: usize,
+        scope: Option<u32>,
+        raw: Json,
+        value: Json,
+        count: usize,
+        decay: f64,
+        line: u32,
+        col: u32,
+    },
+    Call {
+        name: &'a str,
+        depth: usize,
+        line: u32,
+        col: u32,
+    },
+    Return {
+        name: &'a str,
+        depth: usize,
+        value: Json,
+    },
+    Loop {
+        kind: &'static str,
+        iteration: usize,
+        depth: usize,
+        line: u32,
+        col: u32,
+    },
+}
+
+pub struct Trace {
+    out: Box<dyn Write>,
+    /// The statement being executed
+    location: Span,
+    /// Where each call in progress was made from
+    callers: Vec<Span>,
+}
+
+impl Trace {
+    pub fn new(out: impl Write + 'static) -> Self {
+        Trace { out: Box::new(out), location: Span::default(), callers: Vec::new() }
+    }
+
+    /// A trace written to the file at `path`
+    pub fn create(path: &str) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Set the location of the statement about to run
+    pub fn at(&mut self, span: Span) {
+        self.location = span;
+    }
+
+    fn write(&mut self, event: &Event) -> Result<(), RuntimeError> {
+        let io_error = |e: io::Error| RuntimeError::Io(e.to_string());
+        serde_json::to_writer(&mut self.out, event).map_err(|e| io_error(e.into()))?;
+        writeln!(self.out).map_err(io_error)
+    }
+
+    /// Record a push into `name` (or its element at `indices`), declared `scope`
+    /// scopes out. `pushed` is the value given to `push_value`; other values are
+    /// read back from the history.
+    pub fn push(
+        &mut self,
+        name: &str,
+        scope: Option<u32>,
+        indices: &[i64],
+        var: &BlurValue,
+        pushed: Option<&Value>,
+        count: usize,
+    ) -> Result<(), RuntimeError> {
+        let name = indices.iter().fold(name.to_string(), |name, i| format!("{}[{}]", name, i));
+        self.write(&Event::Push {
+            name,
+            depth: self.callers.len(),
+            scope,
+            raw: raw(var, pushed),
+            value: json(&var.get()),
+            count,
+            decay: get_decay(),
+            line: self.location.line,
+            col: self.location.col,
+        })
+    }
+
+    /// Record the pushes a builtin made into the array it works on
+    pub fn array_builtin(&mut self, builtin: &str, name: &str, scope: Option<u32>, arr: &BlurArray) -> Result<(), RuntimeError> {
+        match builtin {
+            // push() appends one element
+            "push" => match arr.elems.last() {
+                Some(elem) => self.push(name, scope, &[arr.elems.len() as i64 - 1], elem, None, 1),
+                None => Ok(()),
+            },
+            // The filters push into every element
+            "smooth" | "convolve" | "gaussian" => {
+                for (i, elem) in arr.elems.iter().enumerate() {
+                    self.push(name, scope, &unflatten(&arr.dims, i), elem, None, 1)?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Record the push into element `index` of an array initializer list (the
+    /// array is declared in the current scope)
+    pub fn init_elem(&mut self, name: &str, arr: &BlurArray, index: usize, pushed: &Value) -> Result<(), RuntimeError> {
+        self.push(name, Some(0), &unflatten(&arr.dims, index), &arr.elems[index], Some(pushed), 1)
+    }
+
+    /// Record a call to `name`, made from the current statement
+    pub fn call(&mut self, name: &str) -> Result<(), RuntimeError> {
+        self.callers.push(self.location);
+        self.write(&Event::Call {
+            name,
+            depth: self.callers.len(),
+            line: self.location.line,
+            col: self.location.col,
+        })
+    }
+
+    /// Record the return from `name`; the caller's statement is current again
+    pub fn ret(&mut self, name: &str, value: &Value) -> Result<(), RuntimeError> {
+        self.write(&Event::Return { name, depth: self.callers.len(), value: json(value) })?;
+        if let Some(location) = self.callers.pop() {
+            self.location = location;
+        }
+        Ok(())
+    }
+
+    /// Record the start of a loop's `iteration`th pass (counting from 1)
+    pub fn iteration(&mut self, kind: LoopKind, iteration: usize, span: Span) -> Result<(), RuntimeError> {
+        self.write(&Event::Loop {
+            kind: kind.as_str(),
+            iteration,
+            depth: self.callers.len(),
+            line: span.line,
+            col: span.col,
+        })
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// The raw value of the latest push
+fn raw(var: &BlurValue, pushed: Option<&Value>) -> Json {
+    match (&var.var_type, pushed) {
+        (Type::Bool, _) => var.bool_history.last().map_or(Json::Null, |&b| b.into()),
+        // Strings keep per-position histories; a fresh element's value is what was pushed
+        (Type::String, Some(value)) => json(value),
+        (Type::String, None) => json(&var.get()),
+        _ => var.history.last().map_or(Json::Null, |&x| x.into()),
+    }
+}
+
+fn json(value: &Value) -> Json {
+    match value {
+        Value::Int(n) => (*n).into(),
+        Value::Float(x) => (*x).into(),
+        Value::Bool(b) => (*b).into(),
+        Value::Char(c) => c.to_string().into(),
+        Value::String(s) => s.as_str().into(),
+        Value::Array(arr) => arr.to_string().into(),
+        Value::Void => Json::Null,
+    }
+}
+
+/// The indices of the element at `offset` in an array of shape `dims`
+fn unflatten(dims: &[usize], mut offset: usize) -> Vec<i64> {
+    let mut indices = vec![0; dims.len()];
+    for (index, &dim) in indices.iter_mut().zip(dims).rev() {
+        if dim > 0 {
+            *index = (offset % dim) as i64;
+            offset /= dim;
+        }
+    }
+    indices
+}
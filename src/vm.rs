@@ -0,0 +1,509 @@
+// Stack VM for compiled Blur programs
+//
+// Executes the output of `compiler::compile` with the same blur semantics as
+// the tree-walking interpreter: locals are BlurValues/BlurArrays in slots,
+// expressions work on a value stack, and call arguments are collected on a
+// separate stack so they keep their history. Calls don't recurse in Rust.
+
+use crate::ast::Type;
+use crate::builtins::{self, Target};
+use crate::compiler::{CompiledProgram, Init, Local, Op, TargetRef};
+use crate::interpreter::{
+    array_size, check_param, compound, copy_array, eval_binop, inc, list_dims, Arg, BlurArray, BlurValue, Interpreter,
+    RuntimeError, Value,
+};
+
+/// Contents of a local slot
+#[derive(Clone)]
+enum Slot {
+    Empty,
+    Var(BlurValue),
+    Array(BlurArray),
+}
+
+struct Frame {
+    func: usize,
+    ip: usize,
+    /// First local slot of this call
+    base: usize,
+    /// Height of the value stack when the call started
+    stack: usize,
+    /// Iteration counters of the function's regular for loops
+    loops: Vec<usize>,
+}
+
+pub struct Vm<'a> {
+    program: &'a CompiledProgram,
+    /// Supplies the execution limits and output sink, and counts steps
+    interp: &'a mut Interpreter,
+    stack: Vec<Value>,
+    locals: Vec<Slot>,
+    args: Vec<Arg>,
+    frames: Vec<Frame>,
+    /// Arrays being built by NewArray/InitElem (initializers may call functions
+    /// that declare arrays themselves)
+    building: Vec<BlurArray>,
+}
+
+/// Run the program's blur() entry point, if there is one
+pub fn run(interp: &mut Interpreter, program: &CompiledProgram) -> Result<Value, RuntimeError> {
+    match program.entry {
+        Some(entry) => {
+            let args = interp.entry_args(&program.functions[entry as usize].params)?;
+            Vm::new(interp, program).call_entry(entry as usize, args)
+        }
+        None => Ok(Value::Void),
+    }
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(interp: &'a mut Interpreter, program: &'a CompiledProgram) -> Self {
+        Vm {
+            program,
+            interp,
+            stack: Vec::new(),
+            locals: Vec::new(),
+            args: Vec::new(),
+            frames: Vec::new(),
+            building: Vec::new(),
+        }
+    }
+
+    fn call_entry(&mut self, func: usize, args: Vec<Arg>) -> Result<Value, RuntimeError> {
+        let argc = args.len() as u32;
+        self.args = args;
+        self.enter(func, argc)?;
+        self.execute()
+    }
+
+    fn name(&self, local: Local) -> String {
+        local.name.to_string()
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap_or(Value::Void)
+    }
+
+    /// Pop `n` values, in the order they were pushed
+    fn pop_n(&mut self, n: u32) -> Vec<Value> {
+        let start = self.stack.len() - n as usize;
+        self.stack.split_off(start)
+    }
+
+    fn pop_indices(&mut self, n: u32) -> Vec<i64> {
+        self.pop_n(n).iter().map(|v| v.to_f64() as i64).collect()
+    }
+
+    /// The slot a declaration of the running function stores into
+    fn slot(&mut self, local: Local) -> &mut Slot {
+        let base = self.frames.last().unwrap().base;
+        &mut self.locals[base + local.slot as usize]
+    }
+
+    /// Where a scalar (array = false) or array is in `locals`: in the running
+    /// function's frame, or for a name it doesn't declare, the innermost
+    /// declaration of one that is live in a frame, the callers' included
+    fn index(&self, local: Local, array: bool) -> Option<usize> {
+        let frame = self.frames.last().unwrap();
+        if !local.is_dynamic() {
+            return Some(frame.base + local.slot as usize);
+        }
+        self.frames.iter().rev().find_map(|frame| {
+            // The instruction running (or the call, in a caller)
+            let at = frame.ip as u32 - 1;
+            self.program.functions[frame.func]
+                .declared
+                .iter()
+                .rev()
+                .filter(|d| d.local.name == local.name && d.from <= at && at < d.to)
+                .map(|d| frame.base + d.local.slot as usize)
+                .find(|&i| matches!((&self.locals[i], array), (Slot::Var(_), false) | (Slot::Array(_), true)))
+        })
+    }
+
+    fn var(&mut self, local: Local) -> Result<&mut BlurValue, RuntimeError> {
+        let index = self.index(local, false);
+        match index.map(|i| &mut self.locals[i]) {
+            Some(Slot::Var(var)) => Ok(var),
+            _ => Err(RuntimeError::UndefinedVar(local.name.to_string())),
+        }
+    }
+
+    fn array(&mut self, local: Local) -> Result<&mut BlurArray, RuntimeError> {
+        let index = self.index(local, true);
+        match index.map(|i| &mut self.locals[i]) {
+            Some(Slot::Array(arr)) => Ok(arr),
+            _ => Err(RuntimeError::UndefinedVar(local.name.to_string())),
+        }
+    }
+
+    /// A name the function doesn't declare that is an array, not a scalar
+    fn is_dynamic_array(&self, local: Local) -> bool {
+        local.is_dynamic() && self.index(local, false).is_none() && self.index(local, true).is_some()
+    }
+
+    /// A variable to push `count` values into; fails if its history is full
+    fn var_push(&mut self, local: Local, count: usize) -> Result<&mut BlurValue, RuntimeError> {
+        let limits = self.interp.limits;
+        let var = self.var(local)?;
+        limits.check_history(var, count)?;
+        Ok(var)
+    }
+
+    /// An array element to push a value into; fails if its history is full
+    fn elem_push(&mut self, local: Local, indices: &[i64]) -> Result<&mut BlurValue, RuntimeError> {
+        let limits = self.interp.limits;
+        let elem = self.array(local)?.elem_mut(indices)?;
+        limits.check_history(elem, 1)?;
+        Ok(elem)
+    }
+
+    /// Record a push into a local variable (or the element at `indices`) in the trace
+    fn traced(&mut self, local: Local, indices: &[i64], pushed: Option<&Value>, count: usize) -> Result<(), RuntimeError> {
+        if self.interp.trace.is_none() {
+            return Ok(());
+        }
+        let Some(index) = self.index(local, !indices.is_empty()) else {
+            return Ok(());
+        };
+        let trace = self.interp.trace.as_mut().unwrap();
+        match &self.locals[index] {
+            Slot::Var(var) => trace.push(local.name.as_str(), local.depth, indices, var, pushed, count),
+            Slot::Array(_) if indices.is_empty() => Ok(()),
+            Slot::Array(arr) => trace.push(local.name.as_str(), local.depth, indices, arr.elem(indices)?, pushed, count),
+            Slot::Empty => Ok(()),
+        }
+    }
+
+    /// Push a frame for `func`, binding the top `argc` arguments to its parameters
+    fn enter(&mut self, func: usize, argc: u32) -> Result<(), RuntimeError> {
+        self.interp.limits.check_call_depth(self.frames.len())?;
+        let args = self.args.split_off(self.args.len() - argc as usize);
+        let callee = &self.program.functions[func];
+        if let Some(trace) = &mut self.interp.trace {
+            trace.call(&callee.name)?;
+        }
+        let base = self.locals.len();
+        self.locals.resize(base + callee.slots, Slot::Empty);
+        for (((param_type, param_name), arg), &slot) in callee.params.iter().zip(args).zip(&callee.param_slots) {
+            check_param(&callee.name, param_type, param_name.name.as_str(), &arg)?;
+            self.locals[base + slot as usize] = match arg {
+                Arg::Scalar(val) => Slot::Var(val),
+                Arg::Array(arr) => Slot::Array(arr),
+            };
+        }
+        self.frames.push(Frame {
+            func,
+            ip: 0,
+            base,
+            stack: self.stack.len(),
+            loops: vec![0; callee.loops],
+        });
+        Ok(())
+    }
+
+    fn execute(&mut self) -> Result<Value, RuntimeError> {
+        let program = self.program;
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let op = &program.functions[frame.func].code[frame.ip];
+            frame.ip += 1;
+
+            match op {
+                Op::Const(i) => self.stack.push(program.constants[*i as usize].clone()),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::BinOp(op) => {
+                    let r = self.pop();
+                    let l = self.pop();
+                    self.stack.push(eval_binop(l, *op, r)?);
+                }
+                Op::Neg => {
+                    let v = self.pop();
+                    self.stack.push(Value::Float(-v.to_f64()));
+                }
+                Op::Not => {
+                    let v = self.pop();
+                    self.stack.push(Value::Bool(!v.to_bool()));
+                }
+                Op::Repeat => {
+                    let count = self.pop().to_f64() as usize;
+                    let s = self.pop();
+                    self.stack.push(match s {
+                        Value::String(s) => Value::String(s.repeat(count)),
+                        _ => Value::String(String::new()),
+                    });
+                }
+
+                Op::LoadVar(local) if self.is_dynamic_array(*local) => {
+                    let arr = self.array(*local)?.clone();
+                    self.stack.push(Value::Array(arr));
+                }
+                Op::LoadVar(local) => {
+                    let value = self.var(*local)?.get();
+                    self.stack.push(value);
+                }
+                Op::DeclVar { local, ty, init, sharp } => {
+                    let mut var = if *sharp { BlurValue::new_sharp(ty.clone()) } else { BlurValue::new(ty.clone()) };
+                    match init {
+                        Init::None => {}
+                        Init::Value => {
+                            let value = self.pop();
+                            var.push_value(&value);
+                            if let Some(trace) = &mut self.interp.trace {
+                                trace.push(local.name.as_str(), local.depth, &[], &var, Some(&value), 1)?;
+                            }
+                        }
+                        Init::Repeat(text) => {
+                            let count = self.pop().to_f64() as usize;
+                            self.interp.limits.check_history(&var, count)?;
+                            let text = &program.constants[*text as usize];
+                            if let Value::String(s) = text {
+                                var.push_string_times(s, count);
+                                if let Some(trace) = &mut self.interp.trace {
+                                    trace.push(local.name.as_str(), local.depth, &[], &var, Some(text), count)?;
+                                }
+                            }
+                        }
+                    }
+                    *self.slot(*local) = Slot::Var(var);
+                }
+                Op::StoreVar(local) => {
+                    let value = self.pop();
+                    self.var_push(*local, 1)?.push_value(&value);
+                    self.traced(*local, &[], Some(&value), 1)?;
+                }
+                Op::StoreVarRepeat { local, text } => {
+                    let count = self.pop().to_f64() as usize;
+                    let text = &program.constants[*text as usize];
+                    if let Value::String(s) = text {
+                        self.var_push(*local, count)?.push_string_times(s, count);
+                        self.traced(*local, &[], Some(text), count)?;
+                    }
+                }
+                Op::CompoundVar(local, op) => {
+                    let rhs = self.pop();
+                    let var = self.var_push(*local, 1)?;
+                    let new_val = compound(var.get_raw(), *op, &rhs)?;
+                    var.push(new_val);
+                    self.traced(*local, &[], None, 1)?;
+                }
+                Op::IncVar { local, delta, result } => {
+                    let var = self.var_push(*local, 1)?;
+                    let value = inc(var, *delta, *result);
+                    self.traced(*local, &[], None, 1)?;
+                    if let Some(value) = value {
+                        self.stack.push(value);
+                    }
+                }
+
+                Op::LoadArray(local) => {
+                    let arr = self.array(*local)?.clone();
+                    self.stack.push(Value::Array(arr));
+                }
+                Op::ArraySize => {
+                    let size = array_size(&self.pop())?;
+                    self.stack.push(Value::Int(size as i64));
+                }
+                Op::NewArray { ty, dims, list_len } => {
+                    let dims = self.pop_dims(*dims);
+                    let dims = match list_len {
+                        Some(count) => list_dims(&dims, *count as usize),
+                        None => dims.iter().map(|d| d.unwrap_or(0)).collect(),
+                    };
+                    self.interp.limits.check_array(&dims)?;
+                    self.building.push(BlurArray::try_new(ty.clone(), dims)?);
+                }
+                Op::JumpIfFull { index, target } => {
+                    let full = self.building.last().is_none_or(|arr| *index as usize >= arr.elems.len());
+                    if full {
+                        self.frames.last_mut().unwrap().ip = *target as usize;
+                    }
+                }
+                Op::InitElem { index, name } => {
+                    let value = self.pop();
+                    if let Some(arr) = self.building.last_mut() {
+                        arr.elems[*index as usize].push_value(&value);
+                        if let Some(trace) = &mut self.interp.trace {
+                            trace.init_elem(name.as_str(), arr, *index as usize, &value)?;
+                        }
+                    }
+                }
+                Op::CopyArray { name, dims } => {
+                    let value = self.pop();
+                    let dims = self.pop_dims(*dims);
+                    self.building.push(copy_array(name.as_str(), value, &dims, &self.interp.limits)?);
+                }
+                Op::StoreArray(local) => {
+                    let arr = self.building.pop().unwrap_or_else(|| BlurArray::new(Type::Void, vec![]));
+                    *self.slot(*local) = Slot::Array(arr);
+                }
+                Op::LoadElem(local, n) => {
+                    let indices = self.pop_indices(*n);
+                    let value = self.array(*local)?.elem(&indices)?.get();
+                    self.stack.push(value);
+                }
+                Op::StoreElem(local, n) => {
+                    let value = self.pop();
+                    let indices = self.pop_indices(*n);
+                    self.elem_push(*local, &indices)?.push_value(&value);
+                    self.traced(*local, &indices, Some(&value), 1)?;
+                }
+                Op::CompoundElem(local, n, op) => {
+                    let rhs = self.pop();
+                    let indices = self.pop_indices(*n);
+                    let elem = self.elem_push(*local, &indices)?;
+                    let new_val = compound(elem.get_raw(), *op, &rhs)?;
+                    elem.push(new_val);
+                    self.traced(*local, &indices, None, 1)?;
+                }
+                Op::IncElem { local, indices, delta, result } => {
+                    let indices = self.pop_indices(*indices);
+                    let elem = self.elem_push(*local, &indices)?;
+                    let value = inc(elem, *delta, *result);
+                    self.traced(*local, &indices, None, 1)?;
+                    if let Some(value) = value {
+                        self.stack.push(value);
+                    }
+                }
+
+                Op::Step(span) => {
+                    self.interp.steps += 1;
+                    self.interp.limits.check_fuel(self.interp.steps)?;
+                    if let Some(trace) = &mut self.interp.trace {
+                        trace.at(*span);
+                    }
+                }
+                Op::Jump(target) => self.frames.last_mut().unwrap().ip = *target as usize,
+                Op::JumpIfFalse(target) => {
+                    if !self.pop().to_bool() {
+                        self.frames.last_mut().unwrap().ip = *target as usize;
+                    }
+                }
+                Op::EnterScope { start, end } => {
+                    let base = self.frames.last().unwrap().base;
+                    for slot in &mut self.locals[base + *start as usize..base + *end as usize] {
+                        *slot = Slot::Empty;
+                    }
+                }
+                Op::LoopStart(counter) => self.frames.last_mut().unwrap().loops[*counter as usize] = 0,
+                Op::LoopGuard { counter, kind } => {
+                    let iterations = &mut self.frames.last_mut().unwrap().loops[*counter as usize];
+                    self.interp.limits.check_loop(*kind, *iterations)?;
+                    *iterations += 1;
+                }
+                Op::Iteration { counter, kind, span } => {
+                    if let Some(trace) = &mut self.interp.trace {
+                        let iterations = self.frames.last().unwrap().loops[*counter as usize];
+                        trace.iteration(*kind, iterations, *span)?;
+                    }
+                }
+                Op::Print(n) => {
+                    let values = self.pop_n(*n);
+                    self.interp.print(&values)?;
+                }
+                Op::Return | Op::ReturnVoid => {
+                    let value = if matches!(op, Op::Return) { self.pop() } else { Value::Void };
+                    let frame = self.frames.pop().unwrap();
+                    if let Some(trace) = &mut self.interp.trace {
+                        trace.ret(&program.functions[frame.func].name, &value)?;
+                    }
+                    self.locals.truncate(frame.base);
+                    self.stack.truncate(frame.stack);
+                    if self.frames.is_empty() {
+                        return Ok(value);
+                    }
+                    self.stack.push(value);
+                }
+
+                Op::ArgVar(local) if self.is_dynamic_array(*local) => {
+                    let arr = self.array(*local)?.clone();
+                    self.args.push(Arg::Array(arr));
+                }
+                Op::ArgVar(local) => {
+                    let var = self.var(*local)?.clone();
+                    self.args.push(Arg::Scalar(var));
+                }
+                Op::ArgArray(local) => {
+                    let arr = self.array(*local)?.clone();
+                    self.args.push(Arg::Array(arr));
+                }
+                Op::ArgElem(local, n) => {
+                    let indices = self.pop_indices(*n);
+                    let elem = self.array(*local)?.elem(&indices)?.clone();
+                    self.args.push(Arg::Scalar(elem));
+                }
+                Op::ArgValue => {
+                    let arg = match self.pop() {
+                        Value::Array(arr) => Arg::Array(arr),
+                        value => {
+                            let mut blur_val = BlurValue::new(Type::Float);
+                            blur_val.push(value.to_f64());
+                            Arg::Scalar(blur_val)
+                        }
+                    };
+                    self.args.push(arg);
+                }
+                Op::Call { func, args } => self.enter(*func as usize, *args)?,
+                Op::Builtin { index, args, target } => {
+                    let builtin = &builtins::BUILTINS[*index as usize];
+                    let values = self.pop_n(*args);
+                    let limits = self.interp.limits;
+                    let value = match *target {
+                        TargetRef::None => builtins::call(builtin.name, values, Target::None, &limits)?,
+                        TargetRef::Undefined(name) => {
+                            return Err(RuntimeError::UndefinedVar(name.to_string()));
+                        }
+                        TargetRef::Array(local) => {
+                            let name = self.name(local);
+                            let value = builtins::call(builtin.name, values, Target::Array(&name, self.array(local)?), &limits)?;
+                            let index = self.index(local, true);
+                            if let (Some(trace), Some(index)) = (&mut self.interp.trace, index) {
+                                if let Slot::Array(arr) = &self.locals[index] {
+                                    trace.array_builtin(builtin.name, &name, local.depth, arr)?;
+                                }
+                            }
+                            value
+                        }
+                        TargetRef::Var(local) => {
+                            let value = builtins::call(builtin.name, values, Target::Var(self.var_push(local, 1)?), &limits)?;
+                            self.traced(local, &[], None, 1)?;
+                            value
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                Op::Host { index, args, as_arg } => {
+                    let args = self.args.split_off(self.args.len() - *args as usize);
+                    let result = self.interp.hosts[*index as usize].call(args)?;
+                    if *as_arg {
+                        self.args.push(result.into_arg());
+                    } else {
+                        self.stack.push(result.get());
+                    }
+                }
+                Op::BlurStr(n) => {
+                    let values = self.pop_n(n * 2);
+                    let parts = values
+                        .chunks(2)
+                        .map(|pair| (pair[0].clone(), pair[1].to_f64() as usize))
+                        .collect();
+                    self.stack.push(builtins::blurstr(parts));
+                }
+                Op::Fail(i) => return Err(program.errors[*i as usize].clone()),
+            }
+        }
+    }
+
+    /// Pop array dimensions pushed by ArraySize (or Void for an omitted size)
+    fn pop_dims(&mut self, n: u32) -> Vec<Option<usize>> {
+        self.pop_n(n)
+            .iter()
+            .map(|d| match d {
+                Value::Void => None,
+                size => Some(size.to_f64() as usize),
+            })
+            .collect()
+    }
+}
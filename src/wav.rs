@@ -0,0 +1,136 @@
+// WAV (RIFF) audio input/output
+//
+// Reads 8/16/24/32-bit integer PCM and 32/64-bit float files. Samples are
+// normalized to [-1.0, 1.0] and multi-channel audio is mixed down to mono
+// by averaging the channels. Files are written as 16-bit mono PCM.
+
+use std::fs;
+use thiserror::Error;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+#[derive(Error, Debug)]
+pub enum WavError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("not a supported WAV file: {0}")]
+    Format(String),
+    #[error("cannot be written as 16-bit WAV: {0}")]
+    Unwritable(String),
+}
+
+/// Mono audio with samples in [-1.0, 1.0]
+pub struct Wav {
+    pub sample_rate: u32,
+    pub samples: Vec<f64>,
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+/// Decode one sample of the given format and width to [-1.0, 1.0]
+fn decode(bytes: &[u8], format: u16, bits: u16) -> f64 {
+    match (format, bits) {
+        (FORMAT_FLOAT, 32) => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+        (FORMAT_FLOAT, _) => f64::from_le_bytes(bytes[..8].try_into().unwrap()),
+        // 8-bit PCM is unsigned, centered on 128
+        (_, 8) => (bytes[0] as f64 - 128.0) / 128.0,
+        (_, 16) => i16::from_le_bytes([bytes[0], bytes[1]]) as f64 / 32768.0,
+        // Shift 24-bit samples into the top of an i32 to keep the sign
+        (_, 24) => (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as f64 / 8_388_608.0,
+        _ => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64 / 2_147_483_648.0,
+    }
+}
+
+pub fn load(path: &str) -> Result<Wav, WavError> {
+    let data = fs::read(path)?;
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err(WavError::Format("missing RIFF/WAVE header".to_string()));
+    }
+
+    // (format, channels, sample rate, bits per sample)
+    let mut fmt: Option<(u16, u16, u32, u16)> = None;
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let id = &data[pos..pos + 4];
+        let size = u32_at(&data, pos + 4) as usize;
+        let body = pos + 8;
+        let end = (body + size).min(data.len());
+
+        if id == b"fmt " {
+            if size < 16 || end - body < 16 {
+                return Err(WavError::Format("fmt chunk is too short".to_string()));
+            }
+            let mut format = u16_at(&data, body);
+            if format == FORMAT_EXTENSIBLE && size >= 26 {
+                // The real format is the first two bytes of the sub-format GUID
+                format = u16_at(&data, body + 24);
+            }
+            fmt = Some((format, u16_at(&data, body + 2), u32_at(&data, body + 4), u16_at(&data, body + 14)));
+        } else if id == b"data" {
+            let (format, channels, sample_rate, bits) =
+                fmt.ok_or_else(|| WavError::Format("data chunk before fmt chunk".to_string()))?;
+            let supported = match format {
+                FORMAT_PCM => matches!(bits, 8 | 16 | 24 | 32),
+                FORMAT_FLOAT => matches!(bits, 32 | 64),
+                _ => false,
+            };
+            if !supported || channels == 0 {
+                return Err(WavError::Format(format!(
+                    "format {} with {} bits per sample and {} channels",
+                    format, bits, channels
+                )));
+            }
+
+            // Mix every frame down to mono
+            let width = bits as usize / 8;
+            let frame = width * channels as usize;
+            let samples = data[body..end]
+                .chunks_exact(frame)
+                .map(|f| f.chunks_exact(width).map(|s| decode(s, format, bits)).sum::<f64>() / channels as f64)
+                .collect();
+            return Ok(Wav { sample_rate, samples });
+        }
+        // Chunks are padded to an even size
+        pos = body + size + (size & 1);
+    }
+    Err(WavError::Format("no data chunk".to_string()))
+}
+
+/// Write 16-bit mono PCM; samples outside [-1.0, 1.0] are clipped
+pub fn save(path: &str, wav: &Wav) -> Result<(), WavError> {
+    // Sizes and the byte rate are 32-bit fields
+    let byte_rate = wav.sample_rate.checked_mul(2).ok_or_else(|| {
+        WavError::Unwritable(format!("sample rate {} is too high", wav.sample_rate))
+    })?;
+    let data_size = wav.samples.len().checked_mul(2)
+        .and_then(|size| u32::try_from(size).ok())
+        .filter(|size| size.checked_add(36).is_some())
+        .ok_or_else(|| WavError::Unwritable(format!("{} samples are too many", wav.samples.len())))?;
+    let mut out = Vec::with_capacity(44 + data_size as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_size).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&FORMAT_PCM.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes()); // mono
+    out.extend_from_slice(&wav.sample_rate.to_le_bytes());
+    out.extend_from_slice(&byte_rate.to_le_bytes()); // byte rate
+    out.extend_from_slice(&2u16.to_le_bytes()); // block align
+    out.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_size.to_le_bytes());
+    for &sample in &wav.samples {
+        let value = (sample.clamp(-1.0, 1.0) * 32767.0).round() as i16;
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    fs::write(path, out)?;
+    Ok(())
+}
@@ -0,0 +1,122 @@
+// The interpreter as a library: host functions and output sinks
+
+use blur::ast::{Program, Type};
+use blur::interpreter::{BlurValue, Engine, Interpreter, RuntimeError, Value};
+use blur::output::Capture;
+use blur::parser::Parser;
+use blur::resolver;
+
+const ENGINES: [Engine; 2] = [Engine::Bytecode, Engine::TreeWalk];
+
+fn parse(source: &str) -> Program {
+    let mut program = Parser::new(source).parse_program().expect("test program parses");
+    resolver::resolve(&mut program);
+    program
+}
+
+/// An interpreter on `engine` whose output goes to the returned capture
+fn capturing(engine: Engine) -> (Interpreter, Capture) {
+    let output = Capture::new();
+    let mut interpreter = Interpreter::with_output(output.clone(), Capture::new());
+    interpreter.engine = engine;
+    (interpreter, output)
+}
+
+#[test]
+fn output_reaches_the_sink() {
+    for engine in ENGINES {
+        let (mut interpreter, output) = capturing(engine);
+        interpreter.run(&parse("void blur() { int x = 1; x = 2; print(\"x is\", x); }")).unwrap();
+        assert_eq!(output.take(), "x is 2\n");
+        assert_eq!(output.contents(), "");
+    }
+}
+
+#[test]
+fn host_functions_check_their_arity() {
+    for engine in ENGINES {
+        let (mut interpreter, _) = capturing(engine);
+        interpreter.register_fn("twice", 1, |args| Ok(Value::Float(args[0].get_raw() * 2.0))).unwrap();
+        let err = interpreter.run(&parse("void blur() { print(twice(1, 2)); }")).unwrap_err();
+        assert_eq!(err.to_string(), "Type mismatch: twice() expects 1 argument(s), got 2");
+    }
+}
+
+#[test]
+fn host_functions_get_the_history_of_their_arguments() {
+    for engine in ENGINES {
+        let (mut interpreter, output) = capturing(engine);
+        interpreter
+            .register_fn("entries", 1, |args| Ok(Value::Int(args[0].history_len() as i64)))
+            .unwrap();
+        interpreter.run(&parse("void blur() { int x = 1; x = 2; x = 3; print(entries(x)); }")).unwrap();
+        assert_eq!(output.contents(), "3\n");
+    }
+}
+
+#[test]
+fn host_functions_can_return_a_history() {
+    for engine in ENGINES {
+        let (mut interpreter, output) = capturing(engine);
+        interpreter
+            .register_fn("ramp", 0, |_| {
+                let mut value = BlurValue::new(Type::Float);
+                value.push(0.0);
+                value.push(10.0);
+                Ok(value)
+            })
+            .unwrap();
+        // Passed straight to a function, the parameter starts with both entries
+        let program = "float more(float x) { x = 20.0; return x; }\nvoid blur() { print(ramp()); print(more(ramp())); }";
+        interpreter.run(&parse(program)).unwrap();
+        assert_eq!(output.contents(), "5.2631578947368425\n10.70110701107011\n");
+    }
+}
+
+#[test]
+fn host_functions_may_not_take_a_builtin_name() {
+    let mut interpreter = Interpreter::new();
+    let err = interpreter.register_fn("len", 1, |_| Ok(Value::Int(0))).unwrap_err();
+    assert!(matches!(err, RuntimeError::BuiltinConflict(name) if name == "len"));
+}
+
+#[test]
+fn host_functions_are_registered_once() {
+    let mut interpreter = Interpreter::new();
+    interpreter.register_fn("answer", 0, |_| Ok(Value::Int(42))).unwrap();
+    let err = interpreter.register_fn("answer", 0, |_| Ok(Value::Int(43))).unwrap_err();
+    assert!(matches!(err, RuntimeError::DuplicateHost(name) if name == "answer"));
+    // get_blur() comes registered
+    let err = interpreter.register_fn("get_blur", 0, |_| Ok(Value::Int(0))).unwrap_err();
+    assert!(matches!(err, RuntimeError::DuplicateHost(name) if name == "get_blur"));
+}
+
+#[test]
+fn programs_may_not_redefine_a_host_function() {
+    for engine in ENGINES {
+        let (mut interpreter, _) = capturing(engine);
+        interpreter.register_fn("answer", 0, |_| Ok(Value::Int(42))).unwrap();
+        let err = interpreter.run(&parse("int answer() { return 1; }\nvoid blur() {}")).unwrap_err();
+        assert!(matches!(err, RuntimeError::HostConflict(name) if name == "answer"));
+    }
+}
+
+#[test]
+fn hosts_may_not_register_a_program_function() {
+    let mut interpreter = Interpreter::new();
+    for func in parse("int answer() { return 1; }").functions {
+        interpreter.define_function(func).unwrap();
+    }
+    let err = interpreter.register_fn("answer", 0, |_| Ok(Value::Int(42))).unwrap_err();
+    assert!(matches!(err, RuntimeError::HostConflict(name) if name == "answer"));
+}
+
+#[test]
+fn programs_may_not_redefine_a_builtin() {
+    for name in ["len", "push", "smooth", "assert"] {
+        let (mut interpreter, _) = capturing(Engine::Bytecode);
+        let program = format!("int {}(int x) {{ return x; }}\nvoid blur() {{}}", name);
+        let err = interpreter.run(&parse(&program)).unwrap_err();
+        assert!(matches!(err, RuntimeError::BuiltinConflict(ref n) if n == name), "{}", err);
+    }
+}
@@ -0,0 +1,31 @@
+// Reading Netpbm images
+
+use blur::image;
+use std::fs;
+
+/// Write `data` to a fresh file in the temp directory and load it
+fn load(name: &str, data: &[u8]) -> Result<image::Image, image::ImageError> {
+    let path = std::env::temp_dir().join(format!("blur-{}-{}", std::process::id(), name));
+    fs::write(&path, data).unwrap();
+    let image = image::load(path.to_str().unwrap());
+    fs::remove_file(&path).unwrap();
+    image
+}
+
+#[test]
+fn binary_raster() {
+    let image = load("raster.pgm", b"P5 2 1 255\n\x01\x02").unwrap();
+    assert_eq!((image.width, image.height, image.samples), (2, 1, vec![1, 2]));
+}
+
+#[test]
+fn bytes_after_the_raster_are_ignored() {
+    let image = load("trailing.pgm", b"P5 2 1 255\n\x01\x02\n# trailing\n").unwrap();
+    assert_eq!(image.samples, vec![1, 2]);
+}
+
+#[test]
+fn short_rasters_are_rejected() {
+    let err = load("short.pgm", b"P5 2 2 255\n\x01\x02").err().unwrap();
+    assert_eq!(err.to_string(), "not a Netpbm image: header gives 4 bytes of pixel data, the file has only 2");
+}
@@ -0,0 +1,51 @@
+// Saving and restoring interpreter state
+
+use blur::ast::StmtKind;
+use blur::interpreter::{Interpreter, Value};
+use blur::parser::Parser;
+use blur::resolver;
+use blur::snapshot::{Snapshot, SnapshotError};
+
+/// Run `input` in the global scope, the way the REPL does
+fn run_global(interpreter: &mut Interpreter, input: &str) -> Value {
+    let mut program = Parser::new(&format!("void f() {{ {} }}", input)).parse_program().unwrap();
+    let body = &mut program.functions[0].body;
+    resolver::resolve_stmts(&interpreter.scopes[0], body);
+    let mut value = Value::Void;
+    for stmt in body.iter() {
+        value = match &stmt.kind {
+            StmtKind::Expr(expr) => interpreter.eval_expr(expr).unwrap(),
+            _ => {
+                interpreter.exec_stmt(stmt).unwrap();
+                Value::Void
+            }
+        };
+    }
+    value
+}
+
+/// An interpreter whose global scope has `int x` (history 1, 3)
+fn with_global() -> Interpreter {
+    let mut interpreter = Interpreter::new();
+    run_global(&mut interpreter, "int x = 1; x = 3;");
+    interpreter
+}
+
+#[test]
+fn round_trip() {
+    let json = serde_json::to_string(&Snapshot::capture(&with_global())).unwrap();
+    let mut restored = Interpreter::new();
+    serde_json::from_str::<Snapshot>(&json).unwrap().restore(&mut restored).unwrap();
+    assert_eq!(run_global(&mut restored, "x;").to_string(), "3");
+}
+
+#[test]
+fn mismatched_slots_are_rejected() {
+    let mut snapshot = Snapshot::capture(&with_global());
+    snapshot.scopes[0].vars.names.clear();
+    let mut interpreter = Interpreter::new();
+    let err = snapshot.restore(&mut interpreter).unwrap_err();
+    assert!(matches!(err, SnapshotError::Slots { scope: 0, kind: "variable", names: 0, values: 1 }), "{}", err);
+    assert_eq!(err.to_string(), "Invalid snapshot: scope 0 has 0 variable names for 1 values");
+    assert!(interpreter.scopes[0].vars.values.is_empty());
+}